@@ -1,28 +1,49 @@
-use actix_web::{App, HttpResponse, HttpServer, Result, guard, web};
-use async_graphql::{EmptyMutation, EmptySubscription, Schema, http::GraphiQLSource};
-use async_graphql_actix_web::GraphQL;
-use starwars::{QueryRoot, StarWars};
+use actix_web::{App, HttpRequest, HttpResponse, HttpServer, Result, guard, web};
+use async_graphql::{Schema, http::GraphiQLSource};
+use async_graphql_actix_web::{GraphQL, GraphQLSubscription};
+use starwars::{MutationRoot, QueryRoot, StarWars, StarWarsSchema, SubscriptionRoot};
 
 async fn index_graphiql() -> Result<HttpResponse> {
     Ok(HttpResponse::Ok()
         .content_type("text/html; charset=utf-8")
-        .body(GraphiQLSource::build().endpoint("/").finish()))
+        .body(
+            GraphiQLSource::build()
+                .endpoint("/")
+                .subscription_endpoint("/")
+                .finish(),
+        ))
+}
+
+async fn index_ws(
+    schema: web::Data<StarWarsSchema>,
+    req: HttpRequest,
+    payload: web::Payload,
+) -> Result<HttpResponse> {
+    GraphQLSubscription::new(Schema::clone(&*schema)).start(&req, payload)
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     println!("GraphiQL IDE: http://localhost:8000");
 
-    HttpServer::new(move || {
-        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
-            .data(StarWars::new())
-            .finish();
+    // Built once so that every worker sees the same reviews.
+    let schema = Schema::build(QueryRoot, MutationRoot, SubscriptionRoot)
+        .data(StarWars::new())
+        .finish();
 
+    HttpServer::new(move || {
         App::new()
             .service(
                 web::resource("/")
                     .guard(guard::Post())
-                    .to(GraphQL::new(schema)),
+                    .to(GraphQL::new(schema.clone())),
+            )
+            .service(
+                web::resource("/")
+                    .guard(guard::Get())
+                    .guard(guard::Header("upgrade", "websocket"))
+                    .app_data(web::Data::new(schema.clone()))
+                    .to(index_ws),
             )
             .service(web::resource("/").guard(guard::Get()).to(index_graphiql))
     })
@@ -1,9 +1,9 @@
 use actix_web::{
     App, HttpRequest, HttpResponse, HttpServer, Result, guard, http::header::HeaderMap, web,
 };
-use async_graphql::{EmptyMutation, Schema, http::GraphiQLSource};
+use async_graphql::{EmptyMutation, Response, Schema, http::GraphiQLSource};
 use async_graphql_actix_web::{GraphQLRequest, GraphQLResponse, GraphQLSubscription};
-use token::{QueryRoot, SubscriptionRoot, Token, TokenSchema, on_connection_init};
+use token::{JwtConfig, QueryRoot, SharedValidator, SubscriptionRoot, TokenSchema};
 
 async fn graphiql() -> HttpResponse {
     HttpResponse::Ok()
@@ -16,43 +16,57 @@ async fn graphiql() -> HttpResponse {
         )
 }
 
-fn get_token_from_headers(headers: &HeaderMap) -> Option<Token> {
+fn get_authorization_from_headers(headers: &HeaderMap) -> Option<&str> {
     headers
-        .get("Token")
-        .and_then(|value| value.to_str().map(|s| Token(s.to_string())).ok())
+        .get("Authorization")
+        .and_then(|value| value.to_str().ok())
 }
 
 async fn index(
     schema: web::Data<TokenSchema>,
+    validator: web::Data<SharedValidator>,
     req: HttpRequest,
     gql_request: GraphQLRequest,
 ) -> GraphQLResponse {
-    let mut request = gql_request.into_inner();
-    if let Some(token) = get_token_from_headers(req.headers()) {
-        request = request.data(token);
+    let authorization = get_authorization_from_headers(req.headers());
+    match validator.authenticate(gql_request.into_inner(), authorization) {
+        Ok(request) => schema.execute(request).await.into(),
+        Err(err) => Response::from_errors(vec![err]).into(),
     }
-    schema.execute(request).await.into()
 }
 
 async fn index_ws(
     schema: web::Data<TokenSchema>,
+    validator: web::Data<SharedValidator>,
     req: HttpRequest,
     payload: web::Payload,
 ) -> Result<HttpResponse> {
+    let validator = SharedValidator::clone(&validator);
     GraphQLSubscription::new(Schema::clone(&*schema))
-        .on_connection_init(on_connection_init)
+        .on_connection_init(move |value| validator.on_connection_init(value))
         .start(&req, payload)
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     let schema = Schema::new(QueryRoot, EmptyMutation, SubscriptionRoot);
+    let validator = match SharedValidator::jwt(&JwtConfig::from_env()) {
+        Ok(validator) => validator,
+        Err(err) => {
+            eprintln!(
+                "Invalid JWT configuration: {err}. Set JWT_SECRET, JWT_PUBLIC_KEY_FILE or \
+                 JWT_JWKS_FILE, for example `JWT_SECRET=secret`."
+            );
+            std::process::exit(1);
+        }
+    };
 
     println!("GraphiQL IDE: http://localhost:8000");
 
     HttpServer::new(move || {
         App::new()
             .app_data(web::Data::new(schema.clone()))
+            .app_data(web::Data::new(validator.clone()))
             .service(web::resource("/").guard(guard::Get()).to(graphiql))
             .service(web::resource("/").guard(guard::Post()).to(index))
             .service(web::resource("/ws").to(index_ws))
@@ -1,25 +1,132 @@
-use actix_web::{App, HttpResponse, HttpServer, guard, web, web::Data};
+use std::io;
+
+use actix_web::{
+    App, HttpMessage, HttpRequest, HttpResponse, HttpServer, Result,
+    body::SizedStream,
+    dev::{Payload, Service, ServiceRequest},
+    error::{ErrorBadRequest, ErrorPayloadTooLarge},
+    guard,
+    http::{StatusCode, header},
+    web,
+    web::Data,
+};
 use async_graphql::{
-    EmptySubscription, Schema,
+    ParseRequestError, Schema,
     http::{GraphiQLSource, MultipartOptions},
 };
-use async_graphql_actix_web::{GraphQLRequest, GraphQLResponse};
-use files::{FilesSchema, MutationRoot, QueryRoot, Storage};
+use async_graphql_actix_web::{GraphQLResponse, GraphQLSubscription};
+use files::{
+    FilesSchema, MutationRoot, QueryRoot, Storage, SubscriptionRoot, UPLOAD_ID_HEADER, UploadPolicy,
+};
+use futures::{SinkExt, StreamExt, TryStreamExt, channel::mpsc};
+
+/// Executes the request with its files streamed to the resolvers as they are
+/// received, the multipart options of the upload policy reject the files that
+/// are too large.
+async fn index(
+    schema: web::Data<FilesSchema>,
+    req: HttpRequest,
+    mut payload: web::Payload,
+) -> Result<GraphQLResponse> {
+    let content_type = req
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok());
+    let options = req
+        .app_data::<MultipartOptions>()
+        .copied()
+        .unwrap_or_default();
+
+    // The payload isn't `Send`, it is received by another task.
+    let (mut sender, body) = mpsc::channel(16);
+    actix_web::rt::spawn(async move {
+        while let Some(chunk) = payload.next().await {
+            if sender.send(chunk.map_err(io::Error::other)).await.is_err() {
+                return;
+            }
+        }
+    });
+    match files::execute(&**schema, content_type, body.into_async_read(), options).await {
+        Ok(response) => Ok(response.into()),
+        Err(err @ ParseRequestError::PayloadTooLarge) => Err(ErrorPayloadTooLarge(err)),
+        Err(err) => Err(ErrorBadRequest(err)),
+    }
+}
+
+/// Sends the progress of the uploads that have an id as their body is
+/// received.
+fn track_upload(req: &mut ServiceRequest) {
+    let Some(upload_id) = req
+        .headers()
+        .get(UPLOAD_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(ToString::to_string)
+    else {
+        return;
+    };
+    let content_length = req
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok()?.parse().ok());
+    let storage = req.app_data::<Data<Storage>>().unwrap().clone();
+    let payload = req.take_payload();
+    req.set_payload(Payload::Stream {
+        payload: Box::pin(storage.track_upload(&upload_id, content_length, payload)),
+    });
+}
 
-async fn index(schema: web::Data<FilesSchema>, req: GraphQLRequest) -> GraphQLResponse {
-    schema.execute(req.into_inner()).await.into()
+async fn index_ws(
+    schema: web::Data<FilesSchema>,
+    req: HttpRequest,
+    payload: web::Payload,
+) -> Result<HttpResponse> {
+    GraphQLSubscription::new(Schema::clone(&*schema)).start(&req, payload)
 }
 
 async fn gql_playgound() -> HttpResponse {
     HttpResponse::Ok()
         .content_type("text/html; charset=utf-8")
-        .body(GraphiQLSource::build().endpoint("/").finish())
+        .body(
+            GraphiQLSource::build()
+                .endpoint("/")
+                .subscription_endpoint("/")
+                .finish(),
+        )
+}
+
+async fn download(
+    storage: web::Data<Storage>,
+    id: web::Path<String>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    let range = req
+        .headers()
+        .get(header::RANGE)
+        .and_then(|value| value.to_str().ok());
+    let Some(download) = storage.download(&id, range).await? else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+
+    let mut response = HttpResponse::build(StatusCode::from_u16(download.status).unwrap());
+    response
+        .content_type(download.content_type.as_str())
+        .insert_header((header::ACCEPT_RANGES, "bytes"));
+    if let Some(content_range) = &download.content_range {
+        response.insert_header((header::CONTENT_RANGE, content_range.as_str()));
+    }
+    let length = download.content_length;
+    Ok(response.body(SizedStream::new(length, Box::pin(download.into_stream()))))
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    let schema = Schema::build(QueryRoot, MutationRoot, EmptySubscription)
-        .data(Storage::default())
+    let storage = Storage::local("uploads")?.with_base_url("http://localhost:8000");
+    let policy = UploadPolicy::default()
+        .max_file_size(1024 * 1024 * 1024)
+        .max_total_size(4 * 1024 * 1024 * 1024);
+    let schema = Schema::build(QueryRoot, MutationRoot, SubscriptionRoot)
+        .data(storage.clone())
+        .data(policy.clone())
         .finish();
 
     println!("GraphiQL IDE: http://localhost:8000");
@@ -27,13 +134,25 @@ async fn main() -> std::io::Result<()> {
     HttpServer::new(move || {
         App::new()
             .app_data(Data::new(schema.clone()))
+            .app_data(Data::new(storage.clone()))
             .service(
                 web::resource("/")
                     .guard(guard::Post())
                     .to(index)
-                    .app_data(MultipartOptions::default().max_num_files(3)),
+                    .app_data(policy.multipart_options().max_num_files(3))
+                    .wrap_fn(|mut req, srv| {
+                        track_upload(&mut req);
+                        srv.call(req)
+                    }),
+            )
+            .service(
+                web::resource("/")
+                    .guard(guard::Get())
+                    .guard(guard::Header("upgrade", "websocket"))
+                    .to(index_ws),
             )
             .service(web::resource("/").guard(guard::Get()).to(gql_playgound))
+            .service(web::resource("/files/{id}").route(web::get().to(download)))
     })
     .bind("127.0.0.1:8000")?
     .run()
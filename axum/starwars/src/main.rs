@@ -1,24 +1,60 @@
-use async_graphql::{EmptyMutation, EmptySubscription, Schema, http::GraphiQLSource};
-use async_graphql_axum::GraphQL;
+use std::convert::Infallible;
+
+use async_graphql::{Schema, http::GraphiQLSource};
+use async_graphql_axum::{GraphQLRequest, GraphQLResponse, GraphQLSubscription};
 use axum::{
     Router,
-    response::{self, IntoResponse},
+    body::Body,
+    extract::State,
+    http::{HeaderMap, header},
+    response::{self, IntoResponse, Response},
     routing::get,
 };
-use starwars::{QueryRoot, StarWars};
+use futures_util::StreamExt;
+use incremental_delivery::{CONTENT_TYPE, Delivery, accepts_multipart, multipart};
+use starwars::{MutationRoot, QueryRoot, StarWars, StarWarsSchema, SubscriptionRoot};
 use tokio::net::TcpListener;
 
 async fn graphiql() -> impl IntoResponse {
-    response::Html(GraphiQLSource::build().endpoint("/").finish())
+    response::Html(
+        GraphiQLSource::build()
+            .endpoint("/")
+            .subscription_endpoint("/ws")
+            .finish(),
+    )
+}
+
+/// Answers with the `@defer` and `@stream` results as they come when the
+/// client accepts `multipart/mixed` responses.
+async fn graphql_handler(
+    State(schema): State<StarWarsSchema>,
+    headers: HeaderMap,
+    req: GraphQLRequest,
+) -> Response {
+    let accept = headers
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok());
+    match incremental_delivery::execute(&schema, req.into_inner(), accepts_multipart(accept)).await
+    {
+        Delivery::Single(response) => GraphQLResponse::from(*response).into_response(),
+        Delivery::Incremental(payloads) => (
+            [(header::CONTENT_TYPE, CONTENT_TYPE)],
+            Body::from_stream(multipart(payloads).map(Ok::<_, Infallible>)),
+        )
+            .into_response(),
+    }
 }
 
 #[tokio::main]
 async fn main() {
-    let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+    let schema = Schema::build(QueryRoot, MutationRoot, SubscriptionRoot)
         .data(StarWars::new())
         .finish();
 
-    let app = Router::new().route("/", get(graphiql).post_service(GraphQL::new(schema)));
+    let app = Router::new()
+        .route("/", get(graphiql).post(graphql_handler))
+        .route_service("/ws", GraphQLSubscription::new(schema.clone()))
+        .with_state(schema);
 
     println!("GraphiQL IDE: http://localhost:8000");
 
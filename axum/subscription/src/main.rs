@@ -1,3 +1,5 @@
+mod sse;
+
 use async_graphql::{Schema, http::GraphiQLSource};
 use async_graphql_axum::{GraphQL, GraphQLSubscription};
 use axum::{
@@ -28,7 +30,8 @@ async fn main() {
             "/",
             get(graphiql).post_service(GraphQL::new(schema.clone())),
         )
-        .route_service("/ws", GraphQLSubscription::new(schema));
+        .route_service("/ws", GraphQLSubscription::new(schema.clone()))
+        .route("/sse", sse::route(schema));
 
     println!("GraphiQL IDE: http://localhost:8000");
 
@@ -0,0 +1,102 @@
+//! The GraphQL over SSE route, the protocol is implemented by the
+//! `sse-transport` crate.
+
+use std::convert::Infallible;
+
+use async_graphql::http::parse_query_string;
+use async_graphql_axum::GraphQLRequest;
+use axum::{
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode, Uri},
+    response::{
+        IntoResponse, Response,
+        sse::{Event, KeepAlive, Sse},
+    },
+    routing::{MethodRouter, get},
+};
+use books::BooksSchema;
+use futures_util::{Stream, StreamExt};
+use sse_transport::{KEEP_ALIVE_INTERVAL, Params, Streams, TOKEN_HEADER, distinct};
+
+/// The route of both modes, it only serves `schema`.
+pub fn route(schema: BooksSchema) -> MethodRouter {
+    get(listen)
+        .post(execute)
+        .put(reserve)
+        .delete(cancel)
+        .with_state(SseState {
+            schema,
+            streams: Streams::default(),
+        })
+}
+
+#[derive(Clone)]
+struct SseState {
+    schema: BooksSchema,
+    streams: Streams,
+}
+
+fn token(headers: &HeaderMap, params: &Params) -> Option<String> {
+    params.token(
+        headers
+            .get(TOKEN_HEADER)
+            .and_then(|value| value.to_str().ok()),
+    )
+}
+
+fn sse(events: impl Stream<Item = sse_transport::Event> + Send + 'static) -> Response {
+    let events = events
+        .map(|event| Ok::<_, Infallible>(Event::default().event(event.event).data(event.data)));
+    Sse::new(events)
+        .keep_alive(KeepAlive::new().interval(KEEP_ALIVE_INTERVAL))
+        .into_response()
+}
+
+async fn reserve(State(state): State<SseState>) -> impl IntoResponse {
+    (StatusCode::CREATED, state.streams.reserve())
+}
+
+async fn listen(
+    State(state): State<SseState>,
+    headers: HeaderMap,
+    uri: Uri,
+    Query(params): Query<Params>,
+) -> Response {
+    match token(&headers, &params) {
+        Some(token) => match state.streams.listen(&token) {
+            Ok(events) => sse(events),
+            Err(status) => status.into_response(),
+        },
+        None => match parse_query_string(uri.query().unwrap_or_default()) {
+            Ok(request) => sse(distinct(&state.schema, request)),
+            Err(err) => (StatusCode::BAD_REQUEST, err.to_string()).into_response(),
+        },
+    }
+}
+
+async fn execute(
+    State(state): State<SseState>,
+    headers: HeaderMap,
+    Query(params): Query<Params>,
+    request: GraphQLRequest,
+) -> Response {
+    let request = request.into_inner();
+    match token(&headers, &params) {
+        Some(token) => match state.streams.execute(&token, &state.schema, request) {
+            Ok(()) => StatusCode::ACCEPTED.into_response(),
+            Err(status) => status.into_response(),
+        },
+        None => sse(distinct(&state.schema, request)),
+    }
+}
+
+async fn cancel(
+    State(state): State<SseState>,
+    headers: HeaderMap,
+    Query(params): Query<Params>,
+) -> StatusCode {
+    match (token(&headers, &params), params.operation_id()) {
+        (Some(token), Some(id)) => state.streams.cancel(&token, id),
+        _ => StatusCode::BAD_REQUEST,
+    }
+}
@@ -10,7 +10,7 @@ use axum::{
     response::{Html, IntoResponse, Response},
     routing::get,
 };
-use token::{QueryRoot, SubscriptionRoot, Token, TokenSchema, on_connection_init};
+use token::{JwtConfig, QueryRoot, SharedValidator, SubscriptionRoot, TokenSchema};
 use tokio::net::TcpListener;
 
 async fn graphql_playground() -> impl IntoResponse {
@@ -22,34 +22,42 @@ async fn graphql_playground() -> impl IntoResponse {
     )
 }
 
-fn get_token_from_headers(headers: &HeaderMap) -> Option<Token> {
+fn get_authorization_from_headers(headers: &HeaderMap) -> Option<&str> {
     headers
-        .get("Token")
-        .and_then(|value| value.to_str().map(|s| Token(s.to_string())).ok())
+        .get("Authorization")
+        .and_then(|value| value.to_str().ok())
+}
+
+#[derive(Clone)]
+struct AppState {
+    schema: TokenSchema,
+    validator: SharedValidator,
 }
 
 async fn graphql_handler(
-    State(schema): State<TokenSchema>,
+    State(state): State<AppState>,
     headers: HeaderMap,
     req: GraphQLRequest,
 ) -> GraphQLResponse {
-    let mut req = req.into_inner();
-    if let Some(token) = get_token_from_headers(&headers) {
-        req = req.data(token);
+    match state
+        .validator
+        .authenticate(req.into_inner(), get_authorization_from_headers(&headers))
+    {
+        Ok(req) => state.schema.execute(req).await.into(),
+        Err(err) => async_graphql::Response::from_errors(vec![err]).into(),
     }
-    schema.execute(req).await.into()
 }
 
 async fn graphql_ws_handler(
-    State(schema): State<TokenSchema>,
+    State(state): State<AppState>,
     protocol: GraphQLProtocol,
     websocket: WebSocketUpgrade,
 ) -> Response {
     websocket
         .protocols(ALL_WEBSOCKET_PROTOCOLS)
         .on_upgrade(move |stream| {
-            GraphQLWebSocket::new(stream, schema.clone(), protocol)
-                .on_connection_init(on_connection_init)
+            GraphQLWebSocket::new(stream, state.schema.clone(), protocol)
+                .on_connection_init(move |value| state.validator.on_connection_init(value))
                 .serve()
         })
 }
@@ -57,11 +65,21 @@ async fn graphql_ws_handler(
 #[tokio::main]
 async fn main() {
     let schema = Schema::new(QueryRoot, EmptyMutation, SubscriptionRoot);
+    let validator = match SharedValidator::jwt(&JwtConfig::from_env()) {
+        Ok(validator) => validator,
+        Err(err) => {
+            eprintln!(
+                "Invalid JWT configuration: {err}. Set JWT_SECRET, JWT_PUBLIC_KEY_FILE or \
+                 JWT_JWKS_FILE, for example `JWT_SECRET=secret`."
+            );
+            std::process::exit(1);
+        }
+    };
 
     let app = Router::new()
         .route("/", get(graphql_playground).post(graphql_handler))
         .route("/ws", get(graphql_ws_handler))
-        .with_state(schema);
+        .with_state(AppState { schema, validator });
 
     println!("Playground: http://localhost:8000");
 
@@ -1,33 +1,145 @@
-use async_graphql::{EmptySubscription, Schema, http::GraphiQLSource};
-use async_graphql_axum::GraphQL;
+use std::io;
+
+use async_graphql::{
+    ParseRequestError, Schema,
+    http::{GraphiQLSource, MultipartOptions},
+};
+use async_graphql_axum::{GraphQLResponse, GraphQLSubscription};
 use axum::{
     Router,
-    http::Method,
-    response::{Html, IntoResponse},
+    body::Body,
+    extract::{Path, Request, State},
+    http::{HeaderMap, HeaderName, Method, StatusCode, header},
+    middleware::{self, Next},
+    response::{Html, IntoResponse, Response},
     routing::get,
 };
-use files::{MutationRoot, QueryRoot, Storage};
+use files::{
+    Download, FilesSchema, MutationRoot, QueryRoot, Storage, SubscriptionRoot, UPLOAD_ID_HEADER,
+    UploadPolicy,
+};
+use futures_util::TryStreamExt;
 use tokio::net::TcpListener;
 use tower_http::cors::{AllowOrigin, CorsLayer};
 
 async fn graphiql() -> impl IntoResponse {
-    Html(GraphiQLSource::build().endpoint("/").finish())
+    Html(
+        GraphiQLSource::build()
+            .endpoint("/")
+            .subscription_endpoint("/ws")
+            .finish(),
+    )
+}
+
+/// Executes the request with its files streamed to the resolvers as they are
+/// received, the multipart options of the upload policy reject the files that
+/// are too large.
+async fn graphql_handler(
+    State((schema, options)): State<(FilesSchema, MultipartOptions)>,
+    request: Request,
+) -> Response {
+    let content_type = request
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(ToString::to_string);
+    let body = request
+        .into_body()
+        .into_data_stream()
+        .map_err(io::Error::other)
+        .into_async_read();
+    match files::execute(&schema, content_type.as_deref(), body, options).await {
+        Ok(response) => GraphQLResponse::from(response).into_response(),
+        Err(err @ ParseRequestError::PayloadTooLarge) => {
+            (StatusCode::PAYLOAD_TOO_LARGE, err.to_string()).into_response()
+        }
+        Err(err) => (StatusCode::BAD_REQUEST, err.to_string()).into_response(),
+    }
+}
+
+async fn download(
+    State(storage): State<Storage>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    let range = headers
+        .get(header::RANGE)
+        .and_then(|value| value.to_str().ok());
+    match storage.download(&id, range).await {
+        Ok(Some(download)) => file_response(download),
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+/// Sends the progress of the uploads that have an id as their body is
+/// received.
+async fn track_upload(State(storage): State<Storage>, request: Request, next: Next) -> Response {
+    let Some(upload_id) = request
+        .headers()
+        .get(UPLOAD_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(ToString::to_string)
+    else {
+        return next.run(request).await;
+    };
+    let content_length = request
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok()?.parse().ok());
+    let request = request.map(|body| {
+        Body::from_stream(storage.track_upload(&upload_id, content_length, body.into_data_stream()))
+    });
+    next.run(request).await
+}
+
+fn file_response(download: Download) -> Response {
+    let mut builder = Response::builder()
+        .status(download.status)
+        .header(header::CONTENT_TYPE, &download.content_type)
+        .header(header::CONTENT_LENGTH, download.content_length)
+        .header(header::ACCEPT_RANGES, "bytes");
+    if let Some(content_range) = &download.content_range {
+        builder = builder.header(header::CONTENT_RANGE, content_range);
+    }
+    builder
+        .body(Body::from_stream(download.into_stream()))
+        .unwrap()
 }
 
 #[tokio::main]
 async fn main() {
-    let schema = Schema::build(QueryRoot, MutationRoot, EmptySubscription)
-        .data(Storage::default())
+    let storage = Storage::local("uploads")
+        .unwrap()
+        .with_base_url("http://localhost:8000");
+    let policy = UploadPolicy::default()
+        .max_file_size(1024 * 1024 * 1024)
+        .max_total_size(4 * 1024 * 1024 * 1024);
+    let schema = Schema::build(QueryRoot, MutationRoot, SubscriptionRoot)
+        .data(storage.clone())
+        .data(policy.clone())
         .finish();
 
     println!("GraphiQL IDE: http://localhost:8000");
 
     let app = Router::new()
-        .route("/", get(graphiql).post_service(GraphQL::new(schema)))
+        .route(
+            "/",
+            get(graphiql)
+                .post(graphql_handler)
+                .with_state((schema.clone(), policy.multipart_options()))
+                .layer(middleware::from_fn_with_state(
+                    storage.clone(),
+                    track_upload,
+                )),
+        )
+        .route_service("/ws", GraphQLSubscription::new(schema))
+        .route("/files/{id}", get(download).with_state(storage))
         .layer(
             CorsLayer::new()
                 .allow_origin(AllowOrigin::predicate(|_, _| true))
-                .allow_methods([Method::GET, Method::POST]),
+                .allow_methods([Method::GET, Method::POST])
+                .allow_headers([HeaderName::from_static(UPLOAD_ID_HEADER)]),
         );
 
     axum::serve(TcpListener::bind("127.0.0.1:8000").await.unwrap(), app)
@@ -1,8 +1,8 @@
-use async_graphql::{http::GraphiQLSource, EmptyMutation, EmptySubscription, Schema};
+use async_graphql::{http::GraphiQLSource, Schema};
 use async_graphql_axum::GraphQL;
 use axum::debug_handler;
 use loco_rs::prelude::*;
-use starwars::{QueryRoot, StarWars};
+use starwars::{MutationRoot, QueryRoot, StarWars, SubscriptionRoot};
 
 #[debug_handler]
 async fn graphiql() -> Result<Response> {
@@ -10,7 +10,7 @@ async fn graphiql() -> Result<Response> {
 }
 
 pub fn routes() -> Routes {
-    let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+    let schema = Schema::build(QueryRoot, MutationRoot, SubscriptionRoot)
         .data(StarWars::new())
         .finish();
 
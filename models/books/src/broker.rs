@@ -0,0 +1,296 @@
+use std::{collections::HashSet, sync::Arc, time::Duration};
+
+use async_graphql::Result;
+use futures_util::{
+    FutureExt, Stream, StreamExt,
+    future::{self, BoxFuture},
+    lock::Mutex,
+    stream::{self, BoxStream},
+};
+use once_cell::sync::Lazy;
+use redis::{Client, RedisResult, Script, aio::ConnectionManager};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+
+use crate::simple_broker::{BrokerConfig, BrokerEvent, MemoryBroker, resume_from};
+
+static DEFAULT_BROKER: Lazy<SharedBroker> =
+    Lazy::new(|| SharedBroker::new(MemoryBroker::global().clone()));
+
+/// Assigns the next sequence number of a topic, appends the message to the
+/// log of the topic and publishes it, all at once so that subscribers never
+/// see the sequence numbers out of order. Both keys expire once the topic
+/// wasn't published to for the TTL.
+static PUBLISH_SCRIPT: Lazy<Script> = Lazy::new(|| {
+    Script::new(
+        r"
+        local seq = redis.call('INCR', KEYS[1])
+        local msg = cjson.encode({seq = seq, payload = ARGV[2]})
+        local log_capacity = tonumber(ARGV[3])
+        local ttl = tonumber(ARGV[4])
+        redis.call('EXPIRE', KEYS[1], ttl)
+        if log_capacity > 0 then
+            redis.call('LPUSH', KEYS[2], msg)
+            redis.call('LTRIM', KEYS[2], 0, log_capacity - 1)
+            redis.call('EXPIRE', KEYS[2], ttl)
+        end
+        redis.call('PUBLISH', ARGV[1], msg)
+        return seq
+        ",
+    )
+});
+
+/// A message broker that delivers JSON payloads to the subscribers of a topic.
+pub trait Broker: Send + Sync {
+    /// Publish a payload to the subscribers of `topic`, returns its sequence
+    /// number in the topic.
+    fn publish(&self, topic: &str, payload: String) -> BoxFuture<'static, Result<u64>>;
+
+    /// Subscribe to the payloads published to `topic`, starting with the
+    /// logged payloads published after the sequence number `since`.
+    fn subscribe(
+        &self,
+        topic: &str,
+        since: Option<u64>,
+    ) -> BoxFuture<'static, Result<BoxStream<'static, BrokerEvent<String>>>>;
+}
+
+impl Broker for MemoryBroker {
+    fn publish(&self, topic: &str, payload: String) -> BoxFuture<'static, Result<u64>> {
+        future::ready(Ok(self.publish_to(topic, payload))).boxed()
+    }
+
+    fn subscribe(
+        &self,
+        topic: &str,
+        since: Option<u64>,
+    ) -> BoxFuture<'static, Result<BoxStream<'static, BrokerEvent<String>>>> {
+        future::ready(Ok(self.subscribe_to(topic, since).boxed())).boxed()
+    }
+}
+
+/// A message as it is published to a Redis channel and kept in the log.
+#[derive(Deserialize)]
+struct Envelope {
+    seq: u64,
+    payload: String,
+}
+
+/// A broker based on Redis pub/sub, every topic is a Redis channel.
+///
+/// All processes connected to the same Redis server share the messages. The
+/// sequence number and the log of a topic are kept in the `<topic>:seq` and
+/// `<topic>:log` keys, which expire when nothing was published to the topic
+/// for the TTL of the topics.
+///
+/// Clones share the connection that the messages are published with, it is
+/// opened on the first use and reconnected when it is lost. Every
+/// subscription has its own pub/sub connection.
+#[derive(Clone)]
+pub struct RedisBroker {
+    client: Client,
+    conn: Arc<Mutex<Option<ConnectionManager>>>,
+    log_capacity: usize,
+    topic_ttl: Duration,
+}
+
+impl RedisBroker {
+    /// Create a broker that uses the Redis server of `client`.
+    pub fn new(client: Client) -> Self {
+        Self {
+            client,
+            conn: Default::default(),
+            log_capacity: BrokerConfig::default().log_capacity,
+            topic_ttl: BrokerConfig::default().topic_ttl,
+        }
+    }
+
+    /// Set how many messages of each topic are kept for replay.
+    pub fn with_log_capacity(self, log_capacity: usize) -> Self {
+        Self {
+            log_capacity,
+            ..self
+        }
+    }
+
+    /// Set how long the keys of a topic are kept after its last message, it
+    /// is rounded down to seconds but is at least one second.
+    pub fn with_topic_ttl(self, topic_ttl: Duration) -> Self {
+        Self { topic_ttl, ..self }
+    }
+
+    /// The shared connection, opened by the first caller.
+    async fn connection(&self) -> RedisResult<ConnectionManager> {
+        let mut conn = self.conn.lock().await;
+        if let Some(conn) = &*conn {
+            return Ok(conn.clone());
+        }
+        let manager = ConnectionManager::new(self.client.clone()).await?;
+        *conn = Some(manager.clone());
+        Ok(manager)
+    }
+}
+
+impl Broker for RedisBroker {
+    fn publish(&self, topic: &str, payload: String) -> BoxFuture<'static, Result<u64>> {
+        let broker = self.clone();
+        let log_capacity = self.log_capacity;
+        let ttl = self.topic_ttl.as_secs().max(1);
+        let topic = topic.to_string();
+        async move {
+            let mut conn = broker.connection().await?;
+            let seq = PUBLISH_SCRIPT
+                .key(format!("{topic}:seq"))
+                .key(format!("{topic}:log"))
+                .arg(&topic)
+                .arg(payload)
+                .arg(log_capacity)
+                .arg(ttl)
+                .invoke_async(&mut conn)
+                .await?;
+            Ok(seq)
+        }
+        .boxed()
+    }
+
+    fn subscribe(
+        &self,
+        topic: &str,
+        since: Option<u64>,
+    ) -> BoxFuture<'static, Result<BoxStream<'static, BrokerEvent<String>>>> {
+        let broker = self.clone();
+        let topic = topic.to_string();
+        async move {
+            // Subscribe before reading the log, so that nothing published in
+            // between is missed, and skip what was already replayed.
+            let mut pubsub = broker.client.get_async_pubsub().await?;
+            pubsub.subscribe(&topic).await?;
+
+            let mut replay = Vec::new();
+            if let Some(since) = since {
+                let mut conn = broker.connection().await?;
+                let (log, seq): (Vec<String>, Option<u64>) = redis::pipe()
+                    .atomic()
+                    .lrange(format!("{topic}:log"), 0, -1)
+                    .get(format!("{topic}:seq"))
+                    .query_async(&mut conn)
+                    .await?;
+                let entries = log
+                    .iter()
+                    .rev()
+                    .filter_map(|msg| serde_json::from_str::<Envelope>(msg).ok())
+                    .collect::<Vec<_>>();
+                let (since, missing) = resume_from(
+                    since,
+                    entries.first().map(|entry| entry.seq),
+                    seq.unwrap_or_default() + 1,
+                );
+                if missing > 0 {
+                    replay.push(BrokerEvent::Lagged(missing));
+                }
+                replay.extend(
+                    entries
+                        .into_iter()
+                        .filter(|entry| entry.seq > since)
+                        .map(|entry| BrokerEvent::Message(entry.seq, entry.payload)),
+                );
+            }
+
+            // The messages published between the subscription and the read of
+            // the log are received again, they are the first ones and are
+            // skipped until a message that wasn't replayed. Comparing the
+            // payloads too keeps the messages of a topic whose keys expired
+            // in the meantime, whose sequence numbers started over.
+            let mut replayed = replay
+                .iter()
+                .filter_map(|event| match event {
+                    BrokerEvent::Message(seq, payload) => Some((*seq, payload.clone())),
+                    BrokerEvent::Lagged(_) => None,
+                })
+                .collect::<HashSet<_>>();
+            let live = pubsub.into_on_message().filter_map(move |msg| {
+                let entry = msg
+                    .get_payload::<String>()
+                    .ok()
+                    .and_then(|payload| serde_json::from_str::<Envelope>(&payload).ok())
+                    .filter(|entry| {
+                        if !replayed.is_empty()
+                            && replayed.remove(&(entry.seq, entry.payload.clone()))
+                        {
+                            return false;
+                        }
+                        replayed.clear();
+                        true
+                    });
+                async move { entry.map(|entry| BrokerEvent::Message(entry.seq, entry.payload)) }
+            });
+            Ok(stream::iter(replay).chain(live).boxed())
+        }
+        .boxed()
+    }
+}
+
+/// The broker used by the books schema.
+///
+/// Put it into the schema data when building the schema, the default is the
+/// in-memory broker shared by the whole process:
+///
+/// ```ignore
+/// let schema = Schema::build(QueryRoot, MutationRoot, SubscriptionRoot)
+///     .data(SharedBroker::redis(Client::open("redis://127.0.0.1/")?))
+///     .finish();
+/// ```
+#[derive(Clone)]
+pub struct SharedBroker(Arc<dyn Broker>);
+
+impl SharedBroker {
+    /// Create a shared broker from a broker.
+    pub fn new(broker: impl Broker + 'static) -> Self {
+        Self(Arc::new(broker))
+    }
+
+    /// Create a shared broker with its own in-memory subscribers.
+    pub fn memory(config: BrokerConfig) -> Self {
+        Self::new(MemoryBroker::new(config))
+    }
+
+    /// Create a shared broker that publishes to the Redis server of `client`.
+    pub fn redis(client: Client) -> Self {
+        Self::new(RedisBroker::new(client))
+    }
+
+    /// The in-memory broker shared by the whole process.
+    pub fn global() -> &'static SharedBroker {
+        &DEFAULT_BROKER
+    }
+
+    /// Serialize `msg` and publish it to the subscribers of `topic`, returns
+    /// its sequence number in the topic.
+    pub async fn publish<T: Serialize>(&self, topic: &str, msg: &T) -> Result<u64> {
+        self.0.publish(topic, serde_json::to_string(msg)?).await
+    }
+
+    /// Subscribe to the messages published to `topic` after the sequence
+    /// number `since` and returns a `Stream` of the deserialized messages.
+    pub async fn subscribe<T: DeserializeOwned>(
+        &self,
+        topic: &str,
+        since: Option<u64>,
+    ) -> Result<impl Stream<Item = Result<BrokerEvent<T>>> + use<T>> {
+        Ok(self
+            .0
+            .subscribe(topic, since)
+            .await?
+            .map(|event| match event {
+                BrokerEvent::Message(seq, payload) => {
+                    Ok(BrokerEvent::Message(seq, serde_json::from_str(&payload)?))
+                }
+                BrokerEvent::Lagged(count) => Ok(BrokerEvent::Lagged(count)),
+            }))
+    }
+}
+
+impl Default for SharedBroker {
+    fn default() -> Self {
+        Self::global().clone()
+    }
+}
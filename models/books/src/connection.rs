@@ -0,0 +1,135 @@
+//! Filtering, ordering and cursor pagination of books, shared by the
+//! `booksConnection` of the static and dynamic schemas.
+
+use std::{cmp::Ordering, str::FromStr};
+
+use async_graphql::{Enum, InputObject, Result, resolver_utils::EnumType};
+use serde::{Deserialize, Serialize};
+
+/// Conditions that the returned books must match.
+#[derive(InputObject, Default)]
+pub struct BookFilter {
+    /// Only books whose author contains this text, ignoring case.
+    pub author_contains: Option<String>,
+
+    /// Only books whose name contains this text, ignoring case.
+    pub name_contains: Option<String>,
+}
+
+impl BookFilter {
+    /// Whether a book with this name and author matches the conditions.
+    pub fn matches(&self, name: &str, author: &str) -> bool {
+        fn contains(value: &str, pattern: &Option<String>) -> bool {
+            pattern
+                .as_ref()
+                .is_none_or(|pattern| value.to_lowercase().contains(&pattern.to_lowercase()))
+        }
+
+        contains(author, &self.author_contains) && contains(name, &self.name_contains)
+    }
+}
+
+/// The order of the returned books.
+#[derive(Enum, Eq, PartialEq, Copy, Clone, Default, Debug)]
+pub enum BookOrder {
+    #[default]
+    IdAsc,
+    IdDesc,
+    NameAsc,
+    NameDesc,
+    AuthorAsc,
+    AuthorDesc,
+}
+
+impl FromStr for BookOrder {
+    type Err = String;
+
+    /// Parses the GraphQL name of an order, such as `NAME_DESC`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        BookOrder::items()
+            .iter()
+            .find(|item| item.name == s)
+            .map(|item| item.value)
+            .ok_or_else(|| format!("Invalid BookOrder: {}", s))
+    }
+}
+
+/// The position of a book in the order, it only depends on the book itself so
+/// it stays valid when other books are deleted.
+#[derive(Serialize, Deserialize, Eq, PartialEq, Ord, PartialOrd, Debug)]
+pub struct BookCursor {
+    key: String,
+    id: usize,
+}
+
+impl BookOrder {
+    /// The cursor of the book with this id, name and author.
+    pub fn cursor(self, id: &str, name: &str, author: &str) -> Result<BookCursor> {
+        let key = match self {
+            BookOrder::IdAsc | BookOrder::IdDesc => "",
+            BookOrder::NameAsc | BookOrder::NameDesc => name,
+            BookOrder::AuthorAsc | BookOrder::AuthorDesc => author,
+        };
+        Ok(BookCursor {
+            key: key.to_string(),
+            id: id.parse()?,
+        })
+    }
+
+    fn compare(self, a: &BookCursor, b: &BookCursor) -> Ordering {
+        match self {
+            BookOrder::IdAsc | BookOrder::NameAsc | BookOrder::AuthorAsc => a.cmp(b),
+            BookOrder::IdDesc | BookOrder::NameDesc | BookOrder::AuthorDesc => b.cmp(a),
+        }
+    }
+}
+
+/// A page of books and their cursors.
+pub struct BookPage<T> {
+    pub books: Vec<(BookCursor, T)>,
+    pub has_previous_page: bool,
+    pub has_next_page: bool,
+}
+
+/// Sorts `books` by `order_by` and selects the page after `after` and before
+/// `before`, with the `first` books of it or else the `last` ones.
+///
+/// The cursors don't need to be those of `books`, a cursor of a deleted book
+/// selects the books around the place it had.
+pub fn page_books<T>(
+    mut books: Vec<(BookCursor, T)>,
+    order_by: BookOrder,
+    after: Option<BookCursor>,
+    before: Option<BookCursor>,
+    first: Option<usize>,
+    last: Option<usize>,
+) -> BookPage<T> {
+    books.sort_by(|(a, _), (b, _)| order_by.compare(a, b));
+
+    let mut start = 0usize;
+    let mut end = books.len();
+
+    if let Some(after) = after {
+        start = books.partition_point(|(cursor, _)| order_by.compare(cursor, &after).is_le());
+    }
+
+    if let Some(before) = before {
+        end = books
+            .partition_point(|(cursor, _)| order_by.compare(cursor, &before).is_lt())
+            .max(start);
+    }
+
+    if let Some(first) = first {
+        end = end.min(start + first);
+    } else if let Some(last) = last {
+        start = start.max(end.saturating_sub(last));
+    }
+
+    let has_previous_page = start > 0;
+    let has_next_page = end < books.len();
+    BookPage {
+        books: books.drain(start..end).collect(),
+        has_previous_page,
+        has_next_page,
+    }
+}
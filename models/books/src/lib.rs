@@ -1,24 +1,37 @@
+mod broker;
+mod connection;
 mod simple_broker;
+mod storage;
 
-use std::{sync::Arc, time::Duration};
+use std::time::Duration;
 
-use async_graphql::{Context, Enum, ID, Object, Result, Schema, Subscription};
-use futures_util::{Stream, StreamExt, lock::Mutex};
-use simple_broker::SimpleBroker;
-use slab::Slab;
+use async_graphql::{
+    Context, Enum, Error, ErrorExtensions, ID, Object, Result, Schema, Subscription,
+    connection::{Connection, Edge, OpaqueCursor, query},
+};
+pub use broker::{Broker, RedisBroker, SharedBroker};
+pub use connection::{BookCursor, BookFilter, BookOrder, BookPage, page_books};
+use futures_util::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+pub use simple_broker::{BrokerConfig, BrokerEvent, MemoryBroker, OverflowPolicy, SimpleBroker};
+pub use storage::{BookRepository, MemoryStorage, SqliteStorage, Storage};
 
 pub type BooksSchema = Schema<QueryRoot, MutationRoot, SubscriptionRoot>;
 
-#[derive(Clone)]
+/// The topic that every book mutation is published to.
+const BOOKS_TOPIC: &str = "books";
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Book {
     id: ID,
     name: String,
     author: String,
 }
 
+/// A book that will be stored.
 #[Object]
 impl Book {
-    async fn id(&self) -> &str {
+    async fn id(&self) -> &ID {
         &self.id
     }
 
@@ -31,15 +44,27 @@ impl Book {
     }
 }
 
-pub type Storage = Arc<Mutex<Slab<Book>>>;
-
 pub struct QueryRoot;
 
 #[Object]
 impl QueryRoot {
-    async fn books(&self, ctx: &Context<'_>) -> Vec<Book> {
-        let books = ctx.data_unchecked::<Storage>().lock().await;
-        books.iter().map(|(_, book)| book).cloned().collect()
+    async fn books(&self, ctx: &Context<'_>) -> Result<Vec<Book>> {
+        ctx.data_unchecked::<Storage>().books().await
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn books_connection(
+        &self,
+        ctx: &Context<'_>,
+        after: Option<String>,
+        before: Option<String>,
+        first: Option<i32>,
+        last: Option<i32>,
+        #[graphql(default)] filter: BookFilter,
+        #[graphql(default)] order_by: BookOrder,
+    ) -> Result<Connection<OpaqueCursor<BookCursor>, Book>> {
+        let books = ctx.data_unchecked::<Storage>().books().await?;
+        query_books(after, before, first, last, books, filter, order_by).await
     }
 }
 
@@ -47,32 +72,70 @@ pub struct MutationRoot;
 
 #[Object]
 impl MutationRoot {
-    async fn create_book(&self, ctx: &Context<'_>, name: String, author: String) -> ID {
-        let mut books = ctx.data_unchecked::<Storage>().lock().await;
-        let entry = books.vacant_entry();
-        let id: ID = entry.key().into();
-        let book = Book {
-            id: id.clone(),
-            name,
-            author,
-        };
-        entry.insert(book);
-        SimpleBroker::publish(BookChanged {
-            mutation_type: MutationType::Created,
-            id: id.clone(),
-        });
-        id
+    async fn create_book(&self, ctx: &Context<'_>, name: String, author: String) -> Result<ID> {
+        let book = ctx
+            .data_unchecked::<Storage>()
+            .create_book(name, author)
+            .await?;
+        let id = book.id.clone();
+        BookChanged::publish(
+            broker(ctx),
+            BookChanged {
+                mutation_type: MutationType::Created,
+                id: id.clone(),
+                previous_book: None,
+                new_book: Some(book),
+                sequence: 0,
+            },
+        )
+        .await?;
+        Ok(id)
+    }
+
+    async fn update_book(
+        &self,
+        ctx: &Context<'_>,
+        id: ID,
+        name: Option<String>,
+        author: Option<String>,
+    ) -> Result<bool> {
+        let id = id.parse::<usize>()?;
+        if let Some((previous, book)) = ctx
+            .data_unchecked::<Storage>()
+            .update_book(id, name, author)
+            .await?
+        {
+            BookChanged::publish(
+                broker(ctx),
+                BookChanged {
+                    mutation_type: MutationType::Updated,
+                    id: id.into(),
+                    previous_book: Some(previous),
+                    new_book: Some(book),
+                    sequence: 0,
+                },
+            )
+            .await?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
     }
 
     async fn delete_book(&self, ctx: &Context<'_>, id: ID) -> Result<bool> {
-        let mut books = ctx.data_unchecked::<Storage>().lock().await;
         let id = id.parse::<usize>()?;
-        if books.contains(id) {
-            books.remove(id);
-            SimpleBroker::publish(BookChanged {
-                mutation_type: MutationType::Deleted,
-                id: id.into(),
-            });
+        if let Some(book) = ctx.data_unchecked::<Storage>().delete_book(id).await? {
+            BookChanged::publish(
+                broker(ctx),
+                BookChanged {
+                    mutation_type: MutationType::Deleted,
+                    id: id.into(),
+                    previous_book: Some(book),
+                    new_book: None,
+                    sequence: 0,
+                },
+            )
+            .await?;
             Ok(true)
         } else {
             Ok(false)
@@ -80,16 +143,59 @@ impl MutationRoot {
     }
 }
 
-#[derive(Enum, Eq, PartialEq, Copy, Clone)]
+#[derive(Enum, Eq, PartialEq, Copy, Clone, Serialize, Deserialize)]
 enum MutationType {
+    /// New book created.
     Created,
+    /// Current book updated.
+    Updated,
+    /// Current book deleted.
     Deleted,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 struct BookChanged {
     mutation_type: MutationType,
     id: ID,
+    previous_book: Option<Book>,
+    new_book: Option<Book>,
+    /// Assigned by the broker, so it is not part of the published message.
+    #[serde(skip)]
+    sequence: u64,
+}
+
+impl BookChanged {
+    /// Publish the event to the subscribers of all books and to the
+    /// subscribers of this book.
+    async fn publish(broker: &SharedBroker, event: Self) -> Result<()> {
+        broker.publish(&book_topic(&event.id), &event).await?;
+        broker.publish(BOOKS_TOPIC, &event).await?;
+        Ok(())
+    }
+
+    fn from_event(event: BrokerEvent<BookChanged>) -> Result<Self> {
+        match event {
+            BrokerEvent::Message(sequence, event) => Ok(Self { sequence, ..event }),
+            BrokerEvent::Lagged(count) => Err(lagged_error(count)),
+        }
+    }
+}
+
+/// The broker in the schema data, or the process-wide one if there is none.
+fn broker<'a>(ctx: &Context<'a>) -> &'a SharedBroker {
+    ctx.data_opt::<SharedBroker>()
+        .unwrap_or_else(|| SharedBroker::global())
+}
+
+fn book_topic(id: &ID) -> String {
+    format!("book/{}", id.as_str())
+}
+
+fn lagged_error(count: usize) -> Error {
+    Error::new(format!("lagged {count} events")).extend_with(|_, e| {
+        e.set("code", "LAGGED");
+        e.set("count", count);
+    })
 }
 
 #[Object]
@@ -103,9 +209,24 @@ impl BookChanged {
     }
 
     async fn book(&self, ctx: &Context<'_>) -> Result<Option<Book>> {
-        let books = ctx.data_unchecked::<Storage>().lock().await;
         let id = self.id.parse::<usize>()?;
-        Ok(books.get(id).cloned())
+        ctx.data_unchecked::<Storage>().book(id).await
+    }
+
+    /// The book before the mutation, `null` if it was created.
+    async fn previous_book(&self) -> Option<&Book> {
+        self.previous_book.as_ref()
+    }
+
+    /// The book after the mutation, `null` if it was deleted.
+    async fn new_book(&self) -> Option<&Book> {
+        self.new_book.as_ref()
+    }
+
+    /// The position of the event in the subscription, pass it as `since` to
+    /// resume the subscription after this event.
+    async fn sequence(&self) -> u64 {
+        self.sequence
     }
 }
 
@@ -124,14 +245,162 @@ impl SubscriptionRoot {
         }
     }
 
-    async fn books(&self, mutation_type: Option<MutationType>) -> impl Stream<Item = BookChanged> {
-        SimpleBroker::<BookChanged>::subscribe().filter(move |event| {
-            let res = if let Some(mutation_type) = mutation_type {
-                event.mutation_type == mutation_type
-            } else {
-                true
-            };
-            async move { res }
-        })
+    /// Book mutations, an error with the `LAGGED` code is sent in place of
+    /// the events that were dropped because the client didn't keep up.
+    ///
+    /// If `since` is specified, the recent events after that sequence number
+    /// are sent first, preceded by a `LAGGED` error for those that are no
+    /// longer kept. A `since` greater than the last sequence number, such as
+    /// one from before a restart, also gets a `LAGGED` error followed by all
+    /// the recent events.
+    async fn books(
+        &self,
+        ctx: &Context<'_>,
+        mutation_type: Option<MutationType>,
+        since: Option<u64>,
+    ) -> Result<impl Stream<Item = Result<BookChanged>>> {
+        Ok(broker(ctx)
+            .subscribe::<BookChanged>(BOOKS_TOPIC, since)
+            .await?
+            .filter_map(move |event| {
+                let res = match event.and_then(BookChanged::from_event) {
+                    Ok(event) => {
+                        if let Some(mutation_type) = mutation_type {
+                            (event.mutation_type == mutation_type).then_some(Ok(event))
+                        } else {
+                            Some(Ok(event))
+                        }
+                    }
+                    Err(err) => Some(Err(err)),
+                };
+                async move { res }
+            }))
+    }
+
+    /// Mutations of the book with the specified id, replayed after `since`
+    /// like in `books`.
+    async fn book_changed(
+        &self,
+        ctx: &Context<'_>,
+        id: ID,
+        since: Option<u64>,
+    ) -> Result<impl Stream<Item = Result<BookChanged>>> {
+        Ok(broker(ctx)
+            .subscribe::<BookChanged>(&book_topic(&id), since)
+            .await?
+            .map(|event| event.and_then(BookChanged::from_event)))
+    }
+}
+
+async fn query_books(
+    after: Option<String>,
+    before: Option<String>,
+    first: Option<i32>,
+    last: Option<i32>,
+    books: Vec<Book>,
+    filter: BookFilter,
+    order_by: BookOrder,
+) -> Result<Connection<OpaqueCursor<BookCursor>, Book>> {
+    query(
+        after,
+        before,
+        first,
+        last,
+        |after: Option<OpaqueCursor<BookCursor>>,
+         before: Option<OpaqueCursor<BookCursor>>,
+         first,
+         last| async move {
+            let books = books
+                .into_iter()
+                .filter(|book| filter.matches(&book.name, &book.author))
+                .map(|book| Ok((order_by.cursor(&book.id, &book.name, &book.author)?, book)))
+                .collect::<Result<Vec<_>>>()?;
+            let page = page_books(
+                books,
+                order_by,
+                after.map(|after| after.0),
+                before.map(|before| before.0),
+                first,
+                last,
+            );
+
+            let mut connection = Connection::new(page.has_previous_page, page.has_next_page);
+            connection.edges.extend(
+                page.books
+                    .into_iter()
+                    .map(|(cursor, book)| Edge::new(OpaqueCursor(cursor), book)),
+            );
+            Ok::<_, Error>(connection)
+        },
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use async_graphql::value;
+    use futures_util::FutureExt;
+
+    use super::*;
+
+    fn new_schema() -> BooksSchema {
+        Schema::build(QueryRoot, MutationRoot, SubscriptionRoot)
+            .data(Storage::memory())
+            .data(SharedBroker::memory(Default::default()))
+            .finish()
+    }
+
+    #[tokio::test]
+    async fn schemas_have_their_own_broker() {
+        let schema = new_schema();
+        let other = new_schema();
+        let mut stream = schema.execute_stream("subscription { books { newBook { name } } }");
+        assert!(stream.next().now_or_never().is_none());
+
+        for (schema, query) in [
+            (&other, r#"mutation { createBook(name: "a", author: "x") }"#),
+            (
+                &schema,
+                r#"mutation { createBook(name: "b", author: "y") }"#,
+            ),
+        ] {
+            assert!(schema.execute(query).await.is_ok());
+        }
+
+        let response = stream.next().await.unwrap();
+        assert_eq!(
+            response.into_result().unwrap().data,
+            value!({ "books": { "newBook": { "name": "b" } } })
+        );
+        assert!(stream.next().now_or_never().is_none());
+    }
+
+    #[tokio::test]
+    async fn book_changed_only_sends_the_book() {
+        let schema = new_schema();
+        for query in [
+            r#"mutation { createBook(name: "a", author: "x") }"#,
+            r#"mutation { createBook(name: "b", author: "y") }"#,
+        ] {
+            assert!(schema.execute(query).await.is_ok());
+        }
+
+        let mut stream = schema
+            .execute_stream(r#"subscription { bookChanged(id: "1") { id newBook { name } } }"#);
+        assert!(stream.next().now_or_never().is_none());
+        for query in [
+            r#"mutation { updateBook(id: "0", name: "c") }"#,
+            r#"mutation { updateBook(id: "1", name: "d") }"#,
+            r#"mutation { deleteBook(id: "0") }"#,
+        ] {
+            assert!(schema.execute(query).await.is_ok());
+        }
+
+        let response = stream.next().await.unwrap();
+        assert_eq!(
+            response.into_result().unwrap().data,
+            value!({ "bookChanged": { "id": "1", "newBook": { "name": "d" } } })
+        );
+        assert!(stream.next().now_or_never().is_none());
     }
 }
@@ -1,68 +1,509 @@
 use std::{
     any::{Any, TypeId},
-    collections::HashMap,
+    collections::{HashMap, VecDeque},
     marker::PhantomData,
     pin::Pin,
-    sync::Mutex,
-    task::{Context, Poll},
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+    time::{Duration, Instant},
 };
 
-use futures_channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
-use futures_util::{Stream, StreamExt};
+use futures_util::Stream;
 use once_cell::sync::Lazy;
 use slab::Slab;
 
-static SUBSCRIBERS: Lazy<Mutex<HashMap<TypeId, Box<dyn Any + Send>>>> = Lazy::new(Default::default);
+static DEFAULT_BROKER: Lazy<MemoryBroker> = Lazy::new(Default::default);
 
-struct Senders<T>(Slab<UnboundedSender<T>>);
+/// The topic used by [`MemoryBroker::publish`] and [`MemoryBroker::subscribe`].
+pub const DEFAULT_TOPIC: &str = "";
 
-struct BrokerStream<T: Sync + Send + Clone + 'static>(usize, UnboundedReceiver<T>);
+/// What to do with a message when a subscriber's buffer is full.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum OverflowPolicy {
+    /// Drop the oldest buffered message to make room for the new one.
+    DropOldest,
+    /// Drop the new message and keep the buffered ones.
+    DropNewest,
+    /// Close the subscription stream after the buffered messages.
+    Disconnect,
+}
+
+/// How many messages are buffered for each subscriber, what happens when it
+/// doesn't keep up, how many messages of each topic are kept for replay, and
+/// how long a topic without subscribers is kept after it was last used.
+///
+/// When a topic is dropped its sequence numbers start over, the subscribers
+/// resuming from an older sequence number receive a [`BrokerEvent::Lagged`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct BrokerConfig {
+    pub capacity: usize,
+    pub policy: OverflowPolicy,
+    pub log_capacity: usize,
+    pub topic_ttl: Duration,
+}
+
+impl Default for BrokerConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 1024,
+            policy: OverflowPolicy::DropOldest,
+            log_capacity: 1024,
+            topic_ttl: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+/// An item of a subscription stream.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum BrokerEvent<T> {
+    /// A published message and its sequence number in the topic, sequence
+    /// numbers start at 1 and increase by 1 for every message.
+    Message(u64, T),
+    /// The number of messages dropped at this point because the subscriber
+    /// was too slow.
+    Lagged(usize),
+}
+
+struct Subscriber<T> {
+    config: BrokerConfig,
+    queue: VecDeque<BrokerEvent<T>>,
+    len: usize,
+    closed: bool,
+    waker: Option<Waker>,
+}
+
+impl<T> Subscriber<T> {
+    fn push(&mut self, seq: u64, msg: T) {
+        if self.closed {
+            return;
+        }
+
+        if self.len < self.config.capacity.max(1) {
+            self.queue.push_back(BrokerEvent::Message(seq, msg));
+            self.len += 1;
+        } else {
+            match self.config.policy {
+                OverflowPolicy::DropOldest => {
+                    let mut lagged = 1;
+                    if let Some(BrokerEvent::Lagged(n)) = self.queue.pop_front() {
+                        self.queue.pop_front();
+                        lagged += n;
+                    }
+                    self.queue.push_front(BrokerEvent::Lagged(lagged));
+                    self.queue.push_back(BrokerEvent::Message(seq, msg));
+                }
+                OverflowPolicy::DropNewest => match self.queue.back_mut() {
+                    Some(BrokerEvent::Lagged(n)) => *n += 1,
+                    _ => self.queue.push_back(BrokerEvent::Lagged(1)),
+                },
+                OverflowPolicy::Disconnect => {
+                    self.queue.push_back(BrokerEvent::Lagged(1));
+                    self.closed = true;
+                }
+            }
+        }
+
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+struct Topic<T> {
+    subscribers: Slab<Arc<Mutex<Subscriber<T>>>>,
+    log: VecDeque<(u64, T)>,
+    next_seq: u64,
+    /// When the last message was published or the last subscriber left.
+    last_used: Instant,
+}
+
+impl<T> Default for Topic<T> {
+    fn default() -> Self {
+        Self {
+            subscribers: Slab::new(),
+            log: VecDeque::new(),
+            next_seq: 1,
+            last_used: Instant::now(),
+        }
+    }
+}
+
+struct Senders<T> {
+    topics: HashMap<String, Topic<T>>,
+    last_pruned: Instant,
+}
+
+impl<T> Default for Senders<T> {
+    fn default() -> Self {
+        Self {
+            topics: HashMap::new(),
+            last_pruned: Instant::now(),
+        }
+    }
+}
+
+impl<T> Senders<T> {
+    /// Returns the topic named `topic`, first dropping the topics without
+    /// subscribers that weren't used for `ttl`, such as the topics of the
+    /// deleted books. They are looked for at most once per `ttl`, so a topic
+    /// is dropped between `ttl` and twice `ttl` after its last use.
+    fn topic(&mut self, topic: &str, ttl: Duration) -> &mut Topic<T> {
+        if self.last_pruned.elapsed() >= ttl {
+            self.topics.retain(|_, topic| {
+                !topic.subscribers.is_empty() || topic.last_used.elapsed() < ttl
+            });
+            self.last_pruned = Instant::now();
+        }
+        self.topics.entry(topic.to_string()).or_default()
+    }
+}
+
+type SendersMap = Arc<Mutex<HashMap<TypeId, Box<dyn Any + Send>>>>;
+
+struct BrokerStream<T: Sync + Send + Clone + 'static> {
+    senders: SendersMap,
+    topic: String,
+    id: usize,
+    subscriber: Arc<Mutex<Subscriber<T>>>,
+}
 
-fn with_senders<T, F, R>(f: F) -> R
+fn with_senders<T, F, R>(senders: &SendersMap, f: F) -> R
 where
     T: Sync + Send + Clone + 'static,
     F: FnOnce(&mut Senders<T>) -> R,
 {
-    let mut map = SUBSCRIBERS.lock().unwrap();
+    let mut map = senders.lock().unwrap();
     let senders = map
         .entry(TypeId::of::<Senders<T>>())
-        .or_insert_with(|| Box::new(Senders::<T>(Default::default())));
+        .or_insert_with(|| Box::new(Senders::<T>::default()));
     f(senders.downcast_mut::<Senders<T>>().unwrap())
 }
 
 impl<T: Sync + Send + Clone + 'static> Drop for BrokerStream<T> {
     fn drop(&mut self) {
-        with_senders::<T, _, _>(|senders| senders.0.remove(self.0));
+        with_senders::<T, _, _>(&self.senders, |senders| {
+            let Some(topic) = senders.topics.get_mut(&self.topic) else {
+                return;
+            };
+            topic.subscribers.remove(self.id);
+            if topic.subscribers.is_empty() {
+                if topic.log.is_empty() {
+                    senders.topics.remove(&self.topic);
+                } else {
+                    topic.last_used = Instant::now();
+                }
+            }
+        });
     }
 }
 
 impl<T: Sync + Send + Clone + 'static> Stream for BrokerStream<T> {
-    type Item = T;
+    type Item = BrokerEvent<T>;
 
-    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        self.1.poll_next_unpin(cx)
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut subscriber = self.subscriber.lock().unwrap();
+        match subscriber.queue.pop_front() {
+            Some(event) => {
+                if let BrokerEvent::Message(..) = event {
+                    subscriber.len -= 1;
+                }
+                Poll::Ready(Some(event))
+            }
+            None if subscriber.closed => Poll::Ready(None),
+            None => {
+                subscriber.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
     }
 }
 
-/// A simple broker based on memory
+/// A broker based on memory.
+///
+/// Clones share the same subscribers, so a broker only delivers messages
+/// within the schemas that it was given to.
+#[derive(Clone, Default)]
+pub struct MemoryBroker {
+    config: BrokerConfig,
+    senders: SendersMap,
+}
+
+impl MemoryBroker {
+    /// Create a broker that buffers messages for its subscribers according to
+    /// `config`.
+    pub fn new(config: BrokerConfig) -> Self {
+        Self {
+            config,
+            senders: Default::default(),
+        }
+    }
+
+    /// The broker shared by the whole process, used by [`SimpleBroker`].
+    pub fn global() -> &'static MemoryBroker {
+        &DEFAULT_BROKER
+    }
+
+    /// Publish a message that all subscription streams of the default topic
+    /// can receive, returns its sequence number.
+    pub fn publish<T: Sync + Send + Clone + 'static>(&self, msg: T) -> u64 {
+        self.publish_to(DEFAULT_TOPIC, msg)
+    }
+
+    /// Publish a message that only the subscription streams of `topic` can
+    /// receive, returns its sequence number.
+    pub fn publish_to<T: Sync + Send + Clone + 'static>(&self, topic: &str, msg: T) -> u64 {
+        with_senders::<T, _, _>(&self.senders, |senders| {
+            let entry = senders.topic(topic, self.config.topic_ttl);
+            let seq = entry.next_seq;
+            entry.next_seq += 1;
+            entry.last_used = Instant::now();
+            for (_, subscriber) in &entry.subscribers {
+                subscriber.lock().unwrap().push(seq, msg.clone());
+            }
+            if self.config.log_capacity > 0 {
+                if entry.log.len() == self.config.log_capacity {
+                    entry.log.pop_front();
+                }
+                entry.log.push_back((seq, msg));
+            }
+            seq
+        })
+    }
+
+    /// Subscribe to the message of the specified type on the default topic and
+    /// returns a `Stream`, messages are buffered for it as configured in
+    /// [`MemoryBroker::new`].
+    pub fn subscribe<T: Sync + Send + Clone + 'static>(
+        &self,
+    ) -> impl Stream<Item = BrokerEvent<T>> + use<T> {
+        self.subscribe_to(DEFAULT_TOPIC, None)
+    }
+
+    /// Like [`MemoryBroker::subscribe`], but only receives the messages
+    /// published to `topic`.
+    ///
+    /// If `since` is specified, the logged messages with a greater sequence
+    /// number are received first, preceded by a [`BrokerEvent::Lagged`] if
+    /// some of them are no longer in the log. If `since` is greater than the
+    /// sequence number of the last message, the topic was dropped since then:
+    /// all the logged messages are received, preceded by a
+    /// [`BrokerEvent::Lagged`].
+    pub fn subscribe_to<T: Sync + Send + Clone + 'static>(
+        &self,
+        topic: &str,
+        since: Option<u64>,
+    ) -> impl Stream<Item = BrokerEvent<T>> + use<T> {
+        with_senders::<T, _, _>(&self.senders, |senders| {
+            let entry = senders.topic(topic, self.config.topic_ttl);
+            let mut subscriber = Subscriber {
+                config: self.config,
+                queue: VecDeque::new(),
+                len: 0,
+                closed: false,
+                waker: None,
+            };
+            if let Some(since) = since {
+                let (since, missing) = resume_from(
+                    since,
+                    entry.log.front().map(|(seq, _)| *seq),
+                    entry.next_seq,
+                );
+                if missing > 0 {
+                    subscriber.queue.push_back(BrokerEvent::Lagged(missing));
+                }
+                for (seq, msg) in entry.log.iter().filter(|(seq, _)| *seq > since) {
+                    subscriber.push(*seq, msg.clone());
+                }
+            }
+            let subscriber = Arc::new(Mutex::new(subscriber));
+            let id = entry.subscribers.insert(subscriber.clone());
+            BrokerStream {
+                senders: self.senders.clone(),
+                topic: topic.to_string(),
+                id,
+                subscriber,
+            }
+        })
+    }
+}
+
+/// Where a subscription resuming after the sequence number `since` starts,
+/// given the sequence number of the first logged message and the next one,
+/// and how many messages it missed.
+///
+/// A `since` beyond the last message was given by an older instance of the
+/// topic, so the subscription starts at the beginning of the log and the
+/// messages published in between are reported missing, at least one.
+pub(crate) fn resume_from(since: u64, first_logged: Option<u64>, next_seq: u64) -> (u64, usize) {
+    let first_seq = first_logged.unwrap_or(next_seq);
+    if since >= next_seq {
+        (0, first_seq.saturating_sub(1).max(1) as usize)
+    } else {
+        (since, first_seq.saturating_sub(since + 1) as usize)
+    }
+}
+
+/// A simple broker based on memory, shared by the whole process.
 pub struct SimpleBroker<T>(PhantomData<T>);
 
 impl<T: Sync + Send + Clone + 'static> SimpleBroker<T> {
     /// Publish a message that all subscription streams can receive.
-    pub fn publish(msg: T) {
-        with_senders::<T, _, _>(|senders| {
-            for (_, sender) in senders.0.iter_mut() {
-                sender.start_send(msg.clone()).ok();
-            }
-        });
+    pub fn publish(msg: T) -> u64 {
+        MemoryBroker::global().publish(msg)
+    }
+
+    /// Publish a message that only the subscription streams of `topic` can
+    /// receive.
+    pub fn publish_to(topic: &str, msg: T) -> u64 {
+        MemoryBroker::global().publish_to(topic, msg)
     }
 
     /// Subscribe to the message of the specified type and returns a `Stream`.
-    pub fn subscribe() -> impl Stream<Item = T> {
-        with_senders::<T, _, _>(|senders| {
-            let (tx, rx) = mpsc::unbounded();
-            let id = senders.0.insert(tx);
-            BrokerStream(id, rx)
-        })
+    pub fn subscribe() -> impl Stream<Item = BrokerEvent<T>> {
+        MemoryBroker::global().subscribe()
+    }
+
+    /// Subscribe to the message of the specified type published to `topic`
+    /// after `since` and returns a `Stream`.
+    pub fn subscribe_to(
+        topic: &str,
+        since: Option<u64>,
+    ) -> impl Stream<Item = BrokerEvent<T>> + use<T> {
+        MemoryBroker::global().subscribe_to(topic, since)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::{FutureExt, StreamExt};
+
+    use super::*;
+
+    fn next<T>(
+        stream: &mut (impl Stream<Item = BrokerEvent<T>> + Unpin),
+    ) -> Option<BrokerEvent<T>> {
+        stream.next().now_or_never().flatten()
+    }
+
+    #[test]
+    fn resume_from_older_topic() {
+        let broker = MemoryBroker::default();
+        broker.publish_to("books", "a");
+        broker.publish_to("books", "b");
+
+        let mut stream = broker.subscribe_to::<&str>("books", Some(5));
+        assert_eq!(next(&mut stream), Some(BrokerEvent::Lagged(1)));
+        assert_eq!(next(&mut stream), Some(BrokerEvent::Message(1, "a")));
+        assert_eq!(next(&mut stream), Some(BrokerEvent::Message(2, "b")));
+        assert_eq!(next(&mut stream), None);
+
+        let mut stream = broker.subscribe_to::<&str>("books", Some(2));
+        assert_eq!(next(&mut stream), None);
+    }
+
+    #[test]
+    fn topics_are_separate() {
+        let broker = MemoryBroker::default();
+        let mut books = broker.subscribe_to::<&str>("book/1", None);
+        let mut other = broker.subscribe_to::<&str>("book/2", None);
+        broker.publish_to("book/2", "a");
+        broker.publish_to("book/1", "b");
+
+        assert_eq!(next(&mut books), Some(BrokerEvent::Message(1, "b")));
+        assert_eq!(next(&mut books), None);
+        assert_eq!(next(&mut other), Some(BrokerEvent::Message(1, "a")));
+        assert_eq!(next(&mut other), None);
+    }
+
+    #[test]
+    fn brokers_are_separate() {
+        let broker = MemoryBroker::default();
+        let other = MemoryBroker::default();
+        let mut stream = broker.subscribe::<&str>();
+        let mut other_stream = other.subscribe::<&str>();
+        other.publish("a");
+        broker.publish("b");
+
+        assert_eq!(next(&mut stream), Some(BrokerEvent::Message(1, "b")));
+        assert_eq!(next(&mut stream), None);
+        assert_eq!(next(&mut other_stream), Some(BrokerEvent::Message(1, "a")));
+        assert_eq!(next(&mut other_stream), None);
+    }
+
+    #[test]
+    fn drop_unused_topics() {
+        let broker = MemoryBroker::new(BrokerConfig {
+            topic_ttl: Duration::ZERO,
+            ..Default::default()
+        });
+        assert_eq!(broker.publish_to("book/1", "a"), 1);
+        assert_eq!(broker.publish_to("book/1", "b"), 1);
+
+        // A topic with subscribers is kept.
+        let stream = broker.subscribe_to::<&str>("book/1", None);
+        assert_eq!(broker.publish_to("book/2", "c"), 1);
+        assert_eq!(broker.publish_to("book/1", "d"), 1);
+        assert_eq!(broker.publish_to("book/1", "e"), 2);
+        drop(stream);
+
+        drop(broker.subscribe_to::<&str>("book/3", None));
+        let topics = with_senders::<&str, _, _>(&broker.senders, |senders| senders.topics.len());
+        assert_eq!(topics, 0);
+    }
+
+    /// A broker that buffers two messages per subscriber, with a subscriber
+    /// that doesn't read the four messages published before it polls.
+    fn slow_subscriber(
+        policy: OverflowPolicy,
+    ) -> (
+        MemoryBroker,
+        impl Stream<Item = BrokerEvent<&'static str>> + Unpin,
+    ) {
+        let broker = MemoryBroker::new(BrokerConfig {
+            capacity: 2,
+            policy,
+            ..Default::default()
+        });
+        let stream = broker.subscribe_to::<&str>("books", None);
+        for msg in ["a", "b", "c", "d"] {
+            broker.publish_to("books", msg);
+        }
+        (broker, Box::pin(stream))
+    }
+
+    #[test]
+    fn drop_oldest() {
+        let (broker, mut stream) = slow_subscriber(OverflowPolicy::DropOldest);
+        assert_eq!(next(&mut stream), Some(BrokerEvent::Lagged(2)));
+        assert_eq!(next(&mut stream), Some(BrokerEvent::Message(3, "c")));
+        assert_eq!(next(&mut stream), Some(BrokerEvent::Message(4, "d")));
+        assert_eq!(next(&mut stream), None);
+
+        broker.publish_to("books", "e");
+        assert_eq!(next(&mut stream), Some(BrokerEvent::Message(5, "e")));
+    }
+
+    #[test]
+    fn drop_newest() {
+        let (broker, mut stream) = slow_subscriber(OverflowPolicy::DropNewest);
+        assert_eq!(next(&mut stream), Some(BrokerEvent::Message(1, "a")));
+        assert_eq!(next(&mut stream), Some(BrokerEvent::Message(2, "b")));
+        assert_eq!(next(&mut stream), Some(BrokerEvent::Lagged(2)));
+        assert_eq!(next(&mut stream), None);
+
+        broker.publish_to("books", "e");
+        assert_eq!(next(&mut stream), Some(BrokerEvent::Message(5, "e")));
+    }
+
+    #[test]
+    fn disconnect() {
+        let (broker, mut stream) = slow_subscriber(OverflowPolicy::Disconnect);
+        assert_eq!(next(&mut stream), Some(BrokerEvent::Message(1, "a")));
+        assert_eq!(next(&mut stream), Some(BrokerEvent::Message(2, "b")));
+        assert_eq!(next(&mut stream), Some(BrokerEvent::Lagged(1)));
+
+        // The stream ends instead of waiting for the next message.
+        broker.publish_to("books", "e");
+        assert_eq!(stream.next().now_or_never(), Some(None));
     }
 }
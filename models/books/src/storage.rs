@@ -0,0 +1,341 @@
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use async_graphql::{ID, Result};
+use rusqlite::{Connection, OptionalExtension, params};
+use slab::Slab;
+
+use crate::Book;
+
+/// A repository that books are read from and written to.
+///
+/// The methods may block, the resolvers call them through [`Storage`] which
+/// runs them on a thread pool.
+pub trait BookRepository: Send + Sync {
+    /// Returns all books.
+    fn books(&self) -> Result<Vec<Book>>;
+
+    /// Returns the book with the specified id.
+    fn book(&self, id: usize) -> Result<Option<Book>>;
+
+    /// Stores a new book and returns it.
+    fn create_book(&self, name: String, author: String) -> Result<Book>;
+
+    /// Changes the name and/or author of the book with the specified id,
+    /// returns the previous and the new value or `None` if it does not exist.
+    fn update_book(
+        &self,
+        id: usize,
+        name: Option<String>,
+        author: Option<String>,
+    ) -> Result<Option<(Book, Book)>>;
+
+    /// Removes the book with the specified id, returns the removed book or
+    /// `None` if it does not exist.
+    fn delete_book(&self, id: usize) -> Result<Option<Book>>;
+}
+
+/// The repository used by the books schema.
+///
+/// Put it into the schema data when building the schema, the default is an
+/// in-memory repository:
+///
+/// ```ignore
+/// let schema = Schema::build(QueryRoot, MutationRoot, SubscriptionRoot)
+///     .data(Storage::sqlite("books.db")?)
+///     .finish();
+/// ```
+#[derive(Clone)]
+pub struct Storage(Arc<dyn BookRepository>);
+
+impl Storage {
+    /// Create a storage from a repository.
+    pub fn new(repository: impl BookRepository + 'static) -> Self {
+        Self(Arc::new(repository))
+    }
+
+    /// Create a storage that keeps books in memory.
+    pub fn memory() -> Self {
+        Self::new(MemoryStorage::default())
+    }
+
+    /// Create a storage that keeps books in the SQLite database at `path`.
+    pub fn sqlite(path: impl AsRef<Path>) -> Result<Self> {
+        Ok(Self::new(SqliteStorage::open(path)?))
+    }
+}
+
+impl Storage {
+    /// Returns all books.
+    pub async fn books(&self) -> Result<Vec<Book>> {
+        self.run(|repository| repository.books()).await
+    }
+
+    /// Returns the book with the specified id.
+    pub async fn book(&self, id: usize) -> Result<Option<Book>> {
+        self.run(move |repository| repository.book(id)).await
+    }
+
+    /// Stores a new book and returns it.
+    pub async fn create_book(&self, name: String, author: String) -> Result<Book> {
+        self.run(move |repository| repository.create_book(name, author))
+            .await
+    }
+
+    /// Changes the name and/or author of the book with the specified id,
+    /// returns the previous and the new value or `None` if it does not exist.
+    pub async fn update_book(
+        &self,
+        id: usize,
+        name: Option<String>,
+        author: Option<String>,
+    ) -> Result<Option<(Book, Book)>> {
+        self.run(move |repository| repository.update_book(id, name, author))
+            .await
+    }
+
+    /// Removes the book with the specified id, returns the removed book or
+    /// `None` if it does not exist.
+    pub async fn delete_book(&self, id: usize) -> Result<Option<Book>> {
+        self.run(move |repository| repository.delete_book(id)).await
+    }
+
+    /// Calls the repository on a thread where blocking is allowed, so that a
+    /// slow query doesn't stall the executor.
+    async fn run<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&dyn BookRepository) -> Result<T> + Send + 'static,
+    {
+        let repository = self.0.clone();
+        blocking::unblock(move || f(&*repository)).await
+    }
+}
+
+impl Default for Storage {
+    fn default() -> Self {
+        Self::memory()
+    }
+}
+
+/// A repository based on memory, books are lost when the process exits.
+#[derive(Default)]
+pub struct MemoryStorage(Mutex<Slab<Book>>);
+
+impl BookRepository for MemoryStorage {
+    fn books(&self) -> Result<Vec<Book>> {
+        let books = self.0.lock().unwrap();
+        Ok(books.iter().map(|(_, book)| book).cloned().collect())
+    }
+
+    fn book(&self, id: usize) -> Result<Option<Book>> {
+        let books = self.0.lock().unwrap();
+        Ok(books.get(id).cloned())
+    }
+
+    fn create_book(&self, name: String, author: String) -> Result<Book> {
+        let mut books = self.0.lock().unwrap();
+        let entry = books.vacant_entry();
+        let book = Book {
+            id: entry.key().into(),
+            name,
+            author,
+        };
+        entry.insert(book.clone());
+        Ok(book)
+    }
+
+    fn update_book(
+        &self,
+        id: usize,
+        name: Option<String>,
+        author: Option<String>,
+    ) -> Result<Option<(Book, Book)>> {
+        let mut books = self.0.lock().unwrap();
+        let Some(book) = books.get_mut(id) else {
+            return Ok(None);
+        };
+        let previous = book.clone();
+        if let Some(name) = name {
+            book.name = name;
+        }
+        if let Some(author) = author {
+            book.author = author;
+        }
+        Ok(Some((previous, book.clone())))
+    }
+
+    fn delete_book(&self, id: usize) -> Result<Option<Book>> {
+        let mut books = self.0.lock().unwrap();
+        Ok(books.try_remove(id))
+    }
+}
+
+/// A repository based on an embedded SQLite database.
+pub struct SqliteStorage(Mutex<Connection>);
+
+impl SqliteStorage {
+    /// Open the database at `path`, creating it if it does not exist.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::with_connection(Connection::open(path)?)
+    }
+
+    /// Open a private database that only lives as long as the returned value.
+    pub fn open_in_memory() -> Result<Self> {
+        Self::with_connection(Connection::open_in_memory()?)
+    }
+
+    fn with_connection(conn: Connection) -> Result<Self> {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS books (
+                id     INTEGER PRIMARY KEY AUTOINCREMENT,
+                name   TEXT NOT NULL,
+                author TEXT NOT NULL
+            )",
+        )?;
+        Ok(Self(Mutex::new(conn)))
+    }
+}
+
+fn book_from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<Book> {
+    Ok(Book {
+        id: ID::from(row.get::<_, i64>(0)?),
+        name: row.get(1)?,
+        author: row.get(2)?,
+    })
+}
+
+fn select_book(conn: &Connection, id: usize) -> rusqlite::Result<Option<Book>> {
+    conn.query_row(
+        "SELECT id, name, author FROM books WHERE id = ?1",
+        params![id as i64],
+        book_from_row,
+    )
+    .optional()
+}
+
+impl BookRepository for SqliteStorage {
+    fn books(&self) -> Result<Vec<Book>> {
+        let conn = self.0.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT id, name, author FROM books ORDER BY id")?;
+        let books = stmt
+            .query_map([], book_from_row)?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(books)
+    }
+
+    fn book(&self, id: usize) -> Result<Option<Book>> {
+        let conn = self.0.lock().unwrap();
+        Ok(select_book(&conn, id)?)
+    }
+
+    fn create_book(&self, name: String, author: String) -> Result<Book> {
+        let conn = self.0.lock().unwrap();
+        conn.execute(
+            "INSERT INTO books (name, author) VALUES (?1, ?2)",
+            params![name, author],
+        )?;
+        Ok(Book {
+            id: conn.last_insert_rowid().into(),
+            name,
+            author,
+        })
+    }
+
+    fn update_book(
+        &self,
+        id: usize,
+        name: Option<String>,
+        author: Option<String>,
+    ) -> Result<Option<(Book, Book)>> {
+        let mut conn = self.0.lock().unwrap();
+        let tx = conn.transaction()?;
+        let Some(previous) = select_book(&tx, id)? else {
+            return Ok(None);
+        };
+        let book = Book {
+            id: previous.id.clone(),
+            name: name.unwrap_or_else(|| previous.name.clone()),
+            author: author.unwrap_or_else(|| previous.author.clone()),
+        };
+        tx.execute(
+            "UPDATE books SET name = ?1, author = ?2 WHERE id = ?3",
+            params![book.name, book.author, id as i64],
+        )?;
+        tx.commit()?;
+        Ok(Some((previous, book)))
+    }
+
+    fn delete_book(&self, id: usize) -> Result<Option<Book>> {
+        let mut conn = self.0.lock().unwrap();
+        let tx = conn.transaction()?;
+        let Some(book) = select_book(&tx, id)? else {
+            return Ok(None);
+        };
+        tx.execute("DELETE FROM books WHERE id = ?1", params![id as i64])?;
+        tx.commit()?;
+        Ok(Some(book))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_graphql::{Schema, value};
+
+    use super::*;
+    use crate::{MutationRoot, QueryRoot, SubscriptionRoot};
+
+    #[test]
+    fn sqlite_repository() {
+        let storage = SqliteStorage::open_in_memory().unwrap();
+        let first = storage.create_book("a".into(), "x".into()).unwrap();
+        let second = storage.create_book("b".into(), "y".into()).unwrap();
+        assert_eq!(first.id.as_str(), "1");
+        assert_eq!(second.id.as_str(), "2");
+
+        let (previous, book) = storage
+            .update_book(1, None, Some("z".into()))
+            .unwrap()
+            .unwrap();
+        assert_eq!((previous.name, previous.author), ("a".into(), "x".into()));
+        assert_eq!((book.name, book.author), ("a".into(), "z".into()));
+        assert!(storage.update_book(3, None, None).unwrap().is_none());
+
+        assert_eq!(storage.delete_book(2).unwrap().unwrap().name, "b");
+        assert!(storage.delete_book(2).unwrap().is_none());
+        assert!(storage.book(2).unwrap().is_none());
+
+        let books = storage.books().unwrap();
+        assert_eq!(books.len(), 1);
+        assert_eq!(books[0].author, "z");
+    }
+
+    #[tokio::test]
+    async fn sqlite_schema() {
+        let schema = Schema::build(QueryRoot, MutationRoot, SubscriptionRoot)
+            .data(Storage::new(SqliteStorage::open_in_memory().unwrap()))
+            .finish();
+        for query in [
+            r#"mutation { createBook(name: "a", author: "x") }"#,
+            r#"mutation { createBook(name: "b", author: "y") }"#,
+            r#"mutation { updateBook(id: "1", author: "z") }"#,
+            r#"mutation { deleteBook(id: "2") }"#,
+        ] {
+            let response = schema.execute(query).await;
+            assert!(response.errors.is_empty(), "{:?}", response.errors);
+        }
+
+        let data = schema
+            .execute("{ books { id name author } }")
+            .await
+            .into_result()
+            .unwrap()
+            .data;
+        assert_eq!(
+            data,
+            value!({ "books": [{ "id": "1", "name": "a", "author": "z" }] })
+        );
+    }
+}
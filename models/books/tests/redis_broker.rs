@@ -0,0 +1,283 @@
+//! Tests of `RedisBroker` against an in-process stand-in for a Redis server,
+//! which speaks just enough of the protocol for the broker.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use books::{Broker, BrokerEvent, RedisBroker};
+use futures_util::StreamExt;
+use redis::Client;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, tcp::OwnedReadHalf},
+    sync::mpsc::{self, UnboundedSender},
+};
+
+#[derive(Default)]
+struct State {
+    connections: usize,
+    script_loaded: bool,
+    seqs: HashMap<String, u64>,
+    logs: HashMap<String, VecDeque<String>>,
+    ttls: HashMap<String, u64>,
+    channels: HashMap<String, Vec<UnboundedSender<Vec<u8>>>>,
+}
+
+/// A Redis server that keeps its data in memory and runs the publish script
+/// of the broker as native code.
+struct FakeRedis {
+    url: String,
+    state: Arc<Mutex<State>>,
+}
+
+impl FakeRedis {
+    async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("redis://{}/", listener.local_addr().unwrap());
+        let state = Arc::<Mutex<State>>::default();
+        let server_state = state.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                server_state.lock().unwrap().connections += 1;
+                let (reader, mut writer) = stream.into_split();
+                let (tx, mut rx) = mpsc::unbounded_channel::<Vec<u8>>();
+                tokio::spawn(async move {
+                    while let Some(reply) = rx.recv().await {
+                        if writer.write_all(&reply).await.is_err() {
+                            break;
+                        }
+                    }
+                });
+                tokio::spawn(serve(BufReader::new(reader), tx, server_state.clone()));
+            }
+        });
+        Self { url, state }
+    }
+
+    fn client(&self) -> Client {
+        Client::open(self.url.as_str()).unwrap()
+    }
+
+    fn connections(&self) -> usize {
+        self.state.lock().unwrap().connections
+    }
+
+    /// Forgets the keys of `topic`, as if they expired.
+    fn expire(&self, topic: &str) {
+        let mut state = self.state.lock().unwrap();
+        state.seqs.remove(&format!("{topic}:seq"));
+        state.logs.remove(&format!("{topic}:log"));
+    }
+}
+
+async fn serve(
+    mut reader: BufReader<OwnedReadHalf>,
+    tx: UnboundedSender<Vec<u8>>,
+    state: Arc<Mutex<State>>,
+) {
+    let mut queued: Option<Vec<Vec<u8>>> = None;
+    while let Some(args) = read_command(&mut reader).await {
+        let name = String::from_utf8_lossy(&args[0]).to_uppercase();
+        let reply = if name == "MULTI" {
+            queued = Some(Vec::new());
+            simple("OK")
+        } else if name == "EXEC" {
+            array(queued.take().unwrap_or_default())
+        } else if let Some(queue) = &mut queued {
+            queue.push(execute(&args, &tx, &state));
+            simple("QUEUED")
+        } else {
+            execute(&args, &tx, &state)
+        };
+        if tx.send(reply).is_err() {
+            break;
+        }
+    }
+}
+
+async fn read_command(reader: &mut BufReader<OwnedReadHalf>) -> Option<Vec<Vec<u8>>> {
+    let mut line = String::new();
+    reader.read_line(&mut line).await.ok().filter(|n| *n > 0)?;
+    let count: usize = line.trim_end().strip_prefix('*')?.parse().ok()?;
+    let mut args = Vec::with_capacity(count);
+    for _ in 0..count {
+        line.clear();
+        reader.read_line(&mut line).await.ok()?;
+        let len: usize = line.trim_end().strip_prefix('$')?.parse().ok()?;
+        let mut arg = vec![0; len + 2];
+        reader.read_exact(&mut arg).await.ok()?;
+        arg.truncate(len);
+        args.push(arg);
+    }
+    Some(args)
+}
+
+fn execute(args: &[Vec<u8>], tx: &UnboundedSender<Vec<u8>>, state: &Mutex<State>) -> Vec<u8> {
+    let args = args
+        .iter()
+        .map(|arg| String::from_utf8_lossy(arg).into_owned())
+        .collect::<Vec<_>>();
+    let mut state = state.lock().unwrap();
+    match args[0].to_uppercase().as_str() {
+        "CLIENT" => simple("OK"),
+        "PING" => simple("PONG"),
+        "SCRIPT" => {
+            state.script_loaded = true;
+            bulk("sha")
+        }
+        "EVALSHA" if !state.script_loaded => error("NOSCRIPT No matching script."),
+        "EVALSHA" => {
+            let [seq_key, log_key, topic, payload, log_capacity, ttl] = &args[3..] else {
+                return error("ERR wrong number of arguments");
+            };
+            let seq = state.seqs.entry(seq_key.clone()).or_default();
+            *seq += 1;
+            let seq = *seq;
+            let msg = serde_json::json!({ "seq": seq, "payload": payload }).to_string();
+            let ttl: u64 = ttl.parse().unwrap();
+            state.ttls.insert(seq_key.clone(), ttl);
+            let log_capacity: usize = log_capacity.parse().unwrap();
+            if log_capacity > 0 {
+                let log = state.logs.entry(log_key.clone()).or_default();
+                log.push_front(msg.clone());
+                log.truncate(log_capacity);
+                state.ttls.insert(log_key.clone(), ttl);
+            }
+            if let Some(subscribers) = state.channels.get_mut(topic) {
+                let message = array(vec![bulk("message"), bulk(topic), bulk(&msg)]);
+                subscribers.retain(|subscriber| subscriber.send(message.clone()).is_ok());
+            }
+            integer(seq)
+        }
+        "SUBSCRIBE" => {
+            state
+                .channels
+                .entry(args[1].clone())
+                .or_default()
+                .push(tx.clone());
+            array(vec![bulk("subscribe"), bulk(&args[1]), integer(1)])
+        }
+        "LRANGE" => array(
+            state
+                .logs
+                .get(&args[1])
+                .into_iter()
+                .flatten()
+                .map(|msg| bulk(msg))
+                .collect(),
+        ),
+        "GET" => match state.seqs.get(&args[1]) {
+            Some(seq) => bulk(&seq.to_string()),
+            None => b"$-1\r\n".to_vec(),
+        },
+        _ => error("ERR unknown command"),
+    }
+}
+
+fn simple(value: &str) -> Vec<u8> {
+    format!("+{value}\r\n").into_bytes()
+}
+
+fn error(message: &str) -> Vec<u8> {
+    format!("-{message}\r\n").into_bytes()
+}
+
+fn integer(value: u64) -> Vec<u8> {
+    format!(":{value}\r\n").into_bytes()
+}
+
+fn bulk(value: &str) -> Vec<u8> {
+    format!("${}\r\n{value}\r\n", value.len()).into_bytes()
+}
+
+fn array(items: Vec<Vec<u8>>) -> Vec<u8> {
+    let mut reply = format!("*{}\r\n", items.len()).into_bytes();
+    reply.extend(items.into_iter().flatten());
+    reply
+}
+
+#[tokio::test]
+async fn publish_reuses_the_connection() {
+    let redis = FakeRedis::start().await;
+    let broker = RedisBroker::new(redis.client());
+    for expected in 1..=3 {
+        let seq = broker.publish("books", "{}".to_string()).await.unwrap();
+        assert_eq!(seq, expected);
+    }
+    let seq = broker
+        .clone()
+        .publish("books", "{}".to_string())
+        .await
+        .unwrap();
+    assert_eq!(seq, 4);
+    assert_eq!(redis.connections(), 1);
+}
+
+#[tokio::test]
+async fn subscribe_replays_the_log_then_receives() {
+    let redis = FakeRedis::start().await;
+    let broker = RedisBroker::new(redis.client()).with_log_capacity(1);
+    broker.publish("books", "a".to_string()).await.unwrap();
+    broker.publish("books", "b".to_string()).await.unwrap();
+
+    let mut stream = broker.subscribe("books", Some(0)).await.unwrap();
+    assert_eq!(stream.next().await, Some(BrokerEvent::Lagged(1)));
+    assert_eq!(
+        stream.next().await,
+        Some(BrokerEvent::Message(2, "b".to_string()))
+    );
+
+    broker.publish("books", "c".to_string()).await.unwrap();
+    assert_eq!(
+        stream.next().await,
+        Some(BrokerEvent::Message(3, "c".to_string()))
+    );
+
+    // The publishing connection and the pub/sub connection of the
+    // subscription.
+    assert_eq!(redis.connections(), 2);
+}
+
+#[tokio::test]
+async fn publish_sets_the_ttl_of_the_topic() {
+    let redis = FakeRedis::start().await;
+    let broker = RedisBroker::new(redis.client()).with_topic_ttl(Duration::from_secs(90));
+    broker.publish("books", "a".to_string()).await.unwrap();
+
+    let state = redis.state.lock().unwrap();
+    assert_eq!(state.ttls.get("books:seq"), Some(&90));
+    assert_eq!(state.ttls.get("books:log"), Some(&90));
+}
+
+#[tokio::test]
+async fn subscribe_after_the_topic_expired() {
+    let redis = FakeRedis::start().await;
+    let broker = RedisBroker::new(redis.client());
+    for payload in ["a", "b", "c"] {
+        broker.publish("books", payload.to_string()).await.unwrap();
+    }
+    let mut stream = broker.subscribe("books", Some(2)).await.unwrap();
+    redis.expire("books");
+
+    // The live messages restart at 1 and are still received.
+    broker.publish("books", "d".to_string()).await.unwrap();
+    assert_eq!(
+        stream.next().await,
+        Some(BrokerEvent::Message(3, "c".to_string()))
+    );
+    assert_eq!(
+        stream.next().await,
+        Some(BrokerEvent::Message(1, "d".to_string()))
+    );
+
+    // Resuming from before the expiration replays the new log.
+    let mut stream = broker.subscribe("books", Some(3)).await.unwrap();
+    assert_eq!(stream.next().await, Some(BrokerEvent::Lagged(1)));
+    assert_eq!(
+        stream.next().await,
+        Some(BrokerEvent::Message(1, "d".to_string()))
+    );
+}
@@ -19,6 +19,7 @@ type Storage = Arc<Mutex<Slab<Book>>>;
 #[derive(Eq, PartialEq, Copy, Clone, Debug)]
 pub enum MutationType {
     Created,
+    Updated,
     Deleted,
 }
 
@@ -28,6 +29,7 @@ impl FromStr for MutationType {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s {
             "CREATED" => Ok(MutationType::Created),
+            "UPDATED" => Ok(MutationType::Updated),
             "DELETED" => Ok(MutationType::Deleted),
             _ => Err(format!("Invalid MutationType: {}", s)),
         }
@@ -38,4 +40,188 @@ impl FromStr for MutationType {
 struct BookChanged {
     mutation_type: MutationType,
     id: ID,
+    previous_book: Option<Book>,
+    new_book: Option<Book>,
+}
+
+#[cfg(test)]
+mod tests {
+    use async_graphql::{Executor, value};
+    use futures_util::{FutureExt, StreamExt};
+    use serde_json::{Value, json};
+
+    async fn execute(schema: &impl Executor, query: &str) -> Value {
+        let response = schema.execute(query.into()).await.into_result().unwrap();
+        response.data.into_json().unwrap()
+    }
+
+    /// The ids of the books of a `booksConnection` page, whether there are
+    /// previous and next pages, and the start and end cursors.
+    async fn page(schema: &impl Executor, arguments: &str) -> (Vec<Value>, Value) {
+        let data = execute(
+            schema,
+            &format!(
+                "{{ booksConnection({arguments}) {{ nodes {{ id }} pageInfo {{ hasPreviousPage hasNextPage startCursor endCursor }} }} }}"
+            ),
+        )
+        .await;
+        let connection = &data["booksConnection"];
+        let ids = connection["nodes"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|node| node["id"].clone())
+            .collect();
+        (ids, connection["pageInfo"].clone())
+    }
+
+    /// Pages through the books of `schema`, which must be empty.
+    async fn check_books_connection(schema: impl Executor) {
+        for (name, author) in [
+            ("Dune", "Frank Herbert"),
+            ("Children of Dune", "Frank Herbert"),
+            ("Hyperion", "Dan Simmons"),
+            ("Foundation", "Isaac Asimov"),
+            ("Dune Messiah", "Frank Herbert"),
+        ] {
+            execute(
+                &schema,
+                &format!(r#"mutation {{ createBook(name: "{name}", author: "{author}") }}"#),
+            )
+            .await;
+        }
+
+        // first and after
+        let (ids, page_info) = page(&schema, "first: 2").await;
+        assert_eq!(ids, [json!("0"), json!("1")]);
+        assert_eq!(page_info["hasPreviousPage"], false);
+        assert_eq!(page_info["hasNextPage"], true);
+        let (ids, page_info) = page(
+            &schema,
+            &format!("first: 2, after: {}", page_info["endCursor"]),
+        )
+        .await;
+        assert_eq!(ids, [json!("2"), json!("3")]);
+        assert_eq!(page_info["hasPreviousPage"], true);
+        assert_eq!(page_info["hasNextPage"], true);
+
+        // last and before
+        let (ids, page_info) = page(&schema, "last: 2").await;
+        assert_eq!(ids, [json!("3"), json!("4")]);
+        assert_eq!(page_info["hasPreviousPage"], true);
+        assert_eq!(page_info["hasNextPage"], false);
+        let (ids, page_info) = page(
+            &schema,
+            &format!("last: 2, before: {}", page_info["startCursor"]),
+        )
+        .await;
+        assert_eq!(ids, [json!("1"), json!("2")]);
+        assert_eq!(page_info["hasPreviousPage"], true);
+        assert_eq!(page_info["hasNextPage"], true);
+
+        // filter and order
+        let (ids, page_info) = page(
+            &schema,
+            r#"filter: { authorContains: "HERBERT" }, orderBy: NAME_DESC"#,
+        )
+        .await;
+        assert_eq!(ids, [json!("4"), json!("0"), json!("1")]);
+        assert_eq!(page_info["hasPreviousPage"], false);
+        assert_eq!(page_info["hasNextPage"], false);
+        let (ids, _) = page(
+            &schema,
+            &format!(
+                r#"filter: {{ nameContains: "dune" }}, orderBy: NAME_DESC, after: {}"#,
+                page_info["startCursor"]
+            ),
+        )
+        .await;
+        assert_eq!(ids, [json!("0"), json!("1")]);
+
+        // The cursor of a deleted book still points between its neighbours.
+        let (_, page_info) = page(&schema, "first: 3").await;
+        execute(&schema, r#"mutation { deleteBook(id: "2") }"#).await;
+        let (ids, page_info) = page(
+            &schema,
+            &format!("first: 2, after: {}", page_info["endCursor"]),
+        )
+        .await;
+        assert_eq!(ids, [json!("3"), json!("4")]);
+        assert_eq!(page_info["hasPreviousPage"], true);
+        assert_eq!(page_info["hasNextPage"], false);
+    }
+
+    #[tokio::test]
+    async fn books_connection() {
+        check_books_connection(crate::schema().unwrap()).await;
+        check_books_connection(
+            async_graphql::Schema::build(
+                books::QueryRoot,
+                books::MutationRoot,
+                books::SubscriptionRoot,
+            )
+            .data(books::Storage::memory())
+            .data(books::SharedBroker::memory(Default::default()))
+            .finish(),
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn update_book() {
+        let schema = crate::schema().unwrap();
+        let response = schema
+            .execute(r#"mutation { createBook(name: "a", author: "x") }"#)
+            .await
+            .into_result()
+            .unwrap();
+        let id = response.data.into_json().unwrap()["createBook"]
+            .as_str()
+            .unwrap()
+            .to_string();
+
+        let mut stream = schema.execute_stream(
+            "subscription { bookMutation(mutationType: UPDATED) { mutationType id previousBook { name author } newBook { name author } } }",
+        );
+        assert!(stream.next().now_or_never().is_none());
+
+        let response = schema
+            .execute(format!(
+                r#"mutation {{ updateBook(id: "{id}", author: "z") }}"#
+            ))
+            .await
+            .into_result()
+            .unwrap();
+        assert_eq!(response.data, value!({ "updateBook": true }));
+
+        let response = schema
+            .execute(format!(r#"{{ getBook(id: "{id}") {{ name author }} }}"#))
+            .await
+            .into_result()
+            .unwrap();
+        assert_eq!(
+            response.data,
+            value!({ "getBook": { "name": "a", "author": "z" } })
+        );
+
+        let event = stream.next().await.unwrap().into_result().unwrap();
+        assert_eq!(
+            event.data,
+            value!({
+                "bookMutation": {
+                    "mutationType": "UPDATED",
+                    "id": id,
+                    "previousBook": { "name": "a", "author": "x" },
+                    "newBook": { "name": "a", "author": "z" },
+                }
+            })
+        );
+
+        let response = schema
+            .execute(r#"mutation { updateBook(id: "1000", name: "b") }"#)
+            .await
+            .into_result()
+            .unwrap();
+        assert_eq!(response.data, value!({ "updateBook": false }));
+    }
 }
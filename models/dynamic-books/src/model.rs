@@ -1,12 +1,19 @@
-use async_graphql::{ID, Value, dynamic::*};
+use async_graphql::{
+    Error, ID, Name, Result, Value,
+    connection::{CursorType, OpaqueCursor, query_with},
+    dynamic::*,
+    indexmap::IndexMap,
+};
 use futures_util::StreamExt;
 
 use crate::{Book, BookChanged, MutationType, Storage, simple_broker::SimpleBroker};
+use books::{BookCursor, BookFilter, BookOrder, page_books};
 
 impl From<MutationType> for FieldValue<'_> {
     fn from(value: MutationType) -> Self {
         match value {
             MutationType::Created => FieldValue::value("CREATED"),
+            MutationType::Updated => FieldValue::value("UPDATED"),
             MutationType::Deleted => FieldValue::value("DELETED"),
         }
     }
@@ -15,6 +22,7 @@ impl From<MutationType> for FieldValue<'_> {
 pub fn schema() -> Result<Schema, SchemaError> {
     let mutation_type = Enum::new("MutationType")
         .item(EnumItem::new("CREATED").description("New book created."))
+        .item(EnumItem::new("UPDATED").description("Current book updated."))
         .item(EnumItem::new("DELETED").description("Current book deleted."));
 
     let book = Object::new("Book")
@@ -75,7 +83,165 @@ pub fn schema() -> Result<Schema, SchemaError> {
                     Ok(book.map(FieldValue::owned_any))
                 })
             },
-        ));
+        ))
+        .field(
+            Field::new("previousBook", TypeRef::named(book.type_name()), |ctx| {
+                FieldFuture::new(async move {
+                    let book_changed = ctx.parent_value.try_downcast_ref::<BookChanged>()?;
+                    Ok(book_changed
+                        .previous_book
+                        .as_ref()
+                        .map(|book| FieldValue::borrowed_any(book)))
+                })
+            })
+            .description("The book before the mutation, `null` if it was created."),
+        )
+        .field(
+            Field::new("newBook", TypeRef::named(book.type_name()), |ctx| {
+                FieldFuture::new(async move {
+                    let book_changed = ctx.parent_value.try_downcast_ref::<BookChanged>()?;
+                    Ok(book_changed
+                        .new_book
+                        .as_ref()
+                        .map(|book| FieldValue::borrowed_any(book)))
+                })
+            })
+            .description("The book after the mutation, `null` if it was deleted."),
+        );
+
+    let book_filter = InputObject::new("BookFilter")
+        .description("Conditions that the returned books must match.")
+        .field(
+            InputValue::new("authorContains", TypeRef::named(TypeRef::STRING))
+                .description("Only books whose author contains this text, ignoring case."),
+        )
+        .field(
+            InputValue::new("nameContains", TypeRef::named(TypeRef::STRING))
+                .description("Only books whose name contains this text, ignoring case."),
+        );
+    let book_order = Enum::new("BookOrder")
+        .description("The order of the returned books.")
+        .item(EnumItem::new("ID_ASC"))
+        .item(EnumItem::new("ID_DESC"))
+        .item(EnumItem::new("NAME_ASC"))
+        .item(EnumItem::new("NAME_DESC"))
+        .item(EnumItem::new("AUTHOR_ASC"))
+        .item(EnumItem::new("AUTHOR_DESC"));
+
+    let page_info = Object::new("PageInfo")
+        .description("Information about pagination in a connection")
+        .field(
+            Field::new(
+                "hasPreviousPage",
+                TypeRef::named_nn(TypeRef::BOOLEAN),
+                |ctx| {
+                    FieldFuture::new(async move {
+                        let page = ctx.parent_value.try_downcast_ref::<Page>()?;
+                        Ok(Some(Value::from(page.has_previous_page)))
+                    })
+                },
+            )
+            .description("When paginating backwards, are there more items?"),
+        )
+        .field(
+            Field::new("hasNextPage", TypeRef::named_nn(TypeRef::BOOLEAN), |ctx| {
+                FieldFuture::new(async move {
+                    let page = ctx.parent_value.try_downcast_ref::<Page>()?;
+                    Ok(Some(Value::from(page.has_next_page)))
+                })
+            })
+            .description("When paginating forwards, are there more items?"),
+        )
+        .field(
+            Field::new("startCursor", TypeRef::named(TypeRef::STRING), |ctx| {
+                FieldFuture::new(async move {
+                    let page = ctx.parent_value.try_downcast_ref::<Page>()?;
+                    Ok(page
+                        .edges
+                        .first()
+                        .map(|edge| Value::from(edge.cursor.clone())))
+                })
+            })
+            .description("When paginating backwards, the cursor to continue."),
+        )
+        .field(
+            Field::new("endCursor", TypeRef::named(TypeRef::STRING), |ctx| {
+                FieldFuture::new(async move {
+                    let page = ctx.parent_value.try_downcast_ref::<Page>()?;
+                    Ok(page
+                        .edges
+                        .last()
+                        .map(|edge| Value::from(edge.cursor.clone())))
+                })
+            })
+            .description("When paginating forwards, the cursor to continue."),
+        );
+    let book_edge = Object::new("BookEdge")
+        .description("An edge in a connection.")
+        .field(
+            Field::new("node", TypeRef::named_nn(book.type_name()), |ctx| {
+                FieldFuture::new(async move {
+                    let edge = ctx.parent_value.try_downcast_ref::<Edge>()?;
+                    Ok(Some(FieldValue::borrowed_any(&edge.book)))
+                })
+            })
+            .description("The item at the end of the edge"),
+        )
+        .field(
+            Field::new("cursor", TypeRef::named_nn(TypeRef::STRING), |ctx| {
+                FieldFuture::new(async move {
+                    let edge = ctx.parent_value.try_downcast_ref::<Edge>()?;
+                    Ok(Some(Value::from(edge.cursor.clone())))
+                })
+            })
+            .description("A cursor for use in pagination"),
+        );
+    let book_connection = Object::new("BookConnection")
+        .field(
+            Field::new(
+                "pageInfo",
+                TypeRef::named_nn(page_info.type_name()),
+                |ctx| {
+                    FieldFuture::new(async move {
+                        let page = ctx.parent_value.try_downcast_ref::<Page>()?;
+                        Ok(Some(FieldValue::borrowed_any(page)))
+                    })
+                },
+            )
+            .description("Information to aid in pagination."),
+        )
+        .field(
+            Field::new(
+                "edges",
+                TypeRef::named_nn_list_nn(book_edge.type_name()),
+                |ctx| {
+                    FieldFuture::new(async move {
+                        let page = ctx.parent_value.try_downcast_ref::<Page>()?;
+                        Ok(Some(FieldValue::list(
+                            page.edges.iter().map(|edge| FieldValue::borrowed_any(edge)),
+                        )))
+                    })
+                },
+            )
+            .description("A list of edges."),
+        )
+        .field(
+            Field::new(
+                "nodes",
+                TypeRef::named_nn_list_nn(book.type_name()),
+                |ctx| {
+                    FieldFuture::new(async move {
+                        let page = ctx.parent_value.try_downcast_ref::<Page>()?;
+                        Ok(Some(FieldValue::list(
+                            page.edges
+                                .iter()
+                                .map(|edge| FieldValue::borrowed_any(&edge.book)),
+                        )))
+                    })
+                },
+            )
+            .description("A list of nodes."),
+        );
 
     let query_root = Object::new("Query")
         .field(Field::new(
@@ -106,11 +272,42 @@ pub fn schema() -> Result<Schema, SchemaError> {
                 })
             })
             .argument(InputValue::new("id", TypeRef::named_nn(TypeRef::ID))),
+        )
+        .field(
+            Field::new(
+                "booksConnection",
+                TypeRef::named_nn(book_connection.type_name()),
+                |ctx| {
+                    FieldFuture::new(async move {
+                        let books: Vec<Book> = {
+                            let store = ctx.data_unchecked::<Storage>().lock().await;
+                            store.iter().map(|(_, book)| book.clone()).collect()
+                        };
+                        let page = Page::new(&ctx.args, books).await?;
+                        Ok(Some(FieldValue::owned_any(page)))
+                    })
+                },
+            )
+            .argument(InputValue::new("after", TypeRef::named(TypeRef::STRING)))
+            .argument(InputValue::new("before", TypeRef::named(TypeRef::STRING)))
+            .argument(InputValue::new("first", TypeRef::named(TypeRef::INT)))
+            .argument(InputValue::new("last", TypeRef::named(TypeRef::INT)))
+            .argument(
+                InputValue::new("filter", TypeRef::named_nn(book_filter.type_name()))
+                    .default_value(Value::Object(IndexMap::from([
+                        (Name::new("authorContains"), Value::Null),
+                        (Name::new("nameContains"), Value::Null),
+                    ]))),
+            )
+            .argument(
+                InputValue::new("orderBy", TypeRef::named_nn(book_order.type_name()))
+                    .default_value(Value::Enum(Name::new("ID_ASC"))),
+            ),
         );
 
     let mutatation_root = Object::new("Mutation")
         .field(
-            Field::new("createBook", TypeRef::named(TypeRef::ID), |ctx| {
+            Field::new("createBook", TypeRef::named_nn(TypeRef::ID), |ctx| {
                 FieldFuture::new(async move {
                     let mut store = ctx.data_unchecked::<Storage>().lock().await;
                     let name = ctx.args.try_get("name")?;
@@ -122,10 +319,12 @@ pub fn schema() -> Result<Schema, SchemaError> {
                         name: name.string()?.to_string(),
                         author: author.string()?.to_string(),
                     };
-                    entry.insert(book);
+                    entry.insert(book.clone());
                     let book_mutated = BookChanged {
                         mutation_type: MutationType::Created,
                         id: id.clone(),
+                        previous_book: None,
+                        new_book: Some(book),
                     };
                     SimpleBroker::publish(book_mutated);
                     Ok(Some(Value::from(id)))
@@ -137,6 +336,53 @@ pub fn schema() -> Result<Schema, SchemaError> {
                 TypeRef::named_nn(TypeRef::STRING),
             )),
         )
+        .field(
+            Field::new("updateBook", TypeRef::named_nn(TypeRef::BOOLEAN), |ctx| {
+                FieldFuture::new(async move {
+                    let mut store = ctx.data_unchecked::<Storage>().lock().await;
+                    let id = ctx.args.try_get("id")?;
+                    let book_id = match id.string() {
+                        Ok(id) => id.to_string(),
+                        Err(_) => id.u64()?.to_string(),
+                    };
+                    let book_id = book_id.parse::<usize>()?;
+                    let name = ctx
+                        .args
+                        .get("name")
+                        .filter(|name| !name.is_null())
+                        .map(|name| name.string())
+                        .transpose()?;
+                    let author = ctx
+                        .args
+                        .get("author")
+                        .filter(|author| !author.is_null())
+                        .map(|author| author.string())
+                        .transpose()?;
+                    if let Some(book) = store.get_mut(book_id) {
+                        let previous_book = book.clone();
+                        if let Some(name) = name {
+                            book.name = name.to_string();
+                        }
+                        if let Some(author) = author {
+                            book.author = author.to_string();
+                        }
+                        let book_mutated = BookChanged {
+                            mutation_type: MutationType::Updated,
+                            id: book_id.into(),
+                            previous_book: Some(previous_book),
+                            new_book: Some(book.clone()),
+                        };
+                        SimpleBroker::publish(book_mutated);
+                        Ok(Some(Value::from(true)))
+                    } else {
+                        Ok(Some(Value::from(false)))
+                    }
+                })
+            })
+            .argument(InputValue::new("id", TypeRef::named_nn(TypeRef::ID)))
+            .argument(InputValue::new("name", TypeRef::named(TypeRef::STRING)))
+            .argument(InputValue::new("author", TypeRef::named(TypeRef::STRING))),
+        )
         .field(
             Field::new("deleteBook", TypeRef::named_nn(TypeRef::BOOLEAN), |ctx| {
                 FieldFuture::new(async move {
@@ -147,11 +393,12 @@ pub fn schema() -> Result<Schema, SchemaError> {
                         Err(_) => id.u64()?.to_string(),
                     };
                     let book_id = book_id.parse::<usize>()?;
-                    if store.contains(book_id) {
-                        store.remove(book_id);
+                    if let Some(book) = store.try_remove(book_id) {
                         let book_mutated = BookChanged {
                             mutation_type: MutationType::Deleted,
                             id: book_id.into(),
+                            previous_book: Some(book),
+                            new_book: None,
                         };
                         SimpleBroker::publish(book_mutated);
                         Ok(Some(Value::from(true)))
@@ -162,16 +409,36 @@ pub fn schema() -> Result<Schema, SchemaError> {
             })
             .argument(InputValue::new("id", TypeRef::named_nn(TypeRef::ID))),
         );
-    let subscription_root = Subscription::new("Subscription").field(SubscriptionField::new(
-        "bookMutation",
-        TypeRef::named_nn(book_changed.type_name()),
-        |_| {
-            SubscriptionFieldFuture::new(async {
-                Ok(SimpleBroker::<BookChanged>::subscribe()
-                    .map(|book| Ok(FieldValue::owned_any(book))))
-            })
-        },
-    ));
+    let subscription_root = Subscription::new("Subscription").field(
+        SubscriptionField::new(
+            "bookMutation",
+            TypeRef::named_nn(book_changed.type_name()),
+            |ctx| {
+                SubscriptionFieldFuture::new(async move {
+                    let mutation_type = match ctx.args.get("mutationType") {
+                        Some(mutation_type) if !mutation_type.is_null() => {
+                            Some(mutation_type.enum_name()?.parse::<MutationType>()?)
+                        }
+                        _ => None,
+                    };
+                    Ok(SimpleBroker::<BookChanged>::subscribe()
+                        .filter(move |event| {
+                            let res = if let Some(mutation_type) = mutation_type {
+                                event.mutation_type == mutation_type
+                            } else {
+                                true
+                            };
+                            async move { res }
+                        })
+                        .map(|book| Ok(FieldValue::owned_any(book))))
+                })
+            },
+        )
+        .argument(InputValue::new(
+            "mutationType",
+            TypeRef::named(mutation_type.type_name()),
+        )),
+    );
 
     Schema::build(
         query_root.type_name(),
@@ -181,9 +448,103 @@ pub fn schema() -> Result<Schema, SchemaError> {
     .register(mutation_type)
     .register(book)
     .register(book_changed)
+    .register(book_filter)
+    .register(book_order)
+    .register(page_info)
+    .register(book_edge)
+    .register(book_connection)
     .register(query_root)
     .register(subscription_root)
     .register(mutatation_root)
     .data(Storage::default())
     .finish()
 }
+
+/// A page of `booksConnection`.
+struct Page {
+    edges: Vec<Edge>,
+    has_previous_page: bool,
+    has_next_page: bool,
+}
+
+struct Edge {
+    cursor: String,
+    book: Book,
+}
+
+impl Page {
+    /// Filters, sorts and paginates `books` like the `booksConnection` of the
+    /// static schema.
+    async fn new(args: &ObjectAccessor<'_>, books: Vec<Book>) -> Result<Self> {
+        let arg = |name: &str| args.get(name).filter(|value| !value.is_null());
+        let string = |name: &str| -> Result<Option<String>> {
+            arg(name)
+                .map(|value| value.string().map(str::to_string))
+                .transpose()
+        };
+        let int = |name: &str| -> Result<Option<i32>> {
+            arg(name)
+                .map(|value| i32::try_from(value.i64()?).map_err(Error::new_with_source))
+                .transpose()
+        };
+        let filter = match arg("filter") {
+            Some(filter) => {
+                let filter = filter.object()?;
+                let contains = |name: &str| -> Result<Option<String>> {
+                    filter
+                        .get(name)
+                        .filter(|value| !value.is_null())
+                        .map(|value| value.string().map(str::to_string))
+                        .transpose()
+                };
+                BookFilter {
+                    author_contains: contains("authorContains")?,
+                    name_contains: contains("nameContains")?,
+                }
+            }
+            None => BookFilter::default(),
+        };
+        let order_by = match arg("orderBy") {
+            Some(order_by) => order_by.enum_name()?.parse::<BookOrder>()?,
+            None => BookOrder::default(),
+        };
+
+        query_with(
+            string("after")?,
+            string("before")?,
+            int("first")?,
+            int("last")?,
+            |after: Option<OpaqueCursor<BookCursor>>,
+             before: Option<OpaqueCursor<BookCursor>>,
+             first,
+             last| async move {
+                let books = books
+                    .into_iter()
+                    .filter(|book| filter.matches(&book.name, &book.author))
+                    .map(|book| Ok((order_by.cursor(&book.id, &book.name, &book.author)?, book)))
+                    .collect::<Result<Vec<_>>>()?;
+                let page = page_books(
+                    books,
+                    order_by,
+                    after.map(|after| after.0),
+                    before.map(|before| before.0),
+                    first,
+                    last,
+                );
+                Ok::<_, Error>(Page {
+                    edges: page
+                        .books
+                        .into_iter()
+                        .map(|(cursor, book)| Edge {
+                            cursor: OpaqueCursor(cursor).encode_cursor(),
+                            book,
+                        })
+                        .collect(),
+                    has_previous_page: page.has_previous_page,
+                    has_next_page: page.has_next_page,
+                })
+            },
+        )
+        .await
+    }
+}
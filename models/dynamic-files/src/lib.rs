@@ -1,51 +1,302 @@
 use async_graphql::{
-    Value,
-    dynamic::{Field, FieldFuture, FieldValue, InputValue, Object, Schema, SchemaError, TypeRef},
+    Error, Result, ResultExt, ScalarType, Value,
+    dynamic::{
+        Field, FieldFuture, FieldValue, InputObject, InputValue, Object, ResolverContext, Scalar,
+        Schema, SchemaError, Subscription, SubscriptionField, SubscriptionFieldFuture, TypeRef,
+        ValueAccessor,
+    },
 };
-use futures::lock::Mutex;
-use slab::Slab;
-
-pub type Storage = Mutex<Slab<FileInfo>>;
+pub use files::{
+    Content, Download, FilePage, FileStore, FileWriter, LocalFileStore, Long, MemoryFileStore,
+    ProgressStream, Storage, UPLOAD_ID_HEADER, UploadError, UploadFile, UploadFilter, UploadPolicy,
+    execute,
+};
+use files::{FileInfo, UploadProgress};
+use futures_util::StreamExt;
 
-#[derive(Clone)]
-pub struct FileInfo {
-    pub id: String,
-    url: String,
-}
+pub fn schema(storage: Storage, policy: UploadPolicy) -> Result<Schema, SchemaError> {
+    let long = Scalar::new("Long")
+        .description(
+            "A non-negative 64-bit integer, sent as a number. It may also be received as\na string of digits.",
+        )
+        .validator(|value| Long::parse(value.clone()).is_ok());
 
-pub fn schema() -> Result<Schema, SchemaError> {
     let file_info = Object::new("FileInfo")
         .field(Field::new("id", TypeRef::named_nn(TypeRef::ID), |ctx| {
             FieldFuture::new(async {
                 let file_info = ctx.parent_value.try_downcast_ref::<FileInfo>()?;
-                Ok(Some(Value::from(&file_info.id)))
+                Ok(Some(Value::from(file_info.id.as_str())))
             })
         }))
-        .field(Field::new(
-            "url",
-            TypeRef::named_nn(TypeRef::STRING),
-            |ctx| {
+        .field(
+            Field::new("filename", TypeRef::named_nn(TypeRef::STRING), |ctx| {
+                FieldFuture::new(async {
+                    let file_info = ctx.parent_value.try_downcast_ref::<FileInfo>()?;
+                    Ok(Some(Value::from(&file_info.filename)))
+                })
+            })
+            .description("The name of the file sent by the client, sanitized."),
+        )
+        .field(
+            Field::new("url", TypeRef::named_nn(TypeRef::STRING), |ctx| {
                 FieldFuture::new(async {
                     let file_info = ctx.parent_value.try_downcast_ref::<FileInfo>()?;
                     Ok(Some(Value::from(&file_info.url)))
                 })
-            },
-        ));
+            })
+            .description("The URL that the content of the file can be downloaded from."),
+        )
+        .field(
+            Field::new("size", TypeRef::named_nn(long.type_name()), |ctx| {
+                FieldFuture::new(async {
+                    let file_info = ctx.parent_value.try_downcast_ref::<FileInfo>()?;
+                    Ok(Some(file_info.size.to_value()))
+                })
+            })
+            .description("The size of the file in bytes."),
+        )
+        .field(
+            Field::new("mimeType", TypeRef::named_nn(TypeRef::STRING), |ctx| {
+                FieldFuture::new(async {
+                    let file_info = ctx.parent_value.try_downcast_ref::<FileInfo>()?;
+                    Ok(Some(Value::from(&file_info.mime_type)))
+                })
+            })
+            .description(
+                "The MIME type detected from the first bytes of the content,\n`application/octet-stream` if it was not recognized.",
+            ),
+        )
+        .field(
+            Field::new("sha256", TypeRef::named_nn(TypeRef::STRING), |ctx| {
+                FieldFuture::new(async {
+                    let file_info = ctx.parent_value.try_downcast_ref::<FileInfo>()?;
+                    Ok(Some(Value::from(&file_info.sha256)))
+                })
+            })
+            .description("The SHA-256 digest of the content, in lowercase hex."),
+        )
+        .field(
+            Field::new("tags", TypeRef::named_nn_list_nn(TypeRef::STRING), |ctx| {
+                FieldFuture::new(async {
+                    let file_info = ctx.parent_value.try_downcast_ref::<FileInfo>()?;
+                    Ok(Some(Value::List(
+                        file_info.tags.iter().map(Value::from).collect(),
+                    )))
+                })
+            })
+            .description("The tags of the file, set with `updateUploadMetadata`."),
+        );
+
+    let upload_progress = Object::new("UploadProgress")
+        .description("The progress of an upload.")
+        .field(
+            Field::new(
+                "bytesReceived",
+                TypeRef::named_nn(long.type_name()),
+                |ctx| {
+                    FieldFuture::new(async {
+                        let progress = ctx.parent_value.try_downcast_ref::<UploadProgress>()?;
+                        Ok(Some(progress.bytes_received.to_value()))
+                    })
+                },
+            )
+            .description("The number of bytes received so far."),
+        )
+        .field(
+            Field::new("totalBytes", TypeRef::named(long.type_name()), |ctx| {
+                FieldFuture::new(async {
+                    let progress = ctx.parent_value.try_downcast_ref::<UploadProgress>()?;
+                    Ok(progress
+                        .total_bytes
+                        .map(|total_bytes| total_bytes.to_value()))
+                })
+            })
+            .description("The number of bytes of the whole upload, if it is known."),
+        )
+        .field(
+            Field::new(
+                "files",
+                TypeRef::named_nn_list_nn(file_info.type_name()),
+                |ctx| {
+                    FieldFuture::new(async {
+                        let progress = ctx.parent_value.try_downcast_ref::<UploadProgress>()?;
+                        Ok(Some(FieldValue::list(
+                            progress
+                                .files
+                                .iter()
+                                .map(|file| FieldValue::borrowed_any(file)),
+                        )))
+                    })
+                },
+            )
+            .description(
+                "The stored files, only set in the last progress, once the upload is\ncomplete.",
+            ),
+        );
+
+    let upload_filter = InputObject::new("UploadFilter")
+        .description("Conditions that the returned files must match.")
+        .field(
+            InputValue::new("filenameContains", TypeRef::named(TypeRef::STRING))
+                .description("Only files whose filename contains this text, ignoring case."),
+        )
+        .field(
+            InputValue::new("mimeType", TypeRef::named(TypeRef::STRING)).description(
+                "Only files of this MIME type, or of all its subtypes if it ends with\n`/*`.",
+            ),
+        )
+        .field(
+            InputValue::new("tag", TypeRef::named(TypeRef::STRING))
+                .description("Only files with this tag."),
+        );
 
-    let query = Object::new("Query").field(Field::new(
-        "uploads",
-        TypeRef::named_nn_list_nn(file_info.type_name()),
-        |ctx| {
-            FieldFuture::new(async move {
-                let storage = ctx.data_unchecked::<Storage>().lock().await;
-                Ok(Some(FieldValue::list(
-                    storage
-                        .iter()
-                        .map(|(_, file)| FieldValue::owned_any(file.clone())),
-                )))
-            })
-        },
-    ));
+    let page_info = Object::new("PageInfo")
+        .description("Information about pagination in a connection")
+        .field(
+            Field::new(
+                "hasPreviousPage",
+                TypeRef::named_nn(TypeRef::BOOLEAN),
+                |ctx| {
+                    FieldFuture::new(async move {
+                        let page = ctx.parent_value.try_downcast_ref::<FilePage>()?;
+                        Ok(Some(Value::from(page.has_previous_page)))
+                    })
+                },
+            )
+            .description("When paginating backwards, are there more items?"),
+        )
+        .field(
+            Field::new("hasNextPage", TypeRef::named_nn(TypeRef::BOOLEAN), |ctx| {
+                FieldFuture::new(async move {
+                    let page = ctx.parent_value.try_downcast_ref::<FilePage>()?;
+                    Ok(Some(Value::from(page.has_next_page)))
+                })
+            })
+            .description("When paginating forwards, are there more items?"),
+        )
+        .field(
+            Field::new("startCursor", TypeRef::named(TypeRef::STRING), |ctx| {
+                FieldFuture::new(async move {
+                    let page = ctx.parent_value.try_downcast_ref::<FilePage>()?;
+                    Ok(page
+                        .files
+                        .first()
+                        .map(|(id, _)| Value::from(id.to_string())))
+                })
+            })
+            .description("When paginating backwards, the cursor to continue."),
+        )
+        .field(
+            Field::new("endCursor", TypeRef::named(TypeRef::STRING), |ctx| {
+                FieldFuture::new(async move {
+                    let page = ctx.parent_value.try_downcast_ref::<FilePage>()?;
+                    Ok(page.files.last().map(|(id, _)| Value::from(id.to_string())))
+                })
+            })
+            .description("When paginating forwards, the cursor to continue."),
+        );
+
+    let file_info_edge = Object::new("FileInfoEdge")
+        .description("An edge in a connection.")
+        .field(
+            Field::new("node", TypeRef::named_nn(file_info.type_name()), |ctx| {
+                FieldFuture::new(async move {
+                    let (_, file) = ctx.parent_value.try_downcast_ref::<(usize, FileInfo)>()?;
+                    Ok(Some(FieldValue::borrowed_any(file)))
+                })
+            })
+            .description("The item at the end of the edge"),
+        )
+        .field(
+            Field::new("cursor", TypeRef::named_nn(TypeRef::STRING), |ctx| {
+                FieldFuture::new(async move {
+                    let (id, _) = ctx.parent_value.try_downcast_ref::<(usize, FileInfo)>()?;
+                    Ok(Some(Value::from(id.to_string())))
+                })
+            })
+            .description("A cursor for use in pagination"),
+        );
+
+    let file_info_connection = Object::new("FileInfoConnection")
+        .field(
+            Field::new(
+                "pageInfo",
+                TypeRef::named_nn(page_info.type_name()),
+                |ctx| {
+                    FieldFuture::new(async move {
+                        let page = ctx.parent_value.try_downcast_ref::<FilePage>()?;
+                        Ok(Some(FieldValue::borrowed_any(page)))
+                    })
+                },
+            )
+            .description("Information to aid in pagination."),
+        )
+        .field(
+            Field::new(
+                "edges",
+                TypeRef::named_nn_list_nn(file_info_edge.type_name()),
+                |ctx| {
+                    FieldFuture::new(async move {
+                        let page = ctx.parent_value.try_downcast_ref::<FilePage>()?;
+                        Ok(Some(FieldValue::list(
+                            page.files.iter().map(|edge| FieldValue::borrowed_any(edge)),
+                        )))
+                    })
+                },
+            )
+            .description("A list of edges."),
+        )
+        .field(
+            Field::new(
+                "nodes",
+                TypeRef::named_nn_list_nn(file_info.type_name()),
+                |ctx| {
+                    FieldFuture::new(async move {
+                        let page = ctx.parent_value.try_downcast_ref::<FilePage>()?;
+                        Ok(Some(FieldValue::list(
+                            page.files
+                                .iter()
+                                .map(|(_, file)| FieldValue::borrowed_any(file)),
+                        )))
+                    })
+                },
+            )
+            .description("A list of nodes."),
+        );
+
+    let query = Object::new("Query")
+        .field(
+            Field::new("upload", TypeRef::named(file_info.type_name()), |ctx| {
+                FieldFuture::new(async move {
+                    let storage = ctx.data_unchecked::<Storage>();
+                    let id = ctx.args.try_get("id")?.string()?;
+                    Ok(storage.file(id).await.map(FieldValue::owned_any))
+                })
+            })
+            .description("The uploaded file with the specified id.")
+            .argument(InputValue::new("id", TypeRef::named_nn(TypeRef::ID))),
+        )
+        .field(
+            Field::new(
+                "uploads",
+                TypeRef::named_nn(file_info_connection.type_name()),
+                |ctx| {
+                    FieldFuture::new(async move {
+                        let storage = ctx.data_unchecked::<Storage>();
+                        let (filter, first, after) = uploads_args(&ctx)?;
+                        let page = storage.find(&filter, after, first).await;
+                        Ok(Some(FieldValue::owned_any(page)))
+                    })
+                },
+            )
+            .description("The uploaded files that match `filter`, by id.")
+            .argument(InputValue::new(
+                "filter",
+                TypeRef::named(upload_filter.type_name()),
+            ))
+            .argument(InputValue::new("first", TypeRef::named(TypeRef::INT)))
+            .argument(InputValue::new("after", TypeRef::named(TypeRef::STRING))),
+        );
 
     let mutation = Object::new("Mutation")
         .field(
@@ -54,20 +305,25 @@ pub fn schema() -> Result<Schema, SchemaError> {
                 TypeRef::named_nn(file_info.type_name()),
                 |ctx| {
                     FieldFuture::new(async move {
-                        let mut storage = ctx.data_unchecked::<Storage>().lock().await;
+                        let storage = ctx.data_unchecked::<Storage>();
+                        let policy = ctx.data_unchecked::<UploadPolicy>();
                         let file = ctx.args.try_get("file")?.upload()?;
-                        let entry = storage.vacant_entry();
-                        let upload = file.value(&ctx).unwrap();
-                        let info = FileInfo {
-                            id: entry.key().to_string(),
-                            url: upload.filename.clone(),
-                        };
-                        entry.insert(info.clone());
-                        Ok(Some(FieldValue::owned_any(info)))
+                        let upload = UploadFile::new(&ctx, &file)
+                            .map_err(UploadError::Invalid)
+                            .extend()?;
+                        let mut infos = storage
+                            .upload(vec![upload], policy, upload_id(&ctx)?)
+                            .await
+                            .extend()?;
+                        Ok(Some(FieldValue::owned_any(infos.remove(0))))
                     })
                 },
             )
-            .argument(InputValue::new("file", TypeRef::named_nn(TypeRef::UPLOAD))),
+            .description(
+                "Upload a file. If `uploadId` is specified, the progress of the upload\nis sent to the `uploadProgress` subscribers with that id.",
+            )
+            .argument(InputValue::new("file", TypeRef::named_nn(TypeRef::UPLOAD)))
+            .argument(InputValue::new("uploadId", TypeRef::named(TypeRef::ID))),
         )
         .field(
             Field::new(
@@ -75,34 +331,248 @@ pub fn schema() -> Result<Schema, SchemaError> {
                 TypeRef::named_nn_list_nn(file_info.type_name()),
                 |ctx| {
                     FieldFuture::new(async move {
-                        let mut infos = Vec::new();
-                        let mut storage = ctx.data_unchecked::<Storage>().lock().await;
+                        let storage = ctx.data_unchecked::<Storage>();
+                        let policy = ctx.data_unchecked::<UploadPolicy>();
+                        let mut uploads = Vec::new();
                         for item in ctx.args.try_get("files")?.list()?.iter() {
                             let file = item.upload()?;
-                            let entry = storage.vacant_entry();
-                            let upload = file.value(&ctx).unwrap();
-                            let info = FileInfo {
-                                id: entry.key().to_string(),
-                                url: upload.filename.clone(),
-                            };
-                            entry.insert(info.clone());
-                            infos.push(FieldValue::owned_any(info))
+                            uploads.push(
+                                UploadFile::new(&ctx, &file)
+                                    .map_err(UploadError::Invalid)
+                                    .extend()?,
+                            );
                         }
-                        Ok(Some(infos))
+                        let infos = storage
+                            .upload(uploads, policy, upload_id(&ctx)?)
+                            .await
+                            .extend()?;
+                        Ok(Some(FieldValue::list(
+                            infos.into_iter().map(FieldValue::owned_any),
+                        )))
                     })
                 },
             )
+            .description(
+                "Upload several files. If `uploadId` is specified, the progress of the\nupload is sent to the `uploadProgress` subscribers with that id.",
+            )
             .argument(InputValue::new(
                 "files",
                 TypeRef::named_nn_list_nn(TypeRef::UPLOAD),
+            ))
+            .argument(InputValue::new("uploadId", TypeRef::named(TypeRef::ID))),
+        )
+        .field(
+            Field::new("deleteUpload", TypeRef::named_nn(TypeRef::BOOLEAN), |ctx| {
+                FieldFuture::new(async move {
+                    let storage = ctx.data_unchecked::<Storage>();
+                    let id = ctx.args.try_get("id")?.string()?;
+                    let deleted = storage
+                        .delete(id)
+                        .await
+                        .map_err(UploadError::Store)
+                        .extend()?;
+                    Ok(Some(Value::from(deleted)))
+                })
+            })
+            .description("Delete an uploaded file, returns whether it existed.")
+            .argument(InputValue::new("id", TypeRef::named_nn(TypeRef::ID))),
+        )
+        .field(
+            Field::new(
+                "updateUploadMetadata",
+                TypeRef::named(file_info.type_name()),
+                |ctx| {
+                    FieldFuture::new(async move {
+                        let storage = ctx.data_unchecked::<Storage>();
+                        let id = ctx.args.try_get("id")?.string()?;
+                        let filename = match ctx.args.get("filename") {
+                            Some(filename) if !filename.is_null() => Some(filename.string()?),
+                            _ => None,
+                        };
+                        let tags = match ctx.args.get("tags") {
+                            Some(tags) if !tags.is_null() => Some(
+                                tags.list()?
+                                    .iter()
+                                    .map(|tag| tag.string().map(str::to_string))
+                                    .collect::<Result<Vec<_>>>()?,
+                            ),
+                            _ => None,
+                        };
+                        let info = storage.update_metadata(id, filename, tags).await;
+                        Ok(info.map(FieldValue::owned_any))
+                    })
+                },
+            )
+            .description(
+                "Rename an uploaded file and replace its tags, each if specified.\nReturns the updated file, or null if there is no such file.",
+            )
+            .argument(InputValue::new("id", TypeRef::named_nn(TypeRef::ID)))
+            .argument(InputValue::new("filename", TypeRef::named(TypeRef::STRING)))
+            .argument(InputValue::new(
+                "tags",
+                TypeRef::List(Box::new(TypeRef::named_nn(TypeRef::STRING))),
             )),
+        )
+        .field(
+            Field::new("beginUpload", TypeRef::named_nn(TypeRef::ID), |ctx| {
+                FieldFuture::new(async move {
+                    let storage = ctx.data_unchecked::<Storage>();
+                    let policy = ctx.data_unchecked::<UploadPolicy>();
+                    let filename = ctx.args.try_get("filename")?.string()?;
+                    let size = long_arg(ctx.args.try_get("size")?)?;
+                    let upload_id = storage
+                        .begin_upload(filename, size, policy)
+                        .await
+                        .extend()?;
+                    Ok(Some(Value::from(upload_id)))
+                })
+            })
+            .description(
+                "Start a chunked upload of a file of `size` bytes, returns the id that\nthe chunks are sent to.",
+            )
+            .argument(InputValue::new(
+                "filename",
+                TypeRef::named_nn(TypeRef::STRING),
+            ))
+            .argument(InputValue::new("size", TypeRef::named_nn(long.type_name()))),
+        )
+        .field(
+            Field::new("uploadChunk", TypeRef::named_nn(long.type_name()), |ctx| {
+                FieldFuture::new(async move {
+                    let storage = ctx.data_unchecked::<Storage>();
+                    let upload_id = ctx.args.try_get("uploadId")?.string()?;
+                    let offset = long_arg(ctx.args.try_get("offset")?)?;
+                    let chunk = ctx.args.try_get("chunk")?.upload()?;
+                    let chunk = UploadFile::new(&ctx, &chunk)
+                        .map_err(UploadError::Invalid)
+                        .extend()?;
+                    let received = storage
+                        .upload_chunk(upload_id, offset, chunk)
+                        .await
+                        .extend()?;
+                    Ok(Some(Long(received).to_value()))
+                })
+            })
+            .description(
+                "Append a chunk to a chunked upload, starting at `offset`, the number of\nbytes received so far. Returns the number of bytes received with the\nchunk.",
+            )
+            .argument(InputValue::new("uploadId", TypeRef::named_nn(TypeRef::ID)))
+            .argument(InputValue::new("offset", TypeRef::named_nn(long.type_name())))
+            .argument(InputValue::new("chunk", TypeRef::named_nn(TypeRef::UPLOAD))),
+        )
+        .field(
+            Field::new(
+                "completeUpload",
+                TypeRef::named_nn(file_info.type_name()),
+                |ctx| {
+                    FieldFuture::new(async move {
+                        let storage = ctx.data_unchecked::<Storage>();
+                        let policy = ctx.data_unchecked::<UploadPolicy>();
+                        let upload_id = ctx.args.try_get("uploadId")?.string()?;
+                        let sha256 = ctx.args.try_get("sha256")?.string()?;
+                        let info = storage
+                            .complete_upload(upload_id, sha256, policy)
+                            .await
+                            .extend()?;
+                        Ok(Some(FieldValue::owned_any(info)))
+                    })
+                },
+            )
+            .description(
+                "Complete a chunked upload once all its chunks were received, `sha256`\nis the SHA-256 digest of the whole content, in hex.",
+            )
+            .argument(InputValue::new("uploadId", TypeRef::named_nn(TypeRef::ID)))
+            .argument(InputValue::new("sha256", TypeRef::named_nn(TypeRef::STRING))),
         );
 
-    Schema::build(query.type_name(), Some(mutation.type_name()), None)
-        .enable_uploading()
-        .register(file_info)
-        .register(query)
-        .register(mutation)
-        .data(Storage::default())
-        .finish()
+    let subscription = Subscription::new("Subscription").field(
+        SubscriptionField::new(
+            "uploadProgress",
+            TypeRef::named_nn(upload_progress.type_name()),
+            |ctx| {
+                SubscriptionFieldFuture::new(async move {
+                    let storage = ctx.data_unchecked::<Storage>();
+                    let upload_id = ctx.args.try_get("uploadId")?.string()?;
+                    Ok(storage
+                        .upload_progress(upload_id)
+                        .await
+                        .map(|progress| progress.map(FieldValue::owned_any)))
+                })
+            },
+        )
+        .description(
+            "The progress of the upload `uploadId`, either a chunked upload or the\nid passed to `singleUpload` or `multipleUpload`, which may be\nsubscribed to before the upload is sent. The bytes of those are counted\nas the request is received if it has an `Upload-Id` header with the same\nid. It ends once the upload is complete, or with an error if it fails or\ndoesn't start within a minute.",
+        )
+        .argument(InputValue::new("uploadId", TypeRef::named_nn(TypeRef::ID))),
+    );
+
+    Schema::build(
+        query.type_name(),
+        Some(mutation.type_name()),
+        Some(subscription.type_name()),
+    )
+    .enable_uploading()
+    .register(long)
+    .register(file_info)
+    .register(upload_progress)
+    .register(upload_filter)
+    .register(page_info)
+    .register(file_info_edge)
+    .register(file_info_connection)
+    .register(query)
+    .register(mutation)
+    .register(subscription)
+    .data(storage)
+    .data(policy)
+    .finish()
+}
+
+/// The value of a `Long` argument, which the scalar has validated.
+fn long_arg(value: ValueAccessor<'_>) -> Result<u64> {
+    Long::parse(value.as_value().clone())
+        .map(|long| long.0)
+        .map_err(|err| Error::new(err.into_server_error(Default::default()).message))
+}
+
+/// The `uploadId` argument of `singleUpload` and `multipleUpload`.
+fn upload_id<'a>(ctx: &'a ResolverContext<'_>) -> Result<Option<&'a str>> {
+    match ctx.args.get("uploadId") {
+        Some(upload_id) if !upload_id.is_null() => Ok(Some(upload_id.string()?)),
+        _ => Ok(None),
+    }
+}
+
+/// The `filter`, `first` and `after` arguments of `uploads`, checked like in
+/// the static schema.
+fn uploads_args(ctx: &ResolverContext<'_>) -> Result<(UploadFilter, Option<usize>, Option<usize>)> {
+    let arg = |name: &str| ctx.args.get(name).filter(|value| !value.is_null());
+    let filter = match arg("filter") {
+        Some(filter) => {
+            let filter = filter.object()?;
+            let string = |name: &str| {
+                filter
+                    .get(name)
+                    .filter(|value| !value.is_null())
+                    .map(|value| value.string().map(str::to_string))
+                    .transpose()
+            };
+            UploadFilter {
+                filename_contains: string("filenameContains")?,
+                mime_type: string("mimeType")?,
+                tag: string("tag")?,
+            }
+        }
+        None => UploadFilter::default(),
+    };
+    let first = match arg("first") {
+        Some(first) => Some(
+            usize::try_from(first.i64()?)
+                .map_err(|_| Error::new("The \"first\" parameter must be a non-negative number"))?,
+        ),
+        None => None,
+    };
+    let after = arg("after")
+        .map(|after| after.string()?.parse().map_err(Error::new_with_source))
+        .transpose()?;
+    Ok((filter, first, after))
 }
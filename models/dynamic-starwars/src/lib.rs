@@ -1,169 +1,129 @@
 mod model;
 
-use std::collections::HashMap;
-
 pub use model::schema;
-use slab::Slab;
-
-/// One of the films in the Star Wars Trilogy
-#[derive(Copy, Clone, Eq, PartialEq)]
-pub enum Episode {
-    /// Released in 1977.
-    NewHope,
-
-    /// Released in 1980.
-    Empire,
-
-    /// Released in 1983.
-    Jedi,
-}
-
-pub struct StarWarsChar {
-    id: &'static str,
-    name: &'static str,
-    is_human: bool,
-    friends: Vec<usize>,
-    appears_in: Vec<Episode>,
-    home_planet: Option<&'static str>,
-    primary_function: Option<&'static str>,
-}
-
-pub struct StarWars {
-    luke: usize,
-    artoo: usize,
-    chars: Slab<StarWarsChar>,
-    chars_by_id: HashMap<&'static str, usize>,
-}
-
-impl StarWars {
-    #[allow(clippy::new_without_default)]
-    pub fn new() -> Self {
-        let mut chars = Slab::new();
-
-        let luke = chars.insert(StarWarsChar {
-            id: "1000",
-            name: "Luke Skywalker",
-            is_human: true,
-            friends: vec![],
-            appears_in: vec![],
-            home_planet: Some("Tatooine"),
-            primary_function: None,
-        });
-
-        let vader = chars.insert(StarWarsChar {
-            id: "1001",
-            name: "Anakin Skywalker",
-            is_human: true,
-            friends: vec![],
-            appears_in: vec![],
-            home_planet: Some("Tatooine"),
-            primary_function: None,
-        });
-
-        let han = chars.insert(StarWarsChar {
-            id: "1002",
-            name: "Han Solo",
-            is_human: true,
-            friends: vec![],
-            appears_in: vec![Episode::Empire, Episode::NewHope, Episode::Jedi],
-            home_planet: None,
-            primary_function: None,
-        });
-
-        let leia = chars.insert(StarWarsChar {
-            id: "1003",
-            name: "Leia Organa",
-            is_human: true,
-            friends: vec![],
-            appears_in: vec![Episode::Empire, Episode::NewHope, Episode::Jedi],
-            home_planet: Some("Alderaa"),
-            primary_function: None,
-        });
-
-        let tarkin = chars.insert(StarWarsChar {
-            id: "1004",
-            name: "Wilhuff Tarkin",
-            is_human: true,
-            friends: vec![],
-            appears_in: vec![Episode::Empire, Episode::NewHope, Episode::Jedi],
-            home_planet: None,
-            primary_function: None,
-        });
-
-        let threepio = chars.insert(StarWarsChar {
-            id: "2000",
-            name: "C-3PO",
-            is_human: false,
-            friends: vec![],
-            appears_in: vec![Episode::Empire, Episode::NewHope, Episode::Jedi],
-            home_planet: None,
-            primary_function: Some("Protocol"),
-        });
-
-        let artoo = chars.insert(StarWarsChar {
-            id: "2001",
-            name: "R2-D2",
-            is_human: false,
-            friends: vec![],
-            appears_in: vec![Episode::Empire, Episode::NewHope, Episode::Jedi],
-            home_planet: None,
-            primary_function: Some("Astromech"),
-        });
-
-        chars[luke].friends = vec![han, leia, threepio, artoo];
-        chars[vader].friends = vec![tarkin];
-        chars[han].friends = vec![luke, leia, artoo];
-        chars[leia].friends = vec![luke, han, threepio, artoo];
-        chars[tarkin].friends = vec![vader];
-        chars[threepio].friends = vec![luke, han, leia, artoo];
-        chars[artoo].friends = vec![luke, han, leia];
-
-        let chars_by_id = chars.iter().map(|(idx, ch)| (ch.id, idx)).collect();
-        Self {
-            luke,
-            artoo,
-            chars,
-            chars_by_id,
-        }
-    }
-
-    pub fn human(&self, id: &str) -> Option<&StarWarsChar> {
-        self.chars_by_id
-            .get(id)
-            .copied()
-            .map(|idx| self.chars.get(idx).unwrap())
-            .filter(|ch| ch.is_human)
-    }
-
-    pub fn droid(&self, id: &str) -> Option<&StarWarsChar> {
-        self.chars_by_id
-            .get(id)
-            .copied()
-            .map(|idx| self.chars.get(idx).unwrap())
-            .filter(|ch| !ch.is_human)
-    }
-
-    pub fn humans(&self) -> Vec<&StarWarsChar> {
-        self.chars
-            .iter()
-            .filter(|(_, ch)| ch.is_human)
-            .map(|(_, ch)| ch)
+pub use starwars::{
+    Episode, FixtureError, FixtureFormat, Review, StarWars, StarWarsChar, StarWarsNode,
+    StarWarsStarship,
+};
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use async_graphql::{Response, Value};
+
+    /// The definitions of `sdl`, with the root types named like in the
+    /// dynamic schema.
+    fn definitions(sdl: &str) -> BTreeSet<String> {
+        sdl.replace("QueryRoot", "Query")
+            .replace("MutationRoot", "Mutation")
+            .replace("SubscriptionRoot", "Subscription")
+            .split("\n\n")
+            .map(|definition| definition.trim().to_string())
             .collect()
     }
 
-    pub fn droids(&self) -> Vec<&StarWarsChar> {
-        self.chars
-            .iter()
-            .filter(|(_, ch)| !ch.is_human)
-            .map(|(_, ch)| ch)
-            .collect()
+    fn summary(response: Response) -> (Value, Vec<String>) {
+        let messages = response.errors.into_iter().map(|err| err.message);
+        (response.data, messages.collect())
     }
 
-    pub fn friends(&self, ch: &StarWarsChar) -> Vec<&StarWarsChar> {
-        ch.friends
-            .iter()
-            .copied()
-            .filter_map(|id| self.chars.get(id))
-            .collect()
+    #[tokio::test]
+    async fn matches_the_static_schema() {
+        let static_schema = async_graphql::Schema::build(
+            starwars::QueryRoot,
+            starwars::MutationRoot,
+            starwars::SubscriptionRoot,
+        )
+        .data(starwars::StarWars::new())
+        .finish();
+        let dynamic_schema = super::schema().unwrap();
+        assert_eq!(
+            definitions(&static_schema.sdl()),
+            definitions(&dynamic_schema.sdl())
+        );
+
+        for field in ["humans", "droids"] {
+            for args in [
+                "",
+                "first: 2",
+                "last: 2",
+                "first: 3, last: 1",
+                "after: \"0\"",
+                "after: \"0\", first: 1",
+                "before: \"2\"",
+                "before: \"2\", last: 1",
+                "after: \"0\", before: \"3\"",
+                "after: \"3\", before: \"0\"",
+                "after: \"100\"",
+                "before: \"x\"",
+                "first: -1",
+                "last: 0",
+            ] {
+                let args = if args.is_empty() {
+                    String::new()
+                } else {
+                    format!("({args})")
+                };
+                let query = format!(
+                    "{{ {field}{args} {{ pageInfo {{ hasPreviousPage hasNextPage startCursor endCursor }} edges {{ cursor node {{ id name }} }} nodes {{ id }} }} }}"
+                );
+                assert_eq!(
+                    summary(static_schema.execute(query.as_str()).await),
+                    summary(dynamic_schema.execute(query.as_str()).await),
+                    "{query}"
+                );
+            }
+        }
+
+        // The dynamic schema copies the validators of `ReviewInput`.
+        let bound = |ty: &str, message: &str| {
+            format!(r#"Failed to parse "{ty}": {message} (occurred while parsing "ReviewInput")"#)
+        };
+        for (review, messages) in [
+            (
+                "{ stars: 0 }".to_string(),
+                vec![bound(
+                    "Int",
+                    "the value is 0, must be greater than or equal to 1",
+                )],
+            ),
+            (
+                "{ stars: 6 }".to_string(),
+                vec![bound(
+                    "Int",
+                    "the value is 6, must be less than or equal to 5",
+                )],
+            ),
+            (
+                r#"{ stars: 1, commentary: "" }"#.to_string(),
+                vec![bound(
+                    "String",
+                    "the string length is 0, must be greater than or equal to 1",
+                )],
+            ),
+            (
+                format!(r#"{{ stars: 1, commentary: "{}" }}"#, "é".repeat(251)),
+                vec![bound(
+                    "String",
+                    "the string length is 502, must be less than or equal to 500",
+                )],
+            ),
+            (
+                format!(r#"{{ stars: 5, commentary: "{}" }}"#, "x".repeat(500)),
+                vec![],
+            ),
+        ] {
+            let query =
+                format!("mutation {{ createReview(episode: JEDI, review: {review}) {{ stars }} }}");
+            let (static_data, static_messages) =
+                summary(static_schema.execute(query.as_str()).await);
+            assert_eq!(static_messages, messages, "{query}");
+            assert_eq!(
+                (static_data, static_messages),
+                summary(dynamic_schema.execute(query.as_str()).await),
+                "{query}"
+            );
+        }
     }
 }
@@ -1,28 +1,45 @@
-use async_graphql::{Value, dynamic::*};
+use async_graphql::{Error, Name, Result, Value, dynamic::*};
 
-use crate::{Episode, StarWars, StarWarsChar};
+use futures_util::{StreamExt, future};
+use starwars::offset_connection;
 
-impl From<Episode> for FieldValue<'_> {
-    fn from(value: Episode) -> Self {
-        match value {
-            Episode::NewHope => FieldValue::value("NEW_HOPE"),
-            Episode::Empire => FieldValue::value("EMPIRE"),
-            Episode::Jedi => FieldValue::value("JEDI"),
-        }
+use crate::{Episode, Review, StarWars, StarWarsChar, StarWarsNode, StarWarsStarship};
+
+fn episode_value(episode: Episode) -> FieldValue<'static> {
+    match episode {
+        Episode::NewHope => FieldValue::value("NEW_HOPE"),
+        Episode::Empire => FieldValue::value("EMPIRE"),
+        Episode::Jedi => FieldValue::value("JEDI"),
+    }
+}
+
+fn parse_episode(value: ValueAccessor<'_>) -> Result<Episode> {
+    match value.enum_name()? {
+        "NEW_HOPE" => Ok(Episode::NewHope),
+        "EMPIRE" => Ok(Episode::Empire),
+        "JEDI" => Ok(Episode::Jedi),
+        name => Err(Error::new(format!("invalid episode \"{name}\""))),
     }
 }
 
 pub fn schema() -> Result<Schema, SchemaError> {
     let episode = Enum::new("Episode")
+        .description("One of the films in the Star Wars Trilogy")
         .item(EnumItem::new("NEW_HOPE").description("Released in 1977."))
         .item(EnumItem::new("EMPIRE").description("Released in 1980."))
         .item(EnumItem::new("JEDI").description("Released in 1983."));
 
+    let length_unit = Enum::new("LengthUnit")
+        .description("The unit of a length.")
+        .item(EnumItem::new("METER").description("The standard unit around the world."))
+        .item(EnumItem::new("FOOT").description("Primarily used in the United States."));
+
+    let node = Interface::new("Node")
+        .description("An object with an id that is unique among all objects.")
+        .field(InterfaceField::new("id", TypeRef::named_nn(TypeRef::ID)));
+
     let character = Interface::new("Character")
-        .field(InterfaceField::new(
-            "id",
-            TypeRef::named_nn(TypeRef::STRING),
-        ))
+        .field(InterfaceField::new("id", TypeRef::named_nn(TypeRef::ID)))
         .field(InterfaceField::new(
             "name",
             TypeRef::named_nn(TypeRef::STRING),
@@ -39,11 +56,12 @@ pub fn schema() -> Result<Schema, SchemaError> {
     let human = Object::new("Human")
         .description("A humanoid creature in the Star Wars universe.")
         .implement(character.type_name())
+        .implement(node.type_name())
         .field(
-            Field::new("id", TypeRef::named_nn(TypeRef::STRING), |ctx| {
+            Field::new("id", TypeRef::named_nn(TypeRef::ID), |ctx| {
                 FieldFuture::new(async move {
                     let char = ctx.parent_value.try_downcast_ref::<StarWarsChar>()?;
-                    Ok(Some(Value::from(char.id)))
+                    Ok(Some(Value::from(char.global_id())))
                 })
             })
             .description("The id of the human."),
@@ -52,7 +70,7 @@ pub fn schema() -> Result<Schema, SchemaError> {
             Field::new("name", TypeRef::named_nn(TypeRef::STRING), |ctx| {
                 FieldFuture::new(async move {
                     let char = ctx.parent_value.try_downcast_ref::<StarWarsChar>()?;
-                    Ok(Some(Value::from(char.name)))
+                    Ok(Some(Value::from(char.name())))
                 })
             })
             .description("The name of the human."),
@@ -65,9 +83,9 @@ pub fn schema() -> Result<Schema, SchemaError> {
                     FieldFuture::new(async move {
                         let char = ctx.parent_value.try_downcast_ref::<StarWarsChar>()?;
                         let starwars = ctx.data::<StarWars>()?;
-                        let friends = starwars.friends(char);
+                        let friends = starwars.friends(char).await;
                         Ok(Some(FieldValue::list(friends.into_iter().map(|friend| {
-                            FieldValue::borrowed_any(friend).with_type(if friend.is_human {
+                            FieldValue::borrowed_any(friend).with_type(if friend.is_human() {
                                 "Human"
                             } else {
                                 "Droid"
@@ -86,7 +104,7 @@ pub fn schema() -> Result<Schema, SchemaError> {
                     FieldFuture::new(async move {
                         let char = ctx.parent_value.try_downcast_ref::<StarWarsChar>()?;
                         Ok(Some(FieldValue::list(
-                            char.appears_in.iter().copied().map(FieldValue::from),
+                            char.appears_in().iter().copied().map(episode_value),
                         )))
                     })
                 },
@@ -97,7 +115,7 @@ pub fn schema() -> Result<Schema, SchemaError> {
             Field::new("homePlanet", TypeRef::named(TypeRef::STRING), |ctx| {
                 FieldFuture::new(async move {
                     let char = ctx.parent_value.try_downcast_ref::<StarWarsChar>()?;
-                    Ok(char.home_planet.map(Value::from))
+                    Ok(char.home_planet().map(Value::from))
                 })
             })
             .description("The home planet of the human, or null if unknown."),
@@ -106,11 +124,12 @@ pub fn schema() -> Result<Schema, SchemaError> {
     let droid = Object::new("Droid")
         .description("A mechanical creature in the Star Wars universe.")
         .implement(character.type_name())
+        .implement(node.type_name())
         .field(
-            Field::new("id", TypeRef::named_nn(TypeRef::STRING), |ctx| {
+            Field::new("id", TypeRef::named_nn(TypeRef::ID), |ctx| {
                 FieldFuture::new(async move {
                     let char = ctx.parent_value.try_downcast_ref::<StarWarsChar>()?;
-                    Ok(Some(Value::from(char.id)))
+                    Ok(Some(Value::from(char.global_id())))
                 })
             })
             .description("The id of the droid."),
@@ -119,7 +138,7 @@ pub fn schema() -> Result<Schema, SchemaError> {
             Field::new("name", TypeRef::named_nn(TypeRef::STRING), |ctx| {
                 FieldFuture::new(async move {
                     let char = ctx.parent_value.try_downcast_ref::<StarWarsChar>()?;
-                    Ok(Some(Value::from(char.name)))
+                    Ok(Some(Value::from(char.name())))
                 })
             })
             .description("The name of the droid."),
@@ -132,9 +151,9 @@ pub fn schema() -> Result<Schema, SchemaError> {
                     FieldFuture::new(async move {
                         let char = ctx.parent_value.try_downcast_ref::<StarWarsChar>()?;
                         let starwars = ctx.data::<StarWars>()?;
-                        let friends = starwars.friends(char);
+                        let friends = starwars.friends(char).await;
                         Ok(Some(FieldValue::list(friends.into_iter().map(|friend| {
-                            FieldValue::borrowed_any(friend).with_type(if friend.is_human {
+                            FieldValue::borrowed_any(friend).with_type(if friend.is_human() {
                                 "Human"
                             } else {
                                 "Droid"
@@ -153,7 +172,7 @@ pub fn schema() -> Result<Schema, SchemaError> {
                     FieldFuture::new(async move {
                         let char = ctx.parent_value.try_downcast_ref::<StarWarsChar>()?;
                         Ok(Some(FieldValue::list(
-                            char.appears_in.iter().copied().map(FieldValue::from),
+                            char.appears_in().iter().copied().map(episode_value),
                         )))
                     })
                 },
@@ -164,50 +183,163 @@ pub fn schema() -> Result<Schema, SchemaError> {
             Field::new("primaryFunction", TypeRef::named(TypeRef::STRING), |ctx| {
                 FieldFuture::new(async move {
                     let char = ctx.parent_value.try_downcast_ref::<StarWarsChar>()?;
-                    Ok(char.primary_function.map(Value::from))
+                    Ok(char.primary_function().map(Value::from))
                 })
             })
             .description("The primary function of the droid."),
         );
 
+    let starship = Object::new("Starship")
+        .description("A ship in the Star Wars universe.")
+        .implement(node.type_name())
+        .field(
+            Field::new("id", TypeRef::named_nn(TypeRef::ID), |ctx| {
+                FieldFuture::new(async move {
+                    let starship = ctx.parent_value.try_downcast_ref::<StarWarsStarship>()?;
+                    Ok(Some(Value::from(starship.global_id())))
+                })
+            })
+            .description("The id of the starship."),
+        )
+        .field(
+            Field::new("name", TypeRef::named_nn(TypeRef::STRING), |ctx| {
+                FieldFuture::new(async move {
+                    let starship = ctx.parent_value.try_downcast_ref::<StarWarsStarship>()?;
+                    Ok(Some(Value::from(starship.name())))
+                })
+            })
+            .description("The name of the starship."),
+        )
+        .field(
+            Field::new("length", TypeRef::named_nn(TypeRef::FLOAT), |ctx| {
+                FieldFuture::new(async move {
+                    let starship = ctx.parent_value.try_downcast_ref::<StarWarsStarship>()?;
+                    let length = match ctx.args.get("unit") {
+                        Some(unit) if unit.enum_name()? == "FOOT" => starship.length() * 3.28084,
+                        _ => starship.length(),
+                    };
+                    Ok(Some(Value::from(length)))
+                })
+            })
+            .argument(
+                InputValue::new("unit", TypeRef::named_nn(length_unit.type_name()))
+                    .default_value(Value::Enum(Name::new("METER"))),
+            )
+            .description("The length of the starship, in meters unless another unit is given."),
+        );
+
+    let page_info = Object::new("PageInfo")
+        .description("Information about pagination in a connection")
+        .field(
+            Field::new(
+                "hasPreviousPage",
+                TypeRef::named_nn(TypeRef::BOOLEAN),
+                |ctx| {
+                    FieldFuture::new(async move {
+                        let page = ctx.parent_value.try_downcast_ref::<Page>()?;
+                        Ok(Some(Value::from(page.has_previous_page)))
+                    })
+                },
+            )
+            .description("When paginating backwards, are there more items?"),
+        )
+        .field(
+            Field::new("hasNextPage", TypeRef::named_nn(TypeRef::BOOLEAN), |ctx| {
+                FieldFuture::new(async move {
+                    let page = ctx.parent_value.try_downcast_ref::<Page>()?;
+                    Ok(Some(Value::from(page.has_next_page)))
+                })
+            })
+            .description("When paginating forwards, are there more items?"),
+        )
+        .field(
+            Field::new("startCursor", TypeRef::named(TypeRef::STRING), |ctx| {
+                FieldFuture::new(async move {
+                    let page = ctx.parent_value.try_downcast_ref::<Page>()?;
+                    Ok(page
+                        .edges
+                        .first()
+                        .map(|edge| Value::from(edge.cursor.to_string())))
+                })
+            })
+            .description("When paginating backwards, the cursor to continue."),
+        )
+        .field(
+            Field::new("endCursor", TypeRef::named(TypeRef::STRING), |ctx| {
+                FieldFuture::new(async move {
+                    let page = ctx.parent_value.try_downcast_ref::<Page>()?;
+                    Ok(page
+                        .edges
+                        .last()
+                        .map(|edge| Value::from(edge.cursor.to_string())))
+                })
+            })
+            .description("When paginating forwards, the cursor to continue."),
+        );
+
+    let (human_connection, human_edge) = connection_types(human.type_name());
+    let (droid_connection, droid_edge) = connection_types(droid.type_name());
+
+    let review = Object::new("Review")
+        .description("Represents a review for a movie.")
+        .field(
+            Field::new("episode", TypeRef::named_nn(episode.type_name()), |ctx| {
+                FieldFuture::new(async move {
+                    let review = ctx.parent_value.try_downcast_ref::<Review>()?;
+                    Ok(Some(episode_value(review.episode)))
+                })
+            })
+            .description("The movie."),
+        )
+        .field(
+            Field::new("stars", TypeRef::named_nn(TypeRef::INT), |ctx| {
+                FieldFuture::new(async move {
+                    let review = ctx.parent_value.try_downcast_ref::<Review>()?;
+                    Ok(Some(Value::from(review.stars)))
+                })
+            })
+            .description("The number of stars this review gave, 1-5."),
+        )
+        .field(
+            Field::new("commentary", TypeRef::named(TypeRef::STRING), |ctx| {
+                FieldFuture::new(async move {
+                    let review = ctx.parent_value.try_downcast_ref::<Review>()?;
+                    Ok(review.commentary.clone().map(Value::from))
+                })
+            })
+            .description("Comment about the movie."),
+        );
+
+    let review_input = InputObject::new("ReviewInput")
+        .description("The input object sent when someone is creating a new review.")
+        .field(InputValue::new("stars", TypeRef::named_nn(TypeRef::INT)).description("1-5 stars."))
+        .field(
+            InputValue::new("commentary", TypeRef::named(TypeRef::STRING))
+                .description("Comment about the movie, optional."),
+        );
+
+    let search_result = Union::new("SearchResult")
+        .possible_type(human.type_name())
+        .possible_type(droid.type_name())
+        .possible_type(starship.type_name());
+
     let query = Object::new("Query")
         .field(
             Field::new("hero", TypeRef::named_nn(character.type_name()), |ctx| {
                 FieldFuture::new(async move {
                     let starwars = ctx.data::<StarWars>()?;
-                    let episode = match ctx.args.get("episode") {
-                        Some(episode) => Some(match episode.enum_name()? {
-                            "NEW_HOPE" => Episode::NewHope,
-                            "EMPIRE" => Episode::Empire,
-                            "JEDI" => Episode::Jedi,
-                            _ => unreachable!(),
-                        }),
-                        None => None,
-                    };
-                    let value = match episode {
-                        Some(episode) => {
-                            if episode == Episode::Empire {
-                                FieldValue::borrowed_any(starwars.chars.get(starwars.luke).unwrap())
-                                    .with_type("Human")
-                            } else {
-                                FieldValue::borrowed_any(
-                                    starwars.chars.get(starwars.artoo).unwrap(),
-                                )
-                                .with_type("Droid")
-                            }
-                        }
-                        None => {
-                            FieldValue::borrowed_any(starwars.chars.get(starwars.luke).unwrap())
-                                .with_type("Human")
-                        }
-                    };
-                    Ok(Some(value))
+                    let episode = ctx.args.get("episode").map(parse_episode).transpose()?;
+                    let hero = starwars.hero(episode);
+                    Ok(Some(FieldValue::borrowed_any(hero).with_type(
+                        if hero.is_human() { "Human" } else { "Droid" },
+                    )))
                 })
             })
-            .argument(InputValue::new(
-                "episode",
-                TypeRef::named(episode.type_name()),
-            )),
+            .argument(
+                InputValue::new("episode", TypeRef::named(episode.type_name())).description(
+                    "If omitted, returns the hero of the whole saga. If provided, returns the hero of that particular episode.",
+                ),
+            ),
         )
         .field(
             Field::new("human", TypeRef::named(human.type_name()), |ctx| {
@@ -219,25 +351,87 @@ pub fn schema() -> Result<Schema, SchemaError> {
                         .map(|human| FieldValue::borrowed_any(human)))
                 })
             })
-            .argument(InputValue::new("id", TypeRef::named_nn(TypeRef::STRING))),
+            .argument(
+                InputValue::new("id", TypeRef::named_nn(TypeRef::STRING))
+                    .description("id or global id of the human"),
+            ),
         )
-        .field(Field::new(
+        .field(connection_field(
             "humans",
-            TypeRef::named_nn_list_nn(human.type_name()),
-            |ctx| {
+            human_connection.type_name(),
+            StarWars::humans,
+        ))
+        .field(
+            Field::new(
+                "reviews",
+                TypeRef::named_nn_list_nn(review.type_name()),
+                |ctx| {
+                    FieldFuture::new(async move {
+                        let starwars = ctx.data::<StarWars>()?;
+                        let episode = parse_episode(ctx.args.try_get("episode")?)?;
+                        Ok(Some(FieldValue::list(
+                            starwars
+                                .reviews(episode)
+                                .into_iter()
+                                .map(FieldValue::owned_any),
+                        )))
+                    })
+                },
+            )
+            .argument(InputValue::new(
+                "episode",
+                TypeRef::named_nn(episode.type_name()),
+            ))
+            .description("The reviews of `episode`, oldest first."),
+        )
+        .field(
+            Field::new(
+                "search",
+                TypeRef::named_nn_list_nn(search_result.type_name()),
+                |ctx| {
+                    FieldFuture::new(async move {
+                        let starwars = ctx.data::<StarWars>()?;
+                        let text = ctx.args.try_get("text")?.string()?.to_lowercase();
+                        let matches = |name: &str| name.to_lowercase().contains(&text);
+                        let chars = starwars
+                            .characters()
+                            .into_iter()
+                            .filter(|ch| matches(ch.name()))
+                            .map(character_value);
+                        let starships = starwars
+                            .starships()
+                            .into_iter()
+                            .filter(|starship| matches(starship.name()))
+                            .map(|starship| {
+                                FieldValue::borrowed_any(starship).with_type("Starship")
+                            });
+                        Ok(Some(FieldValue::list(
+                            chars.chain(starships).collect::<Vec<_>>(),
+                        )))
+                    })
+                },
+            )
+            .argument(InputValue::new("text", TypeRef::named_nn(TypeRef::STRING)))
+            .description("The characters and starships whose name contains `text`, ignoring case."),
+        )
+        .field(
+            Field::new("node", TypeRef::named(node.type_name()), |ctx| {
                 FieldFuture::new(async move {
                     let starwars = ctx.data::<StarWars>()?;
-                    let humans = starwars.humans();
-                    Ok(Some(FieldValue::list(
-                        humans
-                            .into_iter()
-                            .map(|human| FieldValue::borrowed_any(human)),
-                    )))
+                    let id = ctx.args.try_get("id")?;
+                    Ok(starwars.node(id.string()?).map(|node| match node {
+                        StarWarsNode::Char(ch) => character_value(ch),
+                        StarWarsNode::Starship(starship) => {
+                            FieldValue::borrowed_any(starship).with_type("Starship")
+                        }
+                    }))
                 })
-            },
-        ))
+            })
+            .argument(InputValue::new("id", TypeRef::named_nn(TypeRef::ID)))
+            .description("Fetches an object given its id."),
+        )
         .field(
-            Field::new("droid", TypeRef::named(human.type_name()), |ctx| {
+            Field::new("droid", TypeRef::named(droid.type_name()), |ctx| {
                 FieldFuture::new(async move {
                     let starwars = ctx.data::<StarWars>()?;
                     let id = ctx.args.try_get("id")?;
@@ -246,30 +440,288 @@ pub fn schema() -> Result<Schema, SchemaError> {
                         .map(|droid| FieldValue::borrowed_any(droid)))
                 })
             })
-            .argument(InputValue::new("id", TypeRef::named_nn(TypeRef::STRING))),
+            .argument(
+                InputValue::new("id", TypeRef::named_nn(TypeRef::STRING))
+                    .description("id or global id of the droid"),
+            ),
         )
-        .field(Field::new(
+        .field(connection_field(
             "droids",
-            TypeRef::named_nn_list_nn(human.type_name()),
+            droid_connection.type_name(),
+            StarWars::droids,
+        ));
+
+    let mutation = Object::new("Mutation").field(
+        Field::new(
+            "createReview",
+            TypeRef::named_nn(review.type_name()),
             |ctx| {
                 FieldFuture::new(async move {
                     let starwars = ctx.data::<StarWars>()?;
-                    let droids = starwars.droids();
-                    Ok(Some(FieldValue::list(
-                        droids
-                            .into_iter()
-                            .map(|droid| FieldValue::borrowed_any(droid)),
-                    )))
+                    let episode = parse_episode(ctx.args.try_get("episode")?)?;
+                    let review = parse_review_input(episode, ctx.args.try_get("review")?)?;
+                    starwars.add_review(review.clone());
+                    Ok(Some(FieldValue::owned_any(review)))
+                })
+            },
+        )
+        .argument(InputValue::new(
+            "episode",
+            TypeRef::named_nn(episode.type_name()),
+        ))
+        .argument(InputValue::new(
+            "review",
+            TypeRef::named_nn(review_input.type_name()),
+        ))
+        .description("Add a review of `episode` and returns it."),
+    );
+
+    let subscription = Subscription::new("Subscription").field(
+        SubscriptionField::new(
+            "reviewAdded",
+            TypeRef::named_nn(review.type_name()),
+            |ctx| {
+                SubscriptionFieldFuture::new(async move {
+                    let starwars = ctx.data::<StarWars>()?;
+                    let episode = ctx.args.get("episode").map(parse_episode).transpose()?;
+                    Ok(starwars
+                        .subscribe_reviews()
+                        .filter(move |review| {
+                            future::ready(episode.is_none_or(|episode| review.episode == episode))
+                        })
+                        .map(|review| Ok(FieldValue::owned_any(review))))
                 })
             },
+        )
+        .argument(InputValue::new(
+            "episode",
+            TypeRef::named(episode.type_name()),
+        ))
+        .description(
+            "The reviews created from now on, only those of `episode` if it is\nspecified.",
+        ),
+    );
+
+    Schema::build(
+        query.type_name(),
+        Some(mutation.type_name()),
+        Some(subscription.type_name()),
+    )
+    .register(episode)
+    .register(length_unit)
+    .register(node)
+    .register(character)
+    .register(human)
+    .register(droid)
+    .register(starship)
+    .register(page_info)
+    .register(human_connection)
+    .register(human_edge)
+    .register(droid_connection)
+    .register(droid_edge)
+    .register(review)
+    .register(review_input)
+    .register(search_result)
+    .register(query)
+    .register(mutation)
+    .register(subscription)
+    .data(StarWars::new())
+    .finish()
+}
+
+/// Parse a `ReviewInput`, with the validation of the static schema.
+fn parse_review_input(episode: Episode, input: ValueAccessor<'_>) -> Result<Review> {
+    let input = input.object()?;
+    let stars = input.try_get("stars")?.i64()?;
+    if !(1..=5).contains(&stars) {
+        let bound = if stars < 1 {
+            "greater than or equal to 1"
+        } else {
+            "less than or equal to 5"
+        };
+        return Err(invalid_review_input(
+            "Int",
+            format!("the value is {stars}, must be {bound}"),
         ));
+    }
+    let commentary = match input.get("commentary") {
+        Some(commentary) if !commentary.is_null() => {
+            let commentary = commentary.string()?;
+            // Like the `min_length` and `max_length` validators, in bytes.
+            let len = commentary.len();
+            if !(1..=500).contains(&len) {
+                let bound = if len < 1 {
+                    "greater than or equal to 1"
+                } else {
+                    "less than or equal to 500"
+                };
+                return Err(invalid_review_input(
+                    "String",
+                    format!("the string length is {len}, must be {bound}"),
+                ));
+            }
+            Some(commentary.to_string())
+        }
+        _ => None,
+    };
+    Ok(Review {
+        episode,
+        stars: stars as i32,
+        commentary,
+    })
+}
+
+fn invalid_review_input(ty: &str, message: String) -> Error {
+    Error::new(format!(
+        "Failed to parse \"{ty}\": {message} (occurred while parsing \"ReviewInput\")"
+    ))
+}
+
+/// A human or a droid, with its concrete type.
+fn character_value(ch: &StarWarsChar) -> FieldValue<'_> {
+    FieldValue::borrowed_any(ch).with_type(if ch.is_human() { "Human" } else { "Droid" })
+}
+
+/// A page of a connection of characters, whose cursors are their offsets in
+/// the paginated list.
+struct Page {
+    edges: Vec<Edge>,
+    has_previous_page: bool,
+    has_next_page: bool,
+}
+
+struct Edge {
+    cursor: usize,
+    id: String,
+}
+
+impl Page {
+    /// Selects the page of `chars` given by the `after`, `before`, `first` and
+    /// `last` arguments, with the pagination of the static schema.
+    async fn new(args: &ObjectAccessor<'_>, chars: &[&StarWarsChar]) -> Result<Self> {
+        let arg = |name: &str| args.get(name).filter(|value| !value.is_null());
+        let cursor = |name: &str| -> Result<Option<String>> {
+            arg(name)
+                .map(|value| value.string().map(ToString::to_string))
+                .transpose()
+        };
+        let count = |name: &str| -> Result<Option<i32>> {
+            arg(name)
+                .map(|value| i32::try_from(value.i64()?).map_err(Error::new_with_source))
+                .transpose()
+        };
+        let connection = offset_connection(
+            cursor("after")?,
+            cursor("before")?,
+            count("first")?,
+            count("last")?,
+            chars,
+            |ch| ch.id().to_string(),
+        )
+        .await?;
+
+        Ok(Page {
+            edges: connection
+                .edges
+                .into_iter()
+                .map(|edge| Edge {
+                    cursor: edge.cursor,
+                    id: edge.node,
+                })
+                .collect(),
+            has_previous_page: connection.has_previous_page,
+            has_next_page: connection.has_next_page,
+        })
+    }
+}
+
+/// The `<node>Connection` and `<node>Edge` types of a connection of `node`
+/// characters.
+fn connection_types(node: &str) -> (Object, Object) {
+    let edge = Object::new(format!("{node}Edge"))
+        .description("An edge in a connection.")
+        .field(
+            Field::new("node", TypeRef::named_nn(node), |ctx| {
+                FieldFuture::new(async move {
+                    let edge = ctx.parent_value.try_downcast_ref::<Edge>()?;
+                    let starwars = ctx.data::<StarWars>()?;
+                    Ok(starwars
+                        .character(&edge.id)
+                        .map(|ch| FieldValue::borrowed_any(ch)))
+                })
+            })
+            .description("The item at the end of the edge"),
+        )
+        .field(
+            Field::new("cursor", TypeRef::named_nn(TypeRef::STRING), |ctx| {
+                FieldFuture::new(async move {
+                    let edge = ctx.parent_value.try_downcast_ref::<Edge>()?;
+                    Ok(Some(Value::from(edge.cursor.to_string())))
+                })
+            })
+            .description("A cursor for use in pagination"),
+        );
+
+    let connection = Object::new(format!("{node}Connection"))
+        .field(
+            Field::new("pageInfo", TypeRef::named_nn("PageInfo"), |ctx| {
+                FieldFuture::new(async move {
+                    let page = ctx.parent_value.try_downcast_ref::<Page>()?;
+                    Ok(Some(FieldValue::borrowed_any(page)))
+                })
+            })
+            .description("Information to aid in pagination."),
+        )
+        .field(
+            Field::new(
+                "edges",
+                TypeRef::named_nn_list_nn(edge.type_name()),
+                |ctx| {
+                    FieldFuture::new(async move {
+                        let page = ctx.parent_value.try_downcast_ref::<Page>()?;
+                        Ok(Some(FieldValue::list(
+                            page.edges.iter().map(|edge| FieldValue::borrowed_any(edge)),
+                        )))
+                    })
+                },
+            )
+            .description("A list of edges."),
+        )
+        .field(
+            Field::new("nodes", TypeRef::named_nn_list_nn(node), |ctx| {
+                FieldFuture::new(async move {
+                    let page = ctx.parent_value.try_downcast_ref::<Page>()?;
+                    let starwars = ctx.data::<StarWars>()?;
+                    Ok(Some(FieldValue::list(
+                        page.edges
+                            .iter()
+                            .filter_map(|edge| starwars.character(&edge.id))
+                            .map(|ch| FieldValue::borrowed_any(ch)),
+                    )))
+                })
+            })
+            .description("A list of nodes."),
+        );
+
+    (connection, edge)
+}
 
-    Schema::build(query.type_name(), None, None)
-        .register(episode)
-        .register(character)
-        .register(human)
-        .register(droid)
-        .register(query)
-        .data(StarWars::new())
-        .finish()
+/// A field paginating the characters returned by `chars` into a connection
+/// of type `ty`.
+fn connection_field(
+    name: &str,
+    ty: &str,
+    chars: for<'a> fn(&'a StarWars) -> Vec<&'a StarWarsChar>,
+) -> Field {
+    Field::new(name, TypeRef::named_nn(ty), move |ctx| {
+        FieldFuture::new(async move {
+            let starwars = ctx.data::<StarWars>()?;
+            let page = Page::new(&ctx.args, &chars(starwars)).await?;
+            Ok(Some(FieldValue::owned_any(page)))
+        })
+    })
+    .argument(InputValue::new("after", TypeRef::named(TypeRef::STRING)))
+    .argument(InputValue::new("before", TypeRef::named(TypeRef::STRING)))
+    .argument(InputValue::new("first", TypeRef::named(TypeRef::INT)))
+    .argument(InputValue::new("last", TypeRef::named(TypeRef::INT)))
 }
@@ -0,0 +1,223 @@
+//! Compares the peak memory and disk usage of the ways an upload of a large
+//! generated file can be processed.
+//!
+//! ```bash
+//! UPLOAD_BENCH_MB=1024 cargo bench -p files
+//! ```
+//!
+//! The measures are read from `/proc`, so this only runs on Linux. The peak
+//! memory is how much the memory of the process grew over what it used before
+//! each run. The disk usage is the number of bytes written by the process, to
+//! the temporary file that async-graphql buffers the upload in, unless the
+//! request is executed with `files::execute`, and to the store.
+
+use std::{
+    env, fs,
+    io::{self, Read, Write},
+    iter,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Instant,
+};
+
+use async_graphql::{
+    Schema, UploadValue,
+    http::{MultipartOptions, receive_body},
+};
+use bytes::Bytes;
+use files::{
+    FileStore, LocalFileStore, MutationRoot, QueryRoot, Storage, SubscriptionRoot, UploadPolicy,
+};
+use futures::{
+    StreamExt, TryStreamExt,
+    executor::block_on,
+    stream::{self, BoxStream},
+};
+use sha2::{Digest, Sha256};
+
+const MB: u64 = 1024 * 1024;
+
+fn main() {
+    let size = env::var("UPLOAD_BENCH_MB")
+        .ok()
+        .and_then(|size| size.parse().ok())
+        .unwrap_or(256)
+        * MB;
+    let dir = env::temp_dir().join("files-upload-bench");
+    let _ = fs::remove_dir_all(&dir);
+    let limited = UploadPolicy::default().max_file_size(size / 16);
+
+    println!("upload of {} MiB", size / MB);
+    println!(
+        "{:<44} {:>10} {:>16} {:>16}",
+        "", "time", "peak memory", "written to disk"
+    );
+
+    measure("blocking copy to the store (previous path)", || {
+        let upload = receive(size, MultipartOptions::default()).unwrap();
+        let store = LocalFileStore::new(&dir).unwrap();
+        let mut writer = store.create().unwrap();
+        let mut content = upload.into_read();
+        let mut hasher = Sha256::new();
+        let mut buf = vec![0; 8 * 1024];
+        loop {
+            let n = content.read(&mut buf).unwrap();
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buf[..n]);
+            writer.write_all(&buf[..n]).unwrap();
+        }
+        writer.commit("previous").unwrap();
+    });
+
+    let storage = Storage::local(&dir).unwrap();
+    measure("streamed to the store", || {
+        let upload = receive(size, MultipartOptions::default()).unwrap();
+        block_on(storage.upload(vec![upload.into()], &UploadPolicy::default(), None)).unwrap();
+    });
+
+    measure("rejected by the policy after buffering", || {
+        let upload = receive(size, MultipartOptions::default()).unwrap();
+        assert!(block_on(storage.upload(vec![upload.into()], &limited, None)).is_err());
+    });
+
+    measure("rejected while parsing the request", || {
+        assert!(receive(size, limited.multipart_options()).is_err());
+    });
+
+    let schema = Schema::build(QueryRoot, MutationRoot, SubscriptionRoot)
+        .data(storage.clone())
+        .finish();
+    measure("singleUpload mutation", || {
+        let request = block_on(receive_body(
+            Some(CONTENT_TYPE),
+            body(size).into_async_read(),
+            MultipartOptions::default(),
+        ))
+        .unwrap();
+        let response = block_on(schema.execute(request));
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+    });
+
+    measure("singleUpload mutation streamed to the store", || {
+        let response = block_on(files::execute(
+            &schema,
+            Some(CONTENT_TYPE),
+            body(size).into_async_read(),
+            MultipartOptions::default(),
+        ))
+        .unwrap();
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+    });
+
+    let schema = Schema::build(QueryRoot, MutationRoot, SubscriptionRoot)
+        .data(storage)
+        .data(limited)
+        .finish();
+    measure("rejected by the policy while streaming", || {
+        let response = block_on(files::execute(
+            &schema,
+            Some(CONTENT_TYPE),
+            body(size).into_async_read(),
+            MultipartOptions::default(),
+        ))
+        .unwrap();
+        assert!(!response.errors.is_empty());
+    });
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+const CONTENT_TYPE: &str = "multipart/form-data; boundary=BOUNDARY";
+
+/// A multipart request uploading a text file of `size` bytes as the `file`
+/// variable of a `singleUpload` mutation, generated as it is read.
+///
+/// Like a network connection, the body is not ready again right after each
+/// chunk, otherwise the multipart parser would read it all at once.
+fn body(size: u64) -> BoxStream<'static, io::Result<Bytes>> {
+    let head = Bytes::from(
+        "--BOUNDARY\r\n\
+         Content-Disposition: form-data; name=\"operations\"\r\n\r\n\
+         {\"query\":\"mutation($file: Upload!) { singleUpload(file: $file) { id } }\",\
+         \"variables\":{\"file\":null}}\r\n\
+         --BOUNDARY\r\n\
+         Content-Disposition: form-data; name=\"map\"\r\n\r\n\
+         {\"0\":[\"variables.file\"]}\r\n\
+         --BOUNDARY\r\n\
+         Content-Disposition: form-data; name=\"0\"; filename=\"large.txt\"\r\n\r\n",
+    );
+    let line = b"The quick brown fox jumps over the lazy dog.\n";
+    let chunk = Bytes::from(line.repeat(MB as usize / line.len() + 1));
+    let chunks = (0..size.div_ceil(MB)).map(move |i| {
+        let len = (size - i * MB).min(MB) as usize;
+        chunk.slice(..len)
+    });
+    let tail = Bytes::from("\r\n--BOUNDARY--\r\n");
+    stream::iter(iter::once(head).chain(chunks).chain(iter::once(tail)))
+        .then(|chunk| async {
+            YieldNow(false).await;
+            Ok(chunk)
+        })
+        .boxed()
+}
+
+/// A future that is pending once, waking itself up.
+struct YieldNow(bool);
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.0 {
+            return Poll::Ready(());
+        }
+        self.0 = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+/// Parses a generated request with a file of `size` bytes, returns the file.
+fn receive(size: u64, options: MultipartOptions) -> Result<UploadValue, String> {
+    let request = block_on(receive_body(
+        Some(CONTENT_TYPE),
+        body(size).into_async_read(),
+        options,
+    ))
+    .map_err(|err| err.to_string())?;
+    let mut uploads = request.uploads;
+    Ok(uploads.remove(0))
+}
+
+/// Runs `f` and prints how long it took, how much the peak memory of the
+/// process grew and how many bytes it wrote.
+fn measure(name: &str, f: impl FnOnce()) {
+    // Resets the peak memory to the current memory.
+    fs::write("/proc/self/clear_refs", "5").expect("failed to reset the peak memory");
+    let (memory, written) = (proc_value("status", "VmRSS:"), proc_value("io", "wchar:"));
+    let start = Instant::now();
+    f();
+    let elapsed = start.elapsed();
+    let (peak, written) = (
+        proc_value("status", "VmHWM:") - memory,
+        proc_value("io", "wchar:") - written,
+    );
+    println!(
+        "{name:<44} {:>8.2?} {:>12} KiB {:>12} KiB",
+        elapsed,
+        peak,
+        written / 1024
+    );
+}
+
+/// The value of the field `name` of the `/proc/self` file `file`.
+fn proc_value(file: &str, name: &str) -> u64 {
+    let content = fs::read_to_string(format!("/proc/self/{file}")).unwrap();
+    content
+        .lines()
+        .find_map(|line| line.strip_prefix(name))
+        .and_then(|value| value.split_whitespace().next()?.parse().ok())
+        .unwrap_or_else(|| panic!("no {name} in /proc/self/{file}"))
+}
@@ -0,0 +1,341 @@
+//! Chunked uploads, for the files that are too large to be sent in a single
+//! request.
+
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use futures::{AsyncReadExt, lock::Mutex};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    UploadError, UploadFile, UploadProgress,
+    download::CHUNK_SIZE,
+    policy::{SNIFF_LEN, sniff},
+    progress::Progress,
+    storage::{ReceivedFile, SharedWriter, random_id},
+};
+
+/// The time after which an upload that doesn't receive any chunk expires, by
+/// default.
+pub(crate) const DEFAULT_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// A chunked upload in progress, the received bytes are written to a new
+/// file of the store, which is discarded unless the upload is completed.
+pub(crate) struct Session {
+    pub(crate) filename: String,
+    size: u64,
+    received: u64,
+    hasher: Sha256,
+    /// The first bytes of the content, that its MIME type is detected from.
+    head: Vec<u8>,
+    /// `None` once the upload is completed.
+    writer: Option<SharedWriter>,
+}
+
+/// A chunked upload, locked while it receives a chunk or is completed so that
+/// the other uploads don't wait for it.
+pub(crate) type SharedSession = Arc<Mutex<Session>>;
+
+impl Session {
+    /// Appends `chunk`, which must start at the end of the bytes received so
+    /// far, returns the number of bytes received.
+    pub(crate) async fn append(
+        &mut self,
+        offset: u64,
+        chunk: UploadFile,
+    ) -> Result<u64, UploadError> {
+        let Some(writer) = &self.writer else {
+            return Err(UploadError::UnknownUpload);
+        };
+        if offset != self.received {
+            return Err(UploadError::InvalidOffset {
+                expected: self.received,
+            });
+        }
+        let remaining = self.size - self.received;
+
+        // Drop what a previous chunk that failed or was cancelled wrote, so
+        // that it can be sent again.
+        writer
+            .truncate(self.received)
+            .await
+            .map_err(UploadError::Store)?;
+
+        let (_, content) = chunk.open().await.map_err(UploadError::Invalid)?;
+        // One more byte than the remaining ones tells if the chunk is too large.
+        let mut content = content.take(remaining.saturating_add(1));
+        let mut buf = Vec::with_capacity(CHUNK_SIZE);
+        let mut hasher = self.hasher.clone();
+        let mut head = self.head.clone();
+        let mut written = 0;
+        loop {
+            buf.resize(CHUNK_SIZE, 0);
+            let n = content.read(&mut buf).await.map_err(UploadError::Invalid)?;
+            if n == 0 {
+                break;
+            }
+            if written + n as u64 > remaining {
+                return Err(UploadError::ChunkOutOfBounds { size: self.size });
+            }
+            buf.truncate(n);
+            hasher.update(&buf);
+            let sniffed = (SNIFF_LEN as usize).saturating_sub(head.len()).min(n);
+            head.extend_from_slice(&buf[..sniffed]);
+            buf = writer.write_all(buf).await.map_err(UploadError::Store)?;
+            written += n as u64;
+        }
+
+        self.received += written;
+        self.hasher = hasher;
+        self.head = head;
+        Ok(self.received)
+    }
+
+    /// Checks that all the bytes were received and that their SHA-256 digest
+    /// is `sha256`.
+    pub(crate) fn check(&self, sha256: &str) -> Result<(), UploadError> {
+        if self.writer.is_none() {
+            return Err(UploadError::UnknownUpload);
+        }
+        if self.received != self.size {
+            return Err(UploadError::Incomplete {
+                received: self.received,
+                size: self.size,
+            });
+        }
+        let actual = format!("{:x}", self.hasher.clone().finalize());
+        if !actual.eq_ignore_ascii_case(sha256.trim()) {
+            return Err(UploadError::ChecksumMismatch { actual });
+        }
+        Ok(())
+    }
+
+    /// The number of bytes received so far, out of the size of the file.
+    pub(crate) fn progress(&self) -> UploadProgress {
+        UploadProgress {
+            bytes_received: self.received.into(),
+            total_bytes: Some(self.size.into()),
+            files: Vec::new(),
+        }
+    }
+
+    /// The received file, to be committed to the store. The session can't
+    /// receive chunks afterwards.
+    pub(crate) fn take(&mut self) -> Result<ReceivedFile, UploadError> {
+        let writer = self.writer.take().ok_or(UploadError::UnknownUpload)?;
+        Ok(ReceivedFile {
+            writer,
+            mime_type: sniff(&self.head),
+            size: self.size,
+            sha256: format!("{:x}", self.hasher.clone().finalize()),
+        })
+    }
+}
+
+/// A chunked upload and the time it expires at.
+struct Entry {
+    session: SharedSession,
+    expires_at: Instant,
+}
+
+/// The chunked uploads in progress, by id.
+///
+/// The [`Storage`](crate::Storage) removes the expired uploads whenever it is
+/// used, which ends the subscriptions to their progress.
+pub(crate) struct Sessions {
+    sessions: HashMap<String, Entry>,
+    ttl: Duration,
+    progress: Progress,
+}
+
+impl Sessions {
+    pub(crate) fn new(ttl: Duration, progress: Progress) -> Self {
+        Self {
+            sessions: HashMap::new(),
+            ttl,
+            progress,
+        }
+    }
+
+    /// Starts the upload of a file of `size` bytes to `writer`, returns its
+    /// id.
+    pub(crate) fn begin(&mut self, filename: String, size: u64, writer: SharedWriter) -> String {
+        let id = random_id();
+        let session = Session {
+            filename,
+            size,
+            received: 0,
+            hasher: Sha256::new(),
+            head: Vec::new(),
+            writer: Some(writer),
+        };
+        self.sessions.insert(
+            id.clone(),
+            Entry {
+                session: Arc::new(Mutex::new(session)),
+                expires_at: Instant::now() + self.ttl,
+            },
+        );
+        id
+    }
+
+    /// Returns the upload `id` and postpones its expiration, since it is
+    /// about to receive a chunk.
+    pub(crate) fn touch(&mut self, id: &str) -> Result<SharedSession, UploadError> {
+        let entry = self
+            .sessions
+            .get_mut(id)
+            .ok_or(UploadError::UnknownUpload)?;
+        entry.expires_at = Instant::now() + self.ttl;
+        Ok(entry.session.clone())
+    }
+
+    /// Returns the upload `id`, if it exists.
+    pub(crate) fn get(&self, id: &str) -> Option<SharedSession> {
+        self.sessions.get(id).map(|entry| entry.session.clone())
+    }
+
+    /// Removes the upload `id`, which discards what it received unless it was
+    /// completed.
+    pub(crate) fn remove(&mut self, id: &str) {
+        self.sessions.remove(id);
+    }
+
+    /// Removes the expired uploads and returns them, what they received is
+    /// discarded once they are dropped.
+    pub(crate) fn remove_expired(&mut self) -> Vec<SharedSession> {
+        let now = Instant::now();
+        let mut expired = Vec::new();
+        self.sessions.retain(|id, entry| {
+            if entry.expires_at > now {
+                return true;
+            }
+            self.progress.fail(id, &UploadError::UnknownUpload);
+            expired.push(entry.session.clone());
+            false
+        });
+        expired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use futures::executor::block_on;
+
+    use super::*;
+    use crate::{Long, Storage, UploadPolicy, storage::tests::upload};
+
+    fn sha256(content: &[u8]) -> String {
+        format!("{:x}", Sha256::digest(content))
+    }
+
+    fn chunk(storage: &Storage, id: &str, offset: u64, content: &[u8]) -> Result<u64, UploadError> {
+        block_on(storage.upload_chunk(id, offset, upload("chunk", content)))
+    }
+
+    #[test]
+    fn chunked_upload() {
+        let storage = Storage::memory();
+        let policy = UploadPolicy::default();
+        let id = block_on(storage.begin_upload("../hello.txt", 11, &policy)).unwrap();
+        assert_eq!(chunk(&storage, &id, 0, b"hello").unwrap(), 5);
+        assert_eq!(chunk(&storage, &id, 5, b" world").unwrap(), 11);
+
+        let file =
+            block_on(storage.complete_upload(&id, &sha256(b"hello world"), &policy)).unwrap();
+        assert_eq!(file.filename, "hello.txt");
+        assert_eq!(file.size, Long(11));
+        assert_eq!(file.mime_type, "text/plain");
+        assert!(block_on(storage.file(file.id.as_str())).is_some());
+
+        // A completed upload can't receive chunks anymore.
+        let err = chunk(&storage, &id, 11, b"!").unwrap_err();
+        assert!(matches!(err, UploadError::UnknownUpload));
+    }
+
+    #[test]
+    fn offset_mismatch() {
+        let storage = Storage::memory();
+        let id = block_on(storage.begin_upload("a.txt", 10, &UploadPolicy::default())).unwrap();
+        chunk(&storage, &id, 0, b"abcd").unwrap();
+        for offset in [0, 2, 6] {
+            let err = chunk(&storage, &id, offset, b"ef").unwrap_err();
+            assert!(matches!(err, UploadError::InvalidOffset { expected: 4 }));
+        }
+        assert_eq!(chunk(&storage, &id, 4, b"ef").unwrap(), 6);
+    }
+
+    #[test]
+    fn chunk_out_of_bounds() {
+        let storage = Storage::memory();
+        let policy = UploadPolicy::default();
+        let id = block_on(storage.begin_upload("a.txt", 5, &policy)).unwrap();
+        let err = chunk(&storage, &id, 0, b"abcdef").unwrap_err();
+        assert!(matches!(err, UploadError::ChunkOutOfBounds { size: 5 }));
+
+        chunk(&storage, &id, 0, b"abc").unwrap();
+        let err = chunk(&storage, &id, 3, b"def").unwrap_err();
+        assert!(matches!(err, UploadError::ChunkOutOfBounds { size: 5 }));
+
+        // The rejected chunks left nothing behind.
+        assert_eq!(chunk(&storage, &id, 3, b"de").unwrap(), 5);
+        block_on(storage.complete_upload(&id, &sha256(b"abcde"), &policy)).unwrap();
+    }
+
+    #[test]
+    fn checksum_mismatch() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = Storage::local(dir.path()).unwrap();
+        let policy = UploadPolicy::default();
+        let id = block_on(storage.begin_upload("a.txt", 6, &policy)).unwrap();
+        chunk(&storage, &id, 0, b"abc").unwrap();
+
+        // An incomplete upload is kept so that the missing chunks can be sent.
+        let err = block_on(storage.complete_upload(&id, &sha256(b"abcdef"), &policy)).unwrap_err();
+        assert!(matches!(
+            err,
+            UploadError::Incomplete {
+                received: 3,
+                size: 6
+            }
+        ));
+        chunk(&storage, &id, 3, b"def").unwrap();
+
+        let err = block_on(storage.complete_upload(&id, &sha256(b"abcdeg"), &policy)).unwrap_err();
+        assert!(
+            matches!(&err, UploadError::ChecksumMismatch { actual } if *actual == sha256(b"abcdef"))
+        );
+
+        // The upload is discarded with what it received.
+        let err = block_on(storage.complete_upload(&id, &sha256(b"abcdef"), &policy)).unwrap_err();
+        assert!(matches!(err, UploadError::UnknownUpload));
+        assert!(block_on(storage.files()).is_empty());
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn expired_upload() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = Storage::local(dir.path())
+            .unwrap()
+            .with_session_ttl(Duration::ZERO);
+        let policy = UploadPolicy::default();
+        let id = block_on(storage.begin_upload("a.txt", 6, &policy)).unwrap();
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        // Any use of the storage removes the expired uploads, such as a
+        // download.
+        assert!(block_on(storage.download("0", None)).unwrap().is_none());
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+
+        let err = chunk(&storage, &id, 0, b"abc").unwrap_err();
+        assert!(matches!(err, UploadError::UnknownUpload));
+        let err = block_on(storage.complete_upload(&id, &sha256(b""), &policy)).unwrap_err();
+        assert!(matches!(err, UploadError::UnknownUpload));
+    }
+}
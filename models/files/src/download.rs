@@ -0,0 +1,197 @@
+use std::io::{self, Read, Seek, SeekFrom, Take};
+
+use blocking::unblock;
+use bytes::Bytes;
+use futures::{Stream, stream};
+
+use crate::{Content, FileInfo};
+
+/// The size of the chunks that the contents are streamed in.
+pub(crate) const CHUNK_SIZE: usize = 64 * 1024;
+
+/// The response to a request for the content of an uploaded file, with
+/// support for a single byte range.
+pub struct Download {
+    /// `200` for the whole content, `206` for the requested range or `416` if
+    /// the range is not satisfiable.
+    pub status: u16,
+
+    /// The MIME type of the file.
+    pub content_type: String,
+
+    /// The length of the body.
+    pub content_length: u64,
+
+    /// The `Content-Range` header of a `206` or `416` response.
+    pub content_range: Option<String>,
+
+    content: Take<Box<dyn Content>>,
+}
+
+impl Download {
+    /// Selects the part of `content` requested by the `Range` header `range`.
+    ///
+    /// A range that can't be parsed or that has several parts is ignored and
+    /// the whole content is sent, as allowed by RFC 9110.
+    pub(crate) fn new(
+        info: &FileInfo,
+        mut content: Box<dyn Content>,
+        range: Option<&str>,
+    ) -> io::Result<Self> {
+        let size = info.size.0;
+        let (status, content_range, start, length) =
+            match range.and_then(|range| parse_range(range, size)) {
+                None => (200, None, 0, size),
+                Some(Some((start, end))) => (
+                    206,
+                    Some(format!("bytes {start}-{end}/{size}")),
+                    start,
+                    end - start + 1,
+                ),
+                Some(None) => (416, Some(format!("bytes */{size}")), 0, 0),
+            };
+        content.seek(SeekFrom::Start(start))?;
+        Ok(Self {
+            status,
+            content_type: info.mime_type.clone(),
+            content_length: length,
+            content_range,
+            content: content.take(length),
+        })
+    }
+
+    /// The body, read from the store in chunks on the blocking thread pool.
+    pub fn into_stream(self) -> impl Stream<Item = io::Result<Bytes>> + Send + 'static {
+        stream::unfold(Some(self.content), |content| async move {
+            let mut content = content?;
+            let (content, read) = unblock(move || {
+                let mut buf = vec![0; CHUNK_SIZE];
+                let read = content.read(&mut buf).map(|n| {
+                    buf.truncate(n);
+                    buf
+                });
+                (content, read)
+            })
+            .await;
+            match read {
+                Ok(buf) if buf.is_empty() => None,
+                Ok(buf) => Some((Ok(Bytes::from(buf)), Some(content))),
+                Err(err) => Some((Err(err), None)),
+            }
+        })
+    }
+}
+
+/// Parses a `Range` header with a single byte range of a content of `size`
+/// bytes, returns the first and last position of the range or `Some(None)`
+/// if it is not satisfiable.
+fn parse_range(range: &str, size: u64) -> Option<Option<(u64, u64)>> {
+    let spec = range.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return None;
+    }
+    let (start, end) = spec.trim().split_once('-')?;
+    let range = match (start.trim(), end.trim()) {
+        ("", suffix) => {
+            let suffix = suffix.parse::<u64>().ok()?;
+            (suffix > 0 && size > 0).then(|| (size.saturating_sub(suffix), size - 1))
+        }
+        (start, "") => {
+            let start = start.parse::<u64>().ok()?;
+            (start < size).then(|| (start, size - 1))
+        }
+        (start, end) => {
+            let (start, end) = (start.parse::<u64>().ok()?, end.parse::<u64>().ok()?);
+            if end < start {
+                return None;
+            }
+            (start < size).then(|| (start, end.min(size - 1)))
+        }
+    };
+    Some(range)
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{TryStreamExt, executor::block_on};
+
+    use super::*;
+
+    #[test]
+    fn range() {
+        assert_eq!(parse_range("bytes=2-5", 10), Some(Some((2, 5))));
+        assert_eq!(parse_range("bytes=0-0", 10), Some(Some((0, 0))));
+        assert_eq!(parse_range("bytes=8-20", 10), Some(Some((8, 9))));
+        assert_eq!(parse_range(" bytes= 3 - 4 ", 10), Some(Some((3, 4))));
+    }
+
+    #[test]
+    fn open_ended_range() {
+        assert_eq!(parse_range("bytes=4-", 10), Some(Some((4, 9))));
+        assert_eq!(parse_range("bytes=9-", 10), Some(Some((9, 9))));
+        assert_eq!(parse_range("bytes=10-", 10), Some(None));
+    }
+
+    #[test]
+    fn suffix_range() {
+        assert_eq!(parse_range("bytes=-3", 10), Some(Some((7, 9))));
+        assert_eq!(parse_range("bytes=-20", 10), Some(Some((0, 9))));
+        assert_eq!(parse_range("bytes=-0", 10), Some(None));
+        assert_eq!(parse_range("bytes=-3", 0), Some(None));
+    }
+
+    #[test]
+    fn unsatisfiable_range() {
+        assert_eq!(parse_range("bytes=10-12", 10), Some(None));
+        assert_eq!(parse_range("bytes=0-", 0), Some(None));
+    }
+
+    #[test]
+    fn ignored_range() {
+        assert_eq!(parse_range("bytes=0-1,4-5", 10), None);
+        assert_eq!(parse_range("bytes=5-2", 10), None);
+        assert_eq!(parse_range("bytes=a-b", 10), None);
+        assert_eq!(parse_range("bytes=-", 10), None);
+        assert_eq!(parse_range("bytes=3", 10), None);
+        assert_eq!(parse_range("items=0-1", 10), None);
+        assert_eq!(parse_range("", 10), None);
+    }
+
+    #[test]
+    fn download() {
+        let info = FileInfo {
+            id: "0".into(),
+            url: "/files/0".to_string(),
+            filename: "a.txt".to_string(),
+            size: 10u64.into(),
+            mime_type: "text/plain".to_string(),
+            sha256: String::new(),
+            tags: Vec::new(),
+        };
+        let open = || Box::new(io::Cursor::new(b"0123456789".to_vec())) as Box<dyn Content>;
+        let body = |download: Download| {
+            block_on(
+                download
+                    .into_stream()
+                    .map_ok(|bytes| bytes.to_vec())
+                    .try_concat(),
+            )
+            .unwrap()
+        };
+
+        let download = Download::new(&info, open(), None).unwrap();
+        assert_eq!((download.status, download.content_length), (200, 10));
+        assert_eq!(download.content_range, None);
+        assert_eq!(body(download), b"0123456789");
+
+        let download = Download::new(&info, open(), Some("bytes=2-5")).unwrap();
+        assert_eq!((download.status, download.content_length), (206, 4));
+        assert_eq!(download.content_range.as_deref(), Some("bytes 2-5/10"));
+        assert_eq!(body(download), b"2345");
+
+        let download = Download::new(&info, open(), Some("bytes=12-")).unwrap();
+        assert_eq!((download.status, download.content_length), (416, 0));
+        assert_eq!(download.content_range.as_deref(), Some("bytes */10"));
+        assert!(body(download).is_empty());
+    }
+}
@@ -1,24 +1,166 @@
-use async_graphql::{Context, EmptySubscription, ID, Object, Schema, SimpleObject, Upload};
-use futures::lock::Mutex;
-use slab::Slab;
+mod chunked;
+mod download;
+mod multipart;
+mod policy;
+mod progress;
+mod storage;
 
-pub type FilesSchema = Schema<QueryRoot, MutationRoot, EmptySubscription>;
+use async_graphql::{
+    Context, Error, ID, InputObject, InputValueError, InputValueResult, Object, Result, ResultExt,
+    Scalar, ScalarType, Schema, SimpleObject, Subscription, Upload, Value,
+    connection::{Connection, Edge, query},
+};
+pub use download::Download;
+use futures::Stream;
+pub use multipart::{UploadFile, execute};
+pub use policy::{UploadError, UploadPolicy, sanitize_filename};
+pub use progress::ProgressStream;
+pub use storage::{
+    Content, FilePage, FileStore, FileWriter, LocalFileStore, MemoryFileStore, Storage,
+};
 
-#[derive(Clone, SimpleObject)]
+use crate::policy::mime_type_matches;
+
+pub type FilesSchema = Schema<QueryRoot, MutationRoot, SubscriptionRoot>;
+
+/// The header of the requests that send the files of an upload whose progress
+/// is tracked, with the id of the upload.
+pub const UPLOAD_ID_HEADER: &str = "upload-id";
+
+#[derive(Clone, Debug, SimpleObject)]
 pub struct FileInfo {
-    id: ID,
-    url: String,
+    pub id: ID,
+
+    /// The name of the file sent by the client, sanitized.
+    pub filename: String,
+
+    /// The URL that the content of the file can be downloaded from.
+    pub url: String,
+
+    /// The size of the file in bytes.
+    pub size: Long,
+
+    /// The MIME type detected from the first bytes of the content,
+    /// `application/octet-stream` if it was not recognized.
+    pub mime_type: String,
+
+    /// The SHA-256 digest of the content, in lowercase hex.
+    pub sha256: String,
+
+    /// The tags of the file, set with `updateUploadMetadata`.
+    pub tags: Vec<String>,
+}
+
+/// The sizes and offsets of the files, which may not fit in an `Int` since it
+/// is 32-bit.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct Long(pub u64);
+
+/// A non-negative 64-bit integer, sent as a number. It may also be received as
+/// a string of digits.
+#[Scalar]
+impl ScalarType for Long {
+    fn parse(value: Value) -> InputValueResult<Self> {
+        match &value {
+            Value::Number(n) => n.as_u64().map(Long).ok_or_else(|| {
+                InputValueError::custom(format!("{n} is not a non-negative 64-bit integer"))
+            }),
+            Value::String(s) => s.parse().map(Long).map_err(|_| {
+                InputValueError::custom(format!("\"{s}\" is not a non-negative 64-bit integer"))
+            }),
+            _ => Err(InputValueError::expected_type(value)),
+        }
+    }
+
+    fn to_value(&self) -> Value {
+        Value::from(self.0)
+    }
+}
+
+impl From<u64> for Long {
+    fn from(value: u64) -> Self {
+        Long(value)
+    }
+}
+
+/// The progress of an upload.
+#[derive(Clone, SimpleObject)]
+pub struct UploadProgress {
+    /// The number of bytes received so far.
+    pub bytes_received: Long,
+
+    /// The number of bytes of the whole upload, if it is known.
+    pub total_bytes: Option<Long>,
+
+    /// The stored files, only set in the last progress, once the upload is
+    /// complete.
+    pub files: Vec<FileInfo>,
+}
+
+/// Conditions that the returned files must match.
+#[derive(InputObject, Default)]
+pub struct UploadFilter {
+    /// Only files whose filename contains this text, ignoring case.
+    pub filename_contains: Option<String>,
+
+    /// Only files of this MIME type, or of all its subtypes if it ends with
+    /// `/*`.
+    pub mime_type: Option<String>,
+
+    /// Only files with this tag.
+    pub tag: Option<String>,
 }
 
-pub type Storage = Mutex<Slab<FileInfo>>;
+impl UploadFilter {
+    pub fn matches(&self, file: &FileInfo) -> bool {
+        let filename = self.filename_contains.as_ref().is_none_or(|pattern| {
+            file.filename
+                .to_lowercase()
+                .contains(&pattern.to_lowercase())
+        });
+        let mime_type = self
+            .mime_type
+            .as_ref()
+            .is_none_or(|pattern| mime_type_matches(pattern, &file.mime_type));
+        let tag = self.tag.as_ref().is_none_or(|tag| file.tags.contains(tag));
+        filename && mime_type && tag
+    }
+}
 
 pub struct QueryRoot;
 
 #[Object]
 impl QueryRoot {
-    async fn uploads(&self, ctx: &Context<'_>) -> Vec<FileInfo> {
-        let storage = ctx.data_unchecked::<Storage>().lock().await;
-        storage.iter().map(|(_, file)| file).cloned().collect()
+    /// The uploaded file with the specified id.
+    async fn upload(&self, ctx: &Context<'_>, id: ID) -> Option<FileInfo> {
+        ctx.data_unchecked::<Storage>().file(&id).await
+    }
+
+    /// The uploaded files that match `filter`, by id.
+    async fn uploads(
+        &self,
+        ctx: &Context<'_>,
+        filter: Option<UploadFilter>,
+        first: Option<i32>,
+        after: Option<String>,
+    ) -> Result<Connection<usize, FileInfo>> {
+        let storage = ctx.data_unchecked::<Storage>();
+        let filter = filter.unwrap_or_default();
+        query(
+            after,
+            None,
+            first,
+            None,
+            |after, _: Option<usize>, first, _| async move {
+                let page = storage.find(&filter, after, first).await;
+                let mut connection = Connection::new(page.has_previous_page, page.has_next_page);
+                connection
+                    .edges
+                    .extend(page.files.into_iter().map(|(id, file)| Edge::new(id, file)));
+                Ok::<_, Error>(connection)
+            },
+        )
+        .await
     }
 }
 
@@ -26,31 +168,148 @@ pub struct MutationRoot;
 
 #[Object]
 impl MutationRoot {
-    async fn single_upload(&self, ctx: &Context<'_>, file: Upload) -> FileInfo {
-        let mut storage = ctx.data_unchecked::<Storage>().lock().await;
-        let entry = storage.vacant_entry();
-        let upload = file.value(ctx).unwrap();
-        let info = FileInfo {
-            id: entry.key().into(),
-            url: upload.filename,
-        };
-        entry.insert(info.clone());
-        info
-    }
-
-    async fn multiple_upload(&self, ctx: &Context<'_>, files: Vec<Upload>) -> Vec<FileInfo> {
-        let mut infos = Vec::new();
-        let mut storage = ctx.data_unchecked::<Storage>().lock().await;
-        for file in files {
-            let entry = storage.vacant_entry();
-            let upload = file.value(ctx).unwrap();
-            let info = FileInfo {
-                id: entry.key().into(),
-                url: upload.filename.clone(),
-            };
-            entry.insert(info.clone());
-            infos.push(info)
-        }
-        infos
+    /// Upload a file. If `uploadId` is specified, the progress of the upload
+    /// is sent to the `uploadProgress` subscribers with that id.
+    async fn single_upload(
+        &self,
+        ctx: &Context<'_>,
+        file: Upload,
+        upload_id: Option<ID>,
+    ) -> Result<FileInfo> {
+        let storage = ctx.data_unchecked::<Storage>();
+        let upload = UploadFile::new(ctx, &file)
+            .map_err(UploadError::Invalid)
+            .extend()?;
+        let mut infos = storage
+            .upload(
+                vec![upload],
+                &policy(ctx),
+                upload_id.as_deref().map(String::as_str),
+            )
+            .await
+            .extend()?;
+        Ok(infos.remove(0))
+    }
+
+    /// Upload several files. If `uploadId` is specified, the progress of the
+    /// upload is sent to the `uploadProgress` subscribers with that id.
+    async fn multiple_upload(
+        &self,
+        ctx: &Context<'_>,
+        files: Vec<Upload>,
+        upload_id: Option<ID>,
+    ) -> Result<Vec<FileInfo>> {
+        let storage = ctx.data_unchecked::<Storage>();
+        let uploads = files
+            .iter()
+            .map(|file| UploadFile::new(ctx, file).map_err(UploadError::Invalid))
+            .collect::<Result<Vec<_>, _>>()
+            .extend()?;
+        storage
+            .upload(
+                uploads,
+                &policy(ctx),
+                upload_id.as_deref().map(String::as_str),
+            )
+            .await
+            .extend()
+    }
+
+    /// Delete an uploaded file, returns whether it existed.
+    async fn delete_upload(&self, ctx: &Context<'_>, id: ID) -> Result<bool> {
+        let storage = ctx.data_unchecked::<Storage>();
+        storage
+            .delete(&id)
+            .await
+            .map_err(UploadError::Store)
+            .extend()
+    }
+
+    /// Rename an uploaded file and replace its tags, each if specified.
+    /// Returns the updated file, or null if there is no such file.
+    async fn update_upload_metadata(
+        &self,
+        ctx: &Context<'_>,
+        id: ID,
+        filename: Option<String>,
+        tags: Option<Vec<String>>,
+    ) -> Option<FileInfo> {
+        let storage = ctx.data_unchecked::<Storage>();
+        storage
+            .update_metadata(&id, filename.as_deref(), tags)
+            .await
+    }
+
+    /// Start a chunked upload of a file of `size` bytes, returns the id that
+    /// the chunks are sent to.
+    async fn begin_upload(&self, ctx: &Context<'_>, filename: String, size: Long) -> Result<ID> {
+        let storage = ctx.data_unchecked::<Storage>();
+        let upload_id = storage
+            .begin_upload(&filename, size.0, &policy(ctx))
+            .await
+            .extend()?;
+        Ok(upload_id.into())
+    }
+
+    /// Append a chunk to a chunked upload, starting at `offset`, the number of
+    /// bytes received so far. Returns the number of bytes received with the
+    /// chunk.
+    async fn upload_chunk(
+        &self,
+        ctx: &Context<'_>,
+        upload_id: ID,
+        offset: Long,
+        chunk: Upload,
+    ) -> Result<Long> {
+        let storage = ctx.data_unchecked::<Storage>();
+        let chunk = UploadFile::new(ctx, &chunk)
+            .map_err(UploadError::Invalid)
+            .extend()?;
+        storage
+            .upload_chunk(&upload_id, offset.0, chunk)
+            .await
+            .map(Long)
+            .extend()
+    }
+
+    /// Complete a chunked upload once all its chunks were received, `sha256`
+    /// is the SHA-256 digest of the whole content, in hex.
+    async fn complete_upload(
+        &self,
+        ctx: &Context<'_>,
+        upload_id: ID,
+        sha256: String,
+    ) -> Result<FileInfo> {
+        let storage = ctx.data_unchecked::<Storage>();
+        storage
+            .complete_upload(&upload_id, &sha256, &policy(ctx))
+            .await
+            .extend()
     }
 }
+
+pub struct SubscriptionRoot;
+
+#[Subscription]
+impl SubscriptionRoot {
+    /// The progress of the upload `uploadId`, either a chunked upload or the
+    /// id passed to `singleUpload` or `multipleUpload`, which may be
+    /// subscribed to before the upload is sent. The bytes of those are counted
+    /// as the request is received if it has an `Upload-Id` header with the same
+    /// id. It ends once the upload is complete, or with an error if it fails or
+    /// doesn't start within a minute.
+    async fn upload_progress(
+        &self,
+        ctx: &Context<'_>,
+        upload_id: ID,
+    ) -> impl Stream<Item = Result<UploadProgress>> {
+        let storage = ctx.data_unchecked::<Storage>();
+        storage.upload_progress(&upload_id).await
+    }
+}
+
+/// The upload policy in the schema data, or a policy that accepts every
+/// upload.
+fn policy(ctx: &Context<'_>) -> UploadPolicy {
+    ctx.data_opt::<UploadPolicy>().cloned().unwrap_or_default()
+}
@@ -0,0 +1,526 @@
+//! Execution of the GraphQL multipart requests with the files passed to the
+//! resolvers as they are received.
+
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    sync::Mutex,
+};
+
+use async_graphql::{
+    Context, Executor, ParseRequestError, Request, Response, Upload, UploadValue, Value,
+    http::{MultipartOptions, receive_body},
+};
+use bytes::Bytes;
+use futures::{
+    AsyncRead, AsyncReadExt, SinkExt, StreamExt, TryStreamExt,
+    channel::mpsc::{self, UnboundedReceiver, UnboundedSender},
+    future,
+    stream::{self, BoxStream},
+};
+use multer::{Constraints, Multipart, SizeLimit};
+
+use crate::download::CHUNK_SIZE;
+
+/// What is sent to the resolver that reads a file.
+enum Message {
+    Start { filename: String },
+    Chunk(Bytes),
+    End,
+}
+
+type Sender = mpsc::Sender<io::Result<Message>>;
+type Receiver = mpsc::Receiver<io::Result<Message>>;
+
+/// The files of a request executed by [`execute`], put into its data.
+struct Files {
+    /// The files that no resolver read yet, by the index of their `Upload`.
+    receivers: Mutex<HashMap<usize, Receiver>>,
+    /// Where the resolvers send the index of the file they start reading.
+    claims: UnboundedSender<usize>,
+}
+
+/// A file sent for an `Upload` argument, read as a stream of bytes.
+pub struct UploadFile(Source);
+
+enum Source {
+    /// A file that async-graphql wrote to a temporary file.
+    Buffered(UploadValue),
+    /// A file of a request executed by [`execute`], received once it is read.
+    Streamed {
+        index: usize,
+        receiver: Receiver,
+        claims: UnboundedSender<usize>,
+    },
+}
+
+impl UploadFile {
+    /// The file of the `Upload` argument `upload`.
+    ///
+    /// It is read from the body of the request as it is received if the
+    /// request is executed with [`execute`], and from the temporary file that
+    /// async-graphql wrote it to otherwise. It can only be read once.
+    pub fn new(ctx: &Context<'_>, upload: &Upload) -> io::Result<Self> {
+        let Some(files) = ctx.data_opt::<Files>() else {
+            return upload.value(ctx).map(Self::from);
+        };
+        let receiver = files
+            .receivers
+            .lock()
+            .unwrap()
+            .remove(&upload.0)
+            .ok_or_else(|| io::Error::other("the file was already read"))?;
+        Ok(Self(Source::Streamed {
+            index: upload.0,
+            receiver,
+            claims: files.claims.clone(),
+        }))
+    }
+
+    /// Waits for the file to be received, returns its name and its content.
+    pub(crate) async fn open(self) -> io::Result<(String, Box<dyn AsyncRead + Send + Unpin>)> {
+        let (index, mut receiver, claims) = match self.0 {
+            Source::Buffered(upload) => {
+                return Ok((upload.filename.clone(), Box::new(upload.into_async_read())));
+            }
+            Source::Streamed {
+                index,
+                receiver,
+                claims,
+            } => (index, receiver, claims),
+        };
+        let _ = claims.unbounded_send(index);
+        let filename = match receiver.next().await {
+            Some(Ok(Message::Start { filename })) => filename,
+            Some(Err(err)) => return Err(err),
+            _ => return Err(not_received()),
+        };
+        let content = stream::try_unfold(receiver, |mut receiver| async move {
+            match receiver.next().await {
+                Some(Ok(Message::Chunk(chunk))) => Ok(Some((chunk, receiver))),
+                Some(Ok(Message::End)) => Ok(None),
+                Some(Err(err)) => Err(err),
+                _ => Err(not_received()),
+            }
+        });
+        Ok((filename, Box::new(Box::pin(content).into_async_read())))
+    }
+}
+
+impl From<UploadValue> for UploadFile {
+    fn from(upload: UploadValue) -> Self {
+        Self(Source::Buffered(upload))
+    }
+}
+
+fn not_received() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        "the file was not received whole",
+    )
+}
+
+/// Receives a GraphQL request of the content type `content_type` from `body`
+/// and executes it.
+///
+/// Unlike with [`receive_body`], which writes each file of a multipart request
+/// to a temporary file before the request is executed, the request is
+/// executed once its `operations` and `map` parts are received and the files
+/// are passed to the resolvers as the rest of the body is received, see
+/// [`UploadFile`]. The files must be sent in the order that the resolvers read
+/// them: a file that is sent before the one that a resolver waits for can't
+/// be read.
+///
+/// The files larger than the maximum file size of `options` fail the request
+/// with [`ParseRequestError::PayloadTooLarge`].
+pub async fn execute<E: Executor>(
+    executor: &E,
+    content_type: Option<&str>,
+    body: impl AsyncRead + Send,
+    options: MultipartOptions,
+) -> Result<Response, ParseRequestError> {
+    let Some(boundary) =
+        content_type.and_then(|content_type| multer::parse_boundary(content_type).ok())
+    else {
+        let request = receive_body(content_type, body, options).await?;
+        return Ok(executor.execute(request).await);
+    };
+
+    let mut limit = SizeLimit::new();
+    if let Some(max_file_size) = options.max_file_size {
+        limit = limit.per_field(max_file_size as u64);
+        if let Some(max_num_files) = options.max_num_files {
+            limit = limit.whole_stream((max_file_size * max_num_files) as u64);
+        }
+    }
+    let mut multipart =
+        Multipart::with_constraints(chunks(body), boundary, Constraints::new().size_limit(limit));
+
+    let mut request = None;
+    let mut map = None;
+    while request.is_none() || map.is_none() {
+        let Some(field) = multipart.next_field().await? else {
+            break;
+        };
+        match field.name() {
+            Some("operations") => {
+                let content_type = field
+                    .content_type()
+                    .map_or_else(|| "application/json".to_string(), ToString::to_string);
+                let operations = field.bytes().await?;
+                request = Some(receive_body(Some(content_type), &operations[..], options).await?);
+            }
+            Some("map") => {
+                let files = field.bytes().await?;
+                map = Some(
+                    serde_json::from_slice::<HashMap<String, Vec<String>>>(&files)
+                        .map_err(|err| ParseRequestError::InvalidFilesMap(Box::new(err)))?,
+                );
+            }
+            _ if field.file_name().is_some() => {
+                return Err(ParseRequestError::InvalidRequest(
+                    "the files must be sent after the `operations` and `map` parts".into(),
+                ));
+            }
+            _ => {}
+        }
+    }
+    let mut request = request.ok_or(ParseRequestError::MissingOperatorsPart)?;
+    let map = map.ok_or(ParseRequestError::MissingMapPart)?;
+
+    let (claims, claimed) = mpsc::unbounded();
+    let mut senders = HashMap::new();
+    let mut receivers = HashMap::new();
+    for (index, (name, paths)) in map.into_iter().enumerate() {
+        for path in paths {
+            if let Some(variable) = variable(&mut request, &path) {
+                *variable = Value::String(format!("#__graphql_file__:{index}"));
+            }
+        }
+        let (sender, receiver) = mpsc::channel(1);
+        senders.insert(name, (index, sender));
+        receivers.insert(index, receiver);
+    }
+    let request = request.data(Files {
+        receivers: Mutex::new(receivers),
+        claims,
+    });
+
+    let (response, received) = future::join(
+        executor.execute(request),
+        send_files(multipart, senders, claimed),
+    )
+    .await;
+    received?;
+    Ok(response)
+}
+
+/// The body of a request, read in chunks.
+fn chunks<'a>(body: impl AsyncRead + Send + 'a) -> BoxStream<'a, io::Result<Bytes>> {
+    stream::try_unfold(Box::pin(body), |mut body| async move {
+        let mut buf = vec![0; CHUNK_SIZE];
+        let n = body.read(&mut buf).await?;
+        buf.truncate(n);
+        Ok((n > 0).then(|| (Bytes::from(buf), body)))
+    })
+    .boxed()
+}
+
+/// The variable at `path`, such as `variables.files.0`.
+fn variable<'a>(request: &'a mut Request, path: &str) -> Option<&'a mut Value> {
+    let mut parts = path.strip_prefix("variables.")?.split('.');
+    let variable = request.variables.get_mut(parts.next()?)?;
+    parts.try_fold(variable, |value, part| match value {
+        Value::List(list) => list.get_mut(part.parse::<usize>().ok()?),
+        Value::Object(object) => object.get_mut(part),
+        _ => None,
+    })
+}
+
+/// Sends each file of the rest of the body to the resolver that reads it, as
+/// it is received. `senders` are the files by the name of their part, and
+/// `claimed` receives the index of the files that the resolvers start to read,
+/// it ends once the request is executed.
+///
+/// A file waits for a resolver to read it, unless a resolver waits for a file
+/// that is sent later or the request is executed. Then it is skipped.
+async fn send_files(
+    mut multipart: Multipart<'_>,
+    mut senders: HashMap<String, (usize, Sender)>,
+    mut claimed: UnboundedReceiver<usize>,
+) -> Result<(), ParseRequestError> {
+    let mut claims = HashSet::new();
+    let mut executing = true;
+    while let Some(mut field) = multipart.next_field().await? {
+        let Some((index, mut sender)) = field.name().and_then(|name| senders.remove(name)) else {
+            continue;
+        };
+        while executing && !claims.contains(&index) {
+            match claimed.next().await {
+                Some(claim) => {
+                    claims.insert(claim);
+                    if senders.values().any(|(later, _)| *later == claim) {
+                        break;
+                    }
+                }
+                None => executing = false,
+            }
+        }
+        if !claims.contains(&index) {
+            // Each sender has a place in the channel, this doesn't wait.
+            let _ = sender.try_send(Err(io::Error::other(
+                "the file was sent before a file that is read first",
+            )));
+            continue;
+        }
+
+        let filename = field.file_name().unwrap_or_default().to_string();
+        let mut reading = sender.send(Ok(Message::Start { filename })).await.is_ok();
+        while let Some(chunk) = field.chunk().await.transpose() {
+            match chunk {
+                Ok(chunk) if reading => {
+                    reading = sender.send(Ok(Message::Chunk(chunk))).await.is_ok();
+                }
+                Ok(_) => {}
+                Err(err) => {
+                    let _ = sender.try_send(Err(io::Error::other(err.to_string())));
+                    return Err(err.into());
+                }
+            }
+        }
+        if reading {
+            let _ = sender.send(Ok(Message::End)).await;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use async_graphql::{Schema, value};
+    use futures::executor::block_on;
+
+    use super::*;
+    use crate::{FilesSchema, MutationRoot, QueryRoot, Storage, SubscriptionRoot, UploadPolicy};
+
+    const CONTENT_TYPE: &str = "multipart/form-data; boundary=BOUNDARY";
+
+    fn schema(policy: UploadPolicy) -> FilesSchema {
+        Schema::build(QueryRoot, MutationRoot, SubscriptionRoot)
+            .data(Storage::memory())
+            .data(policy)
+            .finish()
+    }
+
+    /// A multipart request with the parts `(name, filename, content)`.
+    fn body(parts: &[(&str, Option<&str>, &str)]) -> Vec<u8> {
+        let mut body = String::new();
+        for (name, filename, content) in parts {
+            body.push_str("--BOUNDARY\r\nContent-Disposition: form-data; name=\"");
+            body.push_str(name);
+            if let Some(filename) = filename {
+                body.push_str("\"; filename=\"");
+                body.push_str(filename);
+            }
+            body.push_str("\"\r\n\r\n");
+            body.push_str(content);
+            body.push_str("\r\n");
+        }
+        body.push_str("--BOUNDARY--\r\n");
+        body.into_bytes()
+    }
+
+    fn run(
+        schema: &FilesSchema,
+        parts: &[(&str, Option<&str>, &str)],
+        options: MultipartOptions,
+    ) -> Result<Response, ParseRequestError> {
+        let body = body(parts);
+        block_on(execute(schema, Some(CONTENT_TYPE), &body[..], options))
+    }
+
+    const MULTIPLE_UPLOAD: &str = r#"{"query":"mutation($files: [Upload!]!) { multipleUpload(files: $files) { filename size sha256 } }","variables":{"files":[null,null]}}"#;
+
+    #[test]
+    fn streamed_files() {
+        let schema = schema(UploadPolicy::default());
+        let response = run(
+            &schema,
+            &[
+                ("operations", None, MULTIPLE_UPLOAD),
+                (
+                    "map",
+                    None,
+                    r#"{"a":["variables.files.0"],"b":["variables.files.1"]}"#,
+                ),
+                ("a", Some("a.txt"), "hello"),
+                ("b", Some("../b.txt"), "world!"),
+            ],
+            MultipartOptions::default(),
+        )
+        .unwrap();
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        assert_eq!(
+            response.data,
+            value!({
+                "multipleUpload": [
+                    {
+                        "filename": "a.txt",
+                        "size": 5,
+                        "sha256": "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824",
+                    },
+                    {
+                        "filename": "b.txt",
+                        "size": 6,
+                        "sha256": "711e9609339e92b03ddc0a211827dba421f38f9ed8b9d806e1ffdd8c15ffa03d",
+                    },
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn files_sent_out_of_order() {
+        let schema = schema(UploadPolicy::default());
+        let response = run(
+            &schema,
+            &[
+                ("operations", None, MULTIPLE_UPLOAD),
+                (
+                    "map",
+                    None,
+                    r#"{"a":["variables.files.0"],"b":["variables.files.1"]}"#,
+                ),
+                ("b", Some("b.txt"), "world!"),
+                ("a", Some("a.txt"), "hello"),
+            ],
+            MultipartOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            response.errors[0].message,
+            "invalid upload: the file was sent before a file that is read first"
+        );
+        let response = block_on(schema.execute("{ uploads { edges { cursor } } }"));
+        assert_eq!(response.data, value!({ "uploads": { "edges": [] } }));
+    }
+
+    #[test]
+    fn missing_file() {
+        let schema = schema(UploadPolicy::default());
+        let response = run(
+            &schema,
+            &[
+                ("operations", None, MULTIPLE_UPLOAD),
+                (
+                    "map",
+                    None,
+                    r#"{"a":["variables.files.0"],"b":["variables.files.1"]}"#,
+                ),
+                ("a", Some("a.txt"), "hello"),
+            ],
+            MultipartOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            response.errors[0].message,
+            "invalid upload: the file was not received whole"
+        );
+    }
+
+    #[test]
+    fn file_rejected_by_the_policy() {
+        let schema = schema(UploadPolicy::default().max_file_size(5));
+        let single_upload = r#"{"query":"mutation($file: Upload!) { singleUpload(file: $file) { size } }","variables":{"file":null}}"#;
+        let map = r#"{"0":["variables.file"]}"#;
+
+        let response = run(
+            &schema,
+            &[
+                ("operations", None, single_upload),
+                ("map", None, map),
+                ("0", Some("a.txt"), "hello world"),
+            ],
+            MultipartOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(response.errors[0].message, "`a.txt` is larger than 5 bytes");
+
+        // With the multipart options of the policy, the request fails.
+        let err = run(
+            &schema,
+            &[
+                ("operations", None, single_upload),
+                ("map", None, map),
+                ("0", Some("a.txt"), "hello world"),
+            ],
+            UploadPolicy::default().max_file_size(5).multipart_options(),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ParseRequestError::PayloadTooLarge));
+    }
+
+    #[test]
+    fn streamed_chunk() {
+        let schema = schema(UploadPolicy::default());
+        let response =
+            block_on(schema.execute(r#"mutation { beginUpload(filename: "a.txt", size: 11) }"#));
+        let upload_id = response.data.into_json().unwrap()["beginUpload"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        let operations = format!(
+            r#"{{"query":"mutation($chunk: Upload!) {{ uploadChunk(uploadId: \"{upload_id}\", offset: 0, chunk: $chunk) }}","variables":{{"chunk":null}}}}"#
+        );
+        let response = run(
+            &schema,
+            &[
+                ("operations", None, &operations),
+                ("map", None, r#"{"0":["variables.chunk"]}"#),
+                ("0", Some("blob"), "hello world"),
+            ],
+            MultipartOptions::default(),
+        )
+        .unwrap();
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        assert_eq!(response.data, value!({ "uploadChunk": 11 }));
+    }
+
+    #[test]
+    fn parts_out_of_order() {
+        let schema = schema(UploadPolicy::default());
+        let err = run(
+            &schema,
+            &[
+                ("0", Some("a.txt"), "hello"),
+                ("operations", None, MULTIPLE_UPLOAD),
+                ("map", None, r#"{"0":["variables.files.0"]}"#),
+            ],
+            MultipartOptions::default(),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ParseRequestError::InvalidRequest(_)));
+
+        let err = run(
+            &schema,
+            &[("operations", None, MULTIPLE_UPLOAD)],
+            MultipartOptions::default(),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ParseRequestError::MissingMapPart));
+    }
+
+    #[test]
+    fn json_request() {
+        let schema = schema(UploadPolicy::default());
+        let body = br#"{"query":"{ uploads { edges { cursor } } }"}"#;
+        let response = block_on(execute(
+            &schema,
+            Some("application/json"),
+            &body[..],
+            MultipartOptions::default(),
+        ))
+        .unwrap();
+        assert_eq!(response.data, value!({ "uploads": { "edges": [] } }));
+    }
+}
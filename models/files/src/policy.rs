@@ -0,0 +1,518 @@
+use std::io;
+
+use async_graphql::{Error, ErrorExtensions, http::MultipartOptions};
+use thiserror::Error;
+
+/// The number of bytes that the MIME type of a content is detected from.
+pub(crate) const SNIFF_LEN: u64 = 512;
+
+/// The limits that the uploads must respect.
+///
+/// Put it into the schema data next to the [`Storage`](crate::Storage),
+/// without it every upload is accepted:
+///
+/// ```ignore
+/// let schema = Schema::build(QueryRoot, MutationRoot, SubscriptionRoot)
+///     .data(Storage::default())
+///     .data(
+///         UploadPolicy::default()
+///             .max_file_size(10 * 1024 * 1024)
+///             .allowed_types(["image/*", "application/pdf"]),
+///     )
+///     .finish();
+/// ```
+#[derive(Clone, Default)]
+pub struct UploadPolicy {
+    max_file_size: Option<u64>,
+    max_total_size: Option<u64>,
+    allowed_types: Option<Vec<String>>,
+}
+
+impl UploadPolicy {
+    /// Set the maximum size of a file, in bytes.
+    pub fn max_file_size(self, max_file_size: u64) -> Self {
+        Self {
+            max_file_size: Some(max_file_size),
+            ..self
+        }
+    }
+
+    /// Set the maximum size of all the files of a request, in bytes.
+    pub fn max_total_size(self, max_total_size: u64) -> Self {
+        Self {
+            max_total_size: Some(max_total_size),
+            ..self
+        }
+    }
+
+    /// Only accept the files whose content is of one of these MIME types, a
+    /// type ending with `/*` accepts all its subtypes.
+    ///
+    /// The type is detected from the first bytes of the content, the content
+    /// type sent by the client is ignored.
+    pub fn allowed_types(self, allowed_types: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        Self {
+            allowed_types: Some(allowed_types.into_iter().map(Into::into).collect()),
+            ..self
+        }
+    }
+
+    /// The options to parse the multipart requests with, so that the files
+    /// larger than the maximum file size are rejected while they are
+    /// received by [`execute`](crate::execute), before they reach the store.
+    ///
+    /// The request is then rejected as a bad request rather than with a
+    /// GraphQL error.
+    pub fn multipart_options(&self) -> MultipartOptions {
+        let options = MultipartOptions::default();
+        match self.max_file_size {
+            Some(max_size) => {
+                options.max_file_size(usize::try_from(max_size).unwrap_or(usize::MAX))
+            }
+            None => options,
+        }
+    }
+
+    /// Checks the size of the file `filename` and the total size of the
+    /// files of its request, both read so far.
+    pub(crate) fn check_size(
+        &self,
+        filename: &str,
+        size: u64,
+        total: u64,
+    ) -> Result<(), UploadError> {
+        if let Some(max_size) = self.max_file_size.filter(|max_size| size > *max_size) {
+            return Err(UploadError::FileTooLarge {
+                filename: filename.to_string(),
+                max_size,
+            });
+        }
+        match self.max_total_size {
+            Some(max_size) if total > max_size => Err(UploadError::TotalTooLarge { max_size }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks the MIME type detected from the content of the file `filename`.
+    pub(crate) fn check_type(&self, filename: &str, mime_type: &str) -> Result<(), UploadError> {
+        let Some(allowed_types) = &self.allowed_types else {
+            return Ok(());
+        };
+        let allowed = allowed_types
+            .iter()
+            .any(|allowed| mime_type_matches(allowed, mime_type));
+        if allowed {
+            Ok(())
+        } else {
+            Err(UploadError::UnsupportedType {
+                filename: filename.to_string(),
+                mime_type: mime_type.to_string(),
+            })
+        }
+    }
+}
+
+/// The reasons an upload is rejected.
+#[derive(Debug, Error)]
+pub enum UploadError {
+    #[error("invalid upload: {0}")]
+    Invalid(io::Error),
+
+    #[error("`{filename}` is larger than {max_size} bytes")]
+    FileTooLarge { filename: String, max_size: u64 },
+
+    #[error("the files are larger than {max_size} bytes in total")]
+    TotalTooLarge { max_size: u64 },
+
+    #[error("`{filename}` is of the unsupported type {mime_type}")]
+    UnsupportedType { filename: String, mime_type: String },
+
+    #[error("failed to store the file: {0}")]
+    Store(io::Error),
+
+    #[error("the upload doesn't exist or has expired")]
+    UnknownUpload,
+
+    #[error("the chunk must start at offset {expected}")]
+    InvalidOffset { expected: u64 },
+
+    #[error("the chunk goes past the size of the file, {size} bytes")]
+    ChunkOutOfBounds { size: u64 },
+
+    #[error("only {received} of the {size} bytes of the file were received")]
+    Incomplete { received: u64, size: u64 },
+
+    #[error("the SHA-256 digest of the content is {actual}")]
+    ChecksumMismatch { actual: String },
+}
+
+impl ErrorExtensions for UploadError {
+    fn extend(&self) -> Error {
+        self.extend_with(|err, e| match err {
+            UploadError::Invalid(_) => e.set("code", "INVALID_UPLOAD"),
+            UploadError::FileTooLarge { max_size, .. } => {
+                e.set("code", "FILE_TOO_LARGE");
+                e.set("maxSize", *max_size);
+            }
+            UploadError::TotalTooLarge { max_size } => {
+                e.set("code", "UPLOAD_TOO_LARGE");
+                e.set("maxSize", *max_size);
+            }
+            UploadError::UnsupportedType { mime_type, .. } => {
+                e.set("code", "UNSUPPORTED_MEDIA_TYPE");
+                e.set("mimeType", mime_type.as_str());
+            }
+            UploadError::Store(_) => e.set("code", "STORAGE_ERROR"),
+            UploadError::UnknownUpload => e.set("code", "UPLOAD_NOT_FOUND"),
+            UploadError::InvalidOffset { expected } => {
+                e.set("code", "INVALID_OFFSET");
+                e.set("expectedOffset", *expected);
+            }
+            UploadError::ChunkOutOfBounds { size } => {
+                e.set("code", "CHUNK_OUT_OF_BOUNDS");
+                e.set("size", *size);
+            }
+            UploadError::Incomplete { received, size } => {
+                e.set("code", "INCOMPLETE_UPLOAD");
+                e.set("received", *received);
+                e.set("size", *size);
+            }
+            UploadError::ChecksumMismatch { actual } => {
+                e.set("code", "CHECKSUM_MISMATCH");
+                e.set("sha256", actual.as_str());
+            }
+        })
+    }
+}
+
+/// Whether `mime_type` is the MIME type `pattern`, or one of its subtypes if
+/// `pattern` ends with `/*`.
+pub(crate) fn mime_type_matches(pattern: &str, mime_type: &str) -> bool {
+    match pattern.strip_suffix("/*") {
+        Some(type_) => mime_type.split('/').next() == Some(type_),
+        None => pattern == mime_type,
+    }
+}
+
+/// Detects the MIME type of a content from its first bytes, or returns
+/// `application/octet-stream` if it is not recognized.
+pub(crate) fn sniff(head: &[u8]) -> &'static str {
+    const SIGNATURES: &[(&[u8], &str)] = &[
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"\xff\xd8\xff", "image/jpeg"),
+        (b"GIF87a", "image/gif"),
+        (b"GIF89a", "image/gif"),
+        (b"%PDF-", "application/pdf"),
+        (b"PK\x03\x04", "application/zip"),
+        (b"\x1f\x8b", "application/gzip"),
+    ];
+
+    if let Some((_, mime_type)) = SIGNATURES
+        .iter()
+        .find(|(signature, _)| head.starts_with(signature))
+    {
+        return mime_type;
+    }
+    if head.starts_with(b"RIFF") && head.get(8..12) == Some(b"WEBP") {
+        return "image/webp";
+    }
+    // A multibyte character may be cut at the end of the head.
+    let is_text = match std::str::from_utf8(head) {
+        Ok(_) => true,
+        Err(err) => err.error_len().is_none(),
+    };
+    if !head.is_empty() && is_text && !head.contains(&0) {
+        "text/plain"
+    } else {
+        "application/octet-stream"
+    }
+}
+
+/// Normalizes a filename sent by the client so that it is safe to use as a
+/// file name: only the last component of a path is kept, control and
+/// reserved characters are replaced, leading and trailing dots and spaces are
+/// removed and the length is limited to 255 bytes.
+pub fn sanitize_filename(filename: &str) -> String {
+    let name = filename.rsplit(['/', '\\']).next().unwrap_or_default();
+    let name: String = name
+        .chars()
+        .map(|c| {
+            if c.is_control() || matches!(c, '<' | '>' | ':' | '"' | '|' | '?' | '*') {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect();
+    let mut name = name.trim_matches(['.', ' ']).to_string();
+    if name.len() > 255 {
+        let mut end = 255;
+        while !name.is_char_boundary(end) {
+            end -= 1;
+        }
+        name.truncate(end);
+    }
+    if name.is_empty() {
+        "file".to_string()
+    } else {
+        name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Seek, Write};
+
+    use async_graphql::{ErrorExtensionValues, UploadValue, Value};
+    use futures::executor::block_on;
+
+    use super::*;
+    use crate::{Storage, UploadFile};
+
+    #[test]
+    fn sanitized_filenames() {
+        assert_eq!(sanitize_filename("report.pdf"), "report.pdf");
+        assert_eq!(sanitize_filename("../../etc/passwd"), "passwd");
+        assert_eq!(sanitize_filename("..\\..\\boot.ini"), "boot.ini");
+        assert_eq!(sanitize_filename("/var/www/index.html"), "index.html");
+        assert_eq!(sanitize_filename("C:\\Users\\me\\notes.txt"), "notes.txt");
+        assert_eq!(sanitize_filename("a\0b.txt"), "a_b.txt");
+        assert_eq!(sanitize_filename("line\nbreak\t.txt"), "line_break_.txt");
+        assert_eq!(sanitize_filename("a<b>:c|d?e*\".txt"), "a_b__c_d_e__.txt");
+        assert_eq!(sanitize_filename(" .hidden. "), "hidden");
+    }
+
+    #[test]
+    fn empty_filenames() {
+        for filename in ["", ".", "..", "...", " . ", "dir/", "../..", "/"] {
+            assert_eq!(sanitize_filename(filename), "file", "{filename:?}");
+        }
+    }
+
+    #[test]
+    fn long_filenames() {
+        assert_eq!(sanitize_filename(&"a".repeat(300)), "a".repeat(255));
+        // The name is cut before the multibyte character that crosses the
+        // limit.
+        assert_eq!(sanitize_filename(&"é".repeat(200)), "é".repeat(127));
+    }
+
+    #[test]
+    fn sniffed_types() {
+        assert_eq!(sniff(b"\x89PNG\r\n\x1a\n...."), "image/png");
+        assert_eq!(sniff(b"\xff\xd8\xff\xe0"), "image/jpeg");
+        assert_eq!(sniff(b"GIF89a"), "image/gif");
+        assert_eq!(sniff(b"RIFF\0\0\0\0WEBPVP8 "), "image/webp");
+        assert_eq!(sniff(b"RIFF\0\0\0\0WAVEfmt "), "application/octet-stream");
+        assert_eq!(sniff(b"%PDF-1.7"), "application/pdf");
+        assert_eq!(sniff(b"PK\x03\x04"), "application/zip");
+        assert_eq!(sniff(b"\x1f\x8b\x08"), "application/gzip");
+        assert_eq!(sniff("héllo".as_bytes()), "text/plain");
+        // A multibyte character cut at the end of the head is still text.
+        assert_eq!(sniff(&"héllo".as_bytes()[..2]), "text/plain");
+        assert_eq!(sniff(b"a\0b"), "application/octet-stream");
+        assert_eq!(sniff(b"\xff\xfe"), "application/octet-stream");
+        assert_eq!(sniff(b""), "application/octet-stream");
+    }
+
+    fn upload(filename: &str, content_type: &str, content: &[u8]) -> UploadFile {
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(content).unwrap();
+        file.rewind().unwrap();
+        UploadValue {
+            filename: filename.to_string(),
+            content_type: Some(content_type.to_string()),
+            content: file,
+        }
+        .into()
+    }
+
+    #[test]
+    fn types_are_sniffed_from_the_content() {
+        let storage = Storage::memory();
+        let policy = UploadPolicy::default().allowed_types(["image/*"]);
+
+        // The extension and the content type sent by the client are ignored.
+        let files = block_on(storage.upload(
+            vec![upload("notes.txt", "text/plain", b"\x89PNG\r\n\x1a\n")],
+            &policy,
+            None,
+        ))
+        .unwrap();
+        assert_eq!(files[0].filename, "notes.txt");
+        assert_eq!(files[0].mime_type, "image/png");
+
+        let err = block_on(storage.upload(
+            vec![upload("photo.png", "image/png", b"not an image")],
+            &policy,
+            None,
+        ))
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            UploadError::UnsupportedType { filename, mime_type }
+                if filename == "photo.png" && mime_type == "text/plain"
+        ));
+        assert_eq!(block_on(storage.files()).len(), 1);
+    }
+
+    #[test]
+    fn allowed_types() {
+        let policy = UploadPolicy::default().allowed_types(["image/*", "application/pdf"]);
+        policy.check_type("a.png", "image/png").unwrap();
+        policy.check_type("a.webp", "image/webp").unwrap();
+        policy.check_type("a.pdf", "application/pdf").unwrap();
+        for mime_type in ["text/plain", "application/zip", "application/octet-stream"] {
+            let err = policy.check_type("a", mime_type).unwrap_err();
+            assert!(matches!(err, UploadError::UnsupportedType { .. }));
+        }
+        // `image/*` doesn't match a type that only starts the same way.
+        let err = policy.check_type("a", "imagex/png").unwrap_err();
+        assert!(matches!(err, UploadError::UnsupportedType { .. }));
+
+        let none = UploadPolicy::default().allowed_types(Vec::<String>::new());
+        assert!(none.check_type("a.png", "image/png").is_err());
+        UploadPolicy::default()
+            .check_type("a", "application/octet-stream")
+            .unwrap();
+    }
+
+    #[test]
+    fn size_limits() {
+        let policy = UploadPolicy::default().max_file_size(10).max_total_size(15);
+        policy.check_size("a", 10, 10).unwrap();
+        policy.check_size("b", 5, 15).unwrap();
+        assert!(matches!(
+            policy.check_size("a", 11, 11).unwrap_err(),
+            UploadError::FileTooLarge { filename, max_size: 10 } if filename == "a"
+        ));
+        assert!(matches!(
+            policy.check_size("b", 6, 16).unwrap_err(),
+            UploadError::TotalTooLarge { max_size: 15 }
+        ));
+        UploadPolicy::default()
+            .check_size("a", u64::MAX, u64::MAX)
+            .unwrap();
+    }
+
+    #[test]
+    fn size_limits_of_an_upload() {
+        let storage = Storage::memory();
+        let policy = UploadPolicy::default().max_file_size(10).max_total_size(15);
+
+        let files = block_on(storage.upload(
+            vec![upload("a", "", &[b'a'; 10]), upload("b", "", &[b'b'; 5])],
+            &policy,
+            None,
+        ))
+        .unwrap();
+        assert_eq!(files[0].size.0 + files[1].size.0, 15);
+
+        let err = block_on(storage.upload(vec![upload("a", "", &[b'a'; 11])], &policy, None))
+            .unwrap_err();
+        assert!(matches!(err, UploadError::FileTooLarge { .. }));
+
+        let err = block_on(storage.upload(
+            vec![upload("a", "", &[b'a'; 10]), upload("b", "", &[b'b'; 6])],
+            &policy,
+            None,
+        ))
+        .unwrap_err();
+        assert!(matches!(err, UploadError::TotalTooLarge { .. }));
+
+        // Nothing is recorded from the rejected requests.
+        assert_eq!(block_on(storage.files()).len(), 2);
+    }
+
+    #[test]
+    fn multipart_options() {
+        let options = UploadPolicy::default()
+            .max_file_size(10)
+            .multipart_options();
+        assert_eq!(options.max_file_size, Some(10));
+        assert_eq!(options.max_num_files, None);
+        let options = UploadPolicy::default().multipart_options();
+        assert_eq!(options.max_file_size, None);
+    }
+
+    fn extensions(values: Vec<(&str, Value)>) -> ErrorExtensionValues {
+        let mut extensions = ErrorExtensionValues::default();
+        for (name, value) in values {
+            extensions.set(name, value);
+        }
+        extensions
+    }
+
+    fn code(code: &str) -> (&'static str, Value) {
+        ("code", Value::from(code))
+    }
+
+    #[test]
+    fn error_codes() {
+        let filename = || "a.txt".to_string();
+        let io_error = || io::Error::other("failed");
+        let cases = [
+            (
+                UploadError::Invalid(io_error()),
+                vec![code("INVALID_UPLOAD")],
+            ),
+            (
+                UploadError::FileTooLarge {
+                    filename: filename(),
+                    max_size: 10,
+                },
+                vec![code("FILE_TOO_LARGE"), ("maxSize", Value::from(10))],
+            ),
+            (
+                UploadError::TotalTooLarge { max_size: 20 },
+                vec![code("UPLOAD_TOO_LARGE"), ("maxSize", Value::from(20))],
+            ),
+            (
+                UploadError::UnsupportedType {
+                    filename: filename(),
+                    mime_type: "text/plain".to_string(),
+                },
+                vec![
+                    code("UNSUPPORTED_MEDIA_TYPE"),
+                    ("mimeType", Value::from("text/plain")),
+                ],
+            ),
+            (UploadError::Store(io_error()), vec![code("STORAGE_ERROR")]),
+            (UploadError::UnknownUpload, vec![code("UPLOAD_NOT_FOUND")]),
+            (
+                UploadError::InvalidOffset { expected: 5 },
+                vec![code("INVALID_OFFSET"), ("expectedOffset", Value::from(5))],
+            ),
+            (
+                UploadError::ChunkOutOfBounds { size: 8 },
+                vec![code("CHUNK_OUT_OF_BOUNDS"), ("size", Value::from(8))],
+            ),
+            (
+                UploadError::Incomplete {
+                    received: 3,
+                    size: 8,
+                },
+                vec![
+                    code("INCOMPLETE_UPLOAD"),
+                    ("received", Value::from(3)),
+                    ("size", Value::from(8)),
+                ],
+            ),
+            (
+                UploadError::ChecksumMismatch {
+                    actual: "ab".to_string(),
+                },
+                vec![code("CHECKSUM_MISMATCH"), ("sha256", Value::from("ab"))],
+            ),
+        ];
+        for (err, expected) in cases {
+            let err = err.extend();
+            assert_eq!(
+                err.extensions,
+                Some(extensions(expected)),
+                "{}",
+                err.message
+            );
+        }
+    }
+}
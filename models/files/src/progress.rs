@@ -0,0 +1,208 @@
+//! The progress of the uploads, sent to the `uploadProgress` subscribers.
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+
+use async_graphql::{Error, ErrorExtensions};
+use futures::Stream;
+use futures_timer::Delay;
+
+use crate::{Long, UploadError, UploadProgress};
+
+/// The time that a subscription to an upload that isn't known yet waits for
+/// its first progress, before it ends with an error.
+pub(crate) const SUBSCRIBE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The subscribers to the progress of the uploads, by upload id.
+#[derive(Clone, Default)]
+pub(crate) struct Progress {
+    subscribers: Arc<Mutex<HashMap<String, Vec<SharedSubscriber>>>>,
+}
+
+type SharedSubscriber = Arc<Mutex<Subscriber>>;
+
+impl Progress {
+    /// Subscribes to the progress of the upload `id`, starting with `current`
+    /// if it is already known.
+    ///
+    /// Otherwise the subscription ends with an error if no progress is sent
+    /// within [`SUBSCRIBE_TIMEOUT`].
+    pub(crate) fn subscribe(&self, id: &str, current: Option<UploadProgress>) -> ProgressStream {
+        let subscriber = Arc::new(Mutex::new(Subscriber {
+            timeout: current
+                .is_none()
+                .then(|| Box::pin(Delay::new(SUBSCRIBE_TIMEOUT))),
+            counted: current
+                .as_ref()
+                .map(|progress| (progress.bytes_received, progress.total_bytes)),
+            latest: current.map(Ok),
+            closed: false,
+            waker: None,
+        }));
+        let mut subscribers = self.subscribers.lock().unwrap();
+        subscribers
+            .entry(id.to_string())
+            .or_default()
+            .push(subscriber.clone());
+        ProgressStream {
+            subscriber,
+            progress: self.clone(),
+            id: id.to_string(),
+        }
+    }
+
+    /// Sends the progress of the upload `id` to its subscribers.
+    pub(crate) fn publish(&self, id: &str, progress: UploadProgress) {
+        self.send(id, Ok(progress), false);
+    }
+
+    /// Sends the last progress of the upload `id`, once its files are stored,
+    /// and ends the subscriptions.
+    ///
+    /// The subscribers that were sent the number of bytes received keep it,
+    /// since it may count more than the content of the files.
+    pub(crate) fn finish(&self, id: &str, progress: UploadProgress) {
+        self.send(id, Ok(progress), true);
+    }
+
+    /// Sends the error that the upload `id` failed with and ends the
+    /// subscriptions.
+    pub(crate) fn fail(&self, id: &str, err: &UploadError) {
+        self.send(id, Err(err.extend()), true);
+    }
+
+    fn send(&self, id: &str, event: Result<UploadProgress, Error>, close: bool) {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        let Some(entry) = subscribers.get(id) else {
+            return;
+        };
+        for subscriber in entry {
+            let mut subscriber = subscriber.lock().unwrap();
+            let mut event = event.clone();
+            if let Ok(progress) = &mut event {
+                match subscriber.counted {
+                    Some((bytes_received, total_bytes)) if close => {
+                        progress.bytes_received = bytes_received;
+                        progress.total_bytes = total_bytes;
+                    }
+                    _ => subscriber.counted = Some((progress.bytes_received, progress.total_bytes)),
+                }
+            }
+            subscriber.latest = Some(event);
+            subscriber.closed = close;
+            subscriber.timeout = None;
+            if let Some(waker) = subscriber.waker.take() {
+                waker.wake();
+            }
+        }
+        if close {
+            subscribers.remove(id);
+        }
+    }
+
+    /// Removes a subscriber whose stream was dropped.
+    fn unsubscribe(&self, id: &str, subscriber: &SharedSubscriber) {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        if let Some(entry) = subscribers.get_mut(id) {
+            entry.retain(|other| !Arc::ptr_eq(other, subscriber));
+            if entry.is_empty() {
+                subscribers.remove(id);
+            }
+        }
+    }
+}
+
+/// A subscriber to the progress of an upload, which only keeps the latest
+/// progress so that a slow client skips the intermediate ones.
+struct Subscriber {
+    latest: Option<Result<UploadProgress, Error>>,
+    /// The last number of bytes received and total number of bytes sent.
+    counted: Option<(Long, Option<Long>)>,
+    closed: bool,
+    /// Set until the first progress of an upload that wasn't known yet.
+    timeout: Option<Pin<Box<Delay>>>,
+    waker: Option<Waker>,
+}
+
+/// The progress of an upload, ending once the upload is complete or failed.
+pub struct ProgressStream {
+    subscriber: SharedSubscriber,
+    progress: Progress,
+    id: String,
+}
+
+impl Stream for ProgressStream {
+    type Item = Result<UploadProgress, Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut subscriber = self.subscriber.lock().unwrap();
+        if let Some(event) = subscriber.latest.take() {
+            return Poll::Ready(Some(event));
+        }
+        if subscriber.closed {
+            return Poll::Ready(None);
+        }
+        if let Some(timeout) = &mut subscriber.timeout
+            && timeout.as_mut().poll(cx).is_ready()
+        {
+            subscriber.timeout = None;
+            subscriber.closed = true;
+            return Poll::Ready(Some(Err(UploadError::UnknownUpload.extend())));
+        }
+        subscriber.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl Drop for ProgressStream {
+    fn drop(&mut self) {
+        self.progress.unsubscribe(&self.id, &self.subscriber);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{StreamExt, executor::block_on};
+
+    use super::*;
+
+    fn progress(bytes_received: u64, total_bytes: u64) -> UploadProgress {
+        UploadProgress {
+            bytes_received: bytes_received.into(),
+            total_bytes: Some(total_bytes.into()),
+            files: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn finish_keeps_the_counted_bytes() {
+        let progresses = Progress::default();
+        let mut stream = progresses.subscribe("id", None);
+        progresses.publish("id", progress(10, 120));
+        progresses.publish("id", progress(120, 120));
+        let event = block_on(stream.next()).unwrap().unwrap();
+        assert_eq!(event.bytes_received.0, 120);
+
+        progresses.finish("id", progress(100, 100));
+        let event = block_on(stream.next()).unwrap().unwrap();
+        assert_eq!(event.bytes_received.0, 120);
+        assert_eq!(event.total_bytes.unwrap().0, 120);
+        assert!(block_on(stream.next()).is_none());
+        assert!(progresses.subscribers.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn dropped_subscriptions_are_removed() {
+        let progresses = Progress::default();
+        let stream = progresses.subscribe("id", None);
+        assert_eq!(progresses.subscribers.lock().unwrap().len(), 1);
+        drop(stream);
+        assert!(progresses.subscribers.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,910 @@
+use std::{
+    collections::{BTreeMap, HashMap, hash_map::Entry},
+    fs::{self, File},
+    io::{self, Cursor, Read, Seek, SeekFrom, Write},
+    mem,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use blocking::unblock;
+use futures::{AsyncRead, AsyncReadExt, Stream, TryStreamExt, lock::Mutex};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    Download, FileInfo, Long, UploadError, UploadFile, UploadFilter, UploadPolicy, UploadProgress,
+    chunked::{DEFAULT_TTL, Sessions},
+    download::CHUNK_SIZE,
+    policy::{SNIFF_LEN, sanitize_filename, sniff},
+    progress::{Progress, ProgressStream},
+};
+
+/// The content of a stored file.
+pub trait Content: Read + Seek + Send {}
+
+impl<T: Read + Seek + Send> Content for T {}
+
+/// A store that the contents of the uploaded files are written to.
+pub trait FileStore: Send + Sync {
+    /// Starts writing the content of a new file, which is only stored once
+    /// the returned writer is committed.
+    fn create(&self) -> io::Result<Box<dyn FileWriter>>;
+
+    /// Opens the content of the file with the specified id, returns `None` if
+    /// it does not exist.
+    fn open(&self, id: &str) -> io::Result<Option<Box<dyn Content>>>;
+
+    /// Removes the content of the file with the specified id, if it exists.
+    fn remove(&self, id: &str) -> io::Result<()>;
+}
+
+/// The content of a new file, as it is written. It is discarded if the
+/// writer is dropped without being committed.
+pub trait FileWriter: Write + Send {
+    /// Stores everything written as the content of the file with the
+    /// specified id. Fails with [`io::ErrorKind::AlreadyExists`] if the store
+    /// already has a file with that id, which is left as it is, the content
+    /// can then be committed with another id.
+    fn commit(&mut self, id: &str) -> io::Result<()>;
+
+    /// Drops what was written after the first `len` bytes, the next writes
+    /// continue from there.
+    fn truncate(&mut self, len: u64) -> io::Result<()>;
+}
+
+/// A store that keeps every file in a local directory, named after its id.
+pub struct LocalFileStore {
+    dir: PathBuf,
+}
+
+impl LocalFileStore {
+    /// Create a store in `dir`, which is created if it does not exist.
+    pub fn new(dir: impl Into<PathBuf>) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+}
+
+impl FileStore for LocalFileStore {
+    fn create(&self) -> io::Result<Box<dyn FileWriter>> {
+        let part = self.dir.join(format!(".{}.part", random_id()));
+        Ok(Box::new(LocalFileWriter {
+            file: File::create_new(&part)?,
+            dir: self.dir.clone(),
+            part,
+        }))
+    }
+
+    fn open(&self, id: &str) -> io::Result<Option<Box<dyn Content>>> {
+        match File::open(self.dir.join(id)) {
+            Ok(file) => Ok(Some(Box::new(file))),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn remove(&self, id: &str) -> io::Result<()> {
+        match fs::remove_file(self.dir.join(id)) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// A new file of a [`LocalFileStore`], written to a hidden file that is
+/// linked under its id once committed.
+struct LocalFileWriter {
+    file: File,
+    dir: PathBuf,
+    part: PathBuf,
+}
+
+impl Write for LocalFileWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl FileWriter for LocalFileWriter {
+    fn commit(&mut self, id: &str) -> io::Result<()> {
+        self.file.flush()?;
+        // Unlike a rename, a link doesn't replace an existing file. The hidden
+        // file is removed with the writer.
+        fs::hard_link(&self.part, self.dir.join(id))
+    }
+
+    fn truncate(&mut self, len: u64) -> io::Result<()> {
+        self.file.set_len(len)?;
+        self.file.seek(SeekFrom::Start(len))?;
+        Ok(())
+    }
+}
+
+impl Drop for LocalFileWriter {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.part);
+    }
+}
+
+/// A store that keeps the files in memory.
+#[derive(Default)]
+pub struct MemoryFileStore {
+    files: Arc<std::sync::Mutex<HashMap<String, Vec<u8>>>>,
+}
+
+impl FileStore for MemoryFileStore {
+    fn create(&self) -> io::Result<Box<dyn FileWriter>> {
+        Ok(Box::new(MemoryFileWriter {
+            data: Vec::new(),
+            files: self.files.clone(),
+        }))
+    }
+
+    fn open(&self, id: &str) -> io::Result<Option<Box<dyn Content>>> {
+        let files = self.files.lock().unwrap();
+        Ok(files
+            .get(id)
+            .map(|data| Box::new(Cursor::new(data.clone())) as Box<dyn Content>))
+    }
+
+    fn remove(&self, id: &str) -> io::Result<()> {
+        self.files.lock().unwrap().remove(id);
+        Ok(())
+    }
+}
+
+/// A new file of a [`MemoryFileStore`].
+struct MemoryFileWriter {
+    data: Vec<u8>,
+    files: Arc<std::sync::Mutex<HashMap<String, Vec<u8>>>>,
+}
+
+impl Write for MemoryFileWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.data.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl FileWriter for MemoryFileWriter {
+    fn commit(&mut self, id: &str) -> io::Result<()> {
+        match self.files.lock().unwrap().entry(id.to_string()) {
+            Entry::Occupied(_) => Err(io::ErrorKind::AlreadyExists.into()),
+            Entry::Vacant(entry) => {
+                entry.insert(mem::take(&mut self.data));
+                Ok(())
+            }
+        }
+    }
+
+    fn truncate(&mut self, len: u64) -> io::Result<()> {
+        self.data
+            .truncate(usize::try_from(len).unwrap_or(usize::MAX));
+        Ok(())
+    }
+}
+
+/// The uploaded files used by the files schema.
+///
+/// Put it into the schema data when building the schema, the default keeps
+/// the contents in memory:
+///
+/// ```ignore
+/// let schema = Schema::build(QueryRoot, MutationRoot, SubscriptionRoot)
+///     .data(Storage::local("uploads")?.with_base_url("http://localhost:8000"))
+///     .finish();
+/// ```
+///
+/// The server is expected to serve the contents at `/files/{id}` with
+/// [`Storage::download`].
+#[derive(Clone)]
+pub struct Storage {
+    files: Arc<Mutex<Files>>,
+    store: Arc<dyn FileStore>,
+    base_url: String,
+    sessions: Arc<Mutex<Sessions>>,
+    progress: Progress,
+}
+
+impl Storage {
+    /// Create a storage that writes the contents to `store`.
+    pub fn new(store: impl FileStore + 'static) -> Self {
+        let progress = Progress::default();
+        Self {
+            files: Default::default(),
+            store: Arc::new(store),
+            base_url: String::new(),
+            sessions: Arc::new(Mutex::new(Sessions::new(DEFAULT_TTL, progress.clone()))),
+            progress,
+        }
+    }
+
+    /// Create a storage that keeps the contents in memory.
+    pub fn memory() -> Self {
+        Self::new(MemoryFileStore::default())
+    }
+
+    /// Create a storage that writes the contents to the directory `dir`.
+    pub fn local(dir: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self::new(LocalFileStore::new(dir.as_ref())?))
+    }
+
+    /// Set the URL of the server, which the download URLs of the files start
+    /// with. Without it they are absolute paths.
+    pub fn with_base_url(self, base_url: impl Into<String>) -> Self {
+        Self {
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            ..self
+        }
+    }
+
+    /// Set the time after which a chunked upload that doesn't receive any
+    /// chunk expires, one hour by default.
+    pub fn with_session_ttl(self, ttl: Duration) -> Self {
+        Self {
+            sessions: Arc::new(Mutex::new(Sessions::new(ttl, self.progress.clone()))),
+            ..self
+        }
+    }
+
+    /// Returns all uploaded files.
+    pub async fn files(&self) -> Vec<FileInfo> {
+        self.expire_sessions().await;
+        let files = self.files.lock().await;
+        files.files.values().cloned().collect()
+    }
+
+    /// Returns the file with the specified id.
+    pub async fn file(&self, id: &str) -> Option<FileInfo> {
+        self.expire_sessions().await;
+        let id = id.parse::<usize>().ok()?;
+        self.files.lock().await.files.get(&id).cloned()
+    }
+
+    /// Returns the page of the files that match `filter`, by id, made of the
+    /// `first` files after the file with the id `after`.
+    pub async fn find(
+        &self,
+        filter: &UploadFilter,
+        after: Option<usize>,
+        first: Option<usize>,
+    ) -> FilePage {
+        self.expire_sessions().await;
+        let files = self.files.lock().await;
+        let matching: Vec<_> = files
+            .files
+            .iter()
+            .filter(|(_, file)| filter.matches(file))
+            .collect();
+        let start = after.map_or(0, |after| matching.partition_point(|(id, _)| **id <= after));
+        let end = first.map_or(matching.len(), |first| {
+            matching.len().min(start.saturating_add(first))
+        });
+        FilePage {
+            files: matching[start..end]
+                .iter()
+                .map(|(id, file)| (**id, (*file).clone()))
+                .collect(),
+            has_previous_page: start > 0,
+            has_next_page: end < matching.len(),
+        }
+    }
+
+    /// Deletes the file with the specified id and its content, returns
+    /// whether it existed.
+    pub async fn delete(&self, id: &str) -> io::Result<bool> {
+        self.expire_sessions().await;
+        let Ok(key) = id.parse::<usize>() else {
+            return Ok(false);
+        };
+        let mut files = self.files.lock().await;
+        if !files.files.contains_key(&key) {
+            return Ok(false);
+        }
+        let store = self.store.clone();
+        let store_id = id.to_string();
+        unblock(move || store.remove(&store_id)).await?;
+        files.files.remove(&key);
+        Ok(true)
+    }
+
+    /// Renames the file with the specified id and replaces its tags, each if
+    /// specified. Returns the updated file, or `None` if there is no such
+    /// file.
+    ///
+    /// The filename is sanitized like the uploaded ones, the tags are trimmed
+    /// and the empty and duplicate ones are dropped.
+    pub async fn update_metadata(
+        &self,
+        id: &str,
+        filename: Option<&str>,
+        tags: Option<Vec<String>>,
+    ) -> Option<FileInfo> {
+        self.expire_sessions().await;
+        let id = id.parse::<usize>().ok()?;
+        let mut files = self.files.lock().await;
+        let file = files.files.get_mut(&id)?;
+        if let Some(filename) = filename {
+            file.filename = sanitize_filename(filename);
+        }
+        if let Some(tags) = tags {
+            file.tags.clear();
+            for tag in tags {
+                let tag = tag.trim();
+                if !tag.is_empty() && !file.tags.iter().any(|other| other == tag) {
+                    file.tags.push(tag.to_string());
+                }
+            }
+        }
+        Some(file.clone())
+    }
+
+    /// Writes the contents of the files of a request to the store and
+    /// records them, if they all respect `policy`.
+    ///
+    /// The contents are read as streams, as they are received if the request
+    /// is executed with [`execute`](crate::execute), checked, hashed and
+    /// written to the store chunk by chunk. The filenames are sanitized and the MIME types
+    /// are detected from the contents.
+    ///
+    /// If `upload_id` is specified, the stored files are sent to the
+    /// subscribers to the progress of that upload, the bytes of the request
+    /// are counted by [`Storage::track_upload`] as it is received.
+    pub async fn upload(
+        &self,
+        uploads: Vec<UploadFile>,
+        policy: &UploadPolicy,
+        upload_id: Option<&str>,
+    ) -> Result<Vec<FileInfo>, UploadError> {
+        self.expire_sessions().await;
+        let result = async {
+            let mut total = 0;
+            let mut received = Vec::with_capacity(uploads.len());
+            for upload in uploads {
+                let (filename, content) = upload.open().await.map_err(UploadError::Invalid)?;
+                let filename = sanitize_filename(&filename);
+                let file = self.receive(&filename, content, policy, &mut total).await?;
+                received.push((filename, file));
+            }
+
+            let mut files = self.files.lock().await;
+            let mut infos = Vec::with_capacity(received.len());
+            for (filename, file) in received {
+                infos.push(self.insert(&mut files, filename, file).await?);
+            }
+            Ok((total, infos))
+        }
+        .await;
+
+        match (result, upload_id) {
+            (Ok((total, files)), Some(upload_id)) => {
+                self.progress.finish(
+                    upload_id,
+                    UploadProgress {
+                        bytes_received: total.into(),
+                        total_bytes: Some(total.into()),
+                        files: files.clone(),
+                    },
+                );
+                Ok(files)
+            }
+            (Err(err), Some(upload_id)) => {
+                self.progress.fail(upload_id, &err);
+                Err(err)
+            }
+            (result, None) => result.map(|(_, files)| files),
+        }
+    }
+
+    /// Counts the bytes of `body`, the body of a request of `content_length`
+    /// bytes that sends the files of the upload `upload_id`, and sends the
+    /// count to the subscribers to the progress of that upload as the body is
+    /// received.
+    ///
+    /// Servers wrap the body of the requests with an `Upload-Id` header with
+    /// it, before it is parsed into a GraphQL request, since the bytes of the
+    /// parts before the files are not passed to the resolvers.
+    pub fn track_upload<S, B, E>(
+        &self,
+        upload_id: &str,
+        content_length: Option<u64>,
+        body: S,
+    ) -> impl Stream<Item = Result<B, E>> + use<S, B, E>
+    where
+        S: Stream<Item = Result<B, E>>,
+        B: AsRef<[u8]>,
+    {
+        let progress = self.progress.clone();
+        let upload_id = upload_id.to_string();
+        let mut bytes_received = 0;
+        body.inspect_ok(move |chunk| {
+            bytes_received += chunk.as_ref().len() as u64;
+            progress.publish(
+                &upload_id,
+                UploadProgress {
+                    bytes_received: bytes_received.into(),
+                    total_bytes: content_length.map(Long),
+                    files: Vec::new(),
+                },
+            );
+        })
+    }
+
+    /// Subscribes to the progress of the upload `upload_id`: the number of
+    /// bytes received so far, then the stored files once it is complete. An
+    /// error is sent in place of the files if it fails.
+    ///
+    /// The id is either the id of a chunked upload, whose current progress is
+    /// sent first, or the one passed to [`Storage::upload`], which may be
+    /// subscribed to before the upload starts. The subscription ends with an
+    /// error if an upload that isn't known yet doesn't start within a minute.
+    pub async fn upload_progress(&self, upload_id: &str) -> ProgressStream {
+        self.expire_sessions().await;
+        let session = self.sessions.lock().await.get(upload_id);
+        let current = match session {
+            Some(session) => Some(session.lock().await.progress()),
+            None => None,
+        };
+        self.progress.subscribe(upload_id, current)
+    }
+
+    /// Starts a chunked upload of a file of `size` bytes, returns its id.
+    ///
+    /// The chunks are written to a new file of the store as they are
+    /// received, which is committed once the upload is completed.
+    pub async fn begin_upload(
+        &self,
+        filename: &str,
+        size: u64,
+        policy: &UploadPolicy,
+    ) -> Result<String, UploadError> {
+        self.expire_sessions().await;
+        let filename = sanitize_filename(filename);
+        policy.check_size(&filename, size, size)?;
+        let writer = SharedWriter::create(&self.store)
+            .await
+            .map_err(UploadError::Store)?;
+        let mut sessions = self.sessions.lock().await;
+        Ok(sessions.begin(filename, size, writer))
+    }
+
+    /// Appends `chunk` to the chunked upload `upload_id`, it must start at
+    /// `offset`, the number of bytes received so far. Returns the number of
+    /// bytes received with the chunk.
+    pub async fn upload_chunk(
+        &self,
+        upload_id: &str,
+        offset: u64,
+        chunk: UploadFile,
+    ) -> Result<u64, UploadError> {
+        self.expire_sessions().await;
+        let session = self.sessions.lock().await.touch(upload_id)?;
+        let mut session = session.lock().await;
+        let received = session.append(offset, chunk).await?;
+        self.progress.publish(upload_id, session.progress());
+        Ok(received)
+    }
+
+    /// Completes the chunked upload `upload_id` once all its bytes were
+    /// received, checks their SHA-256 digest and the MIME type detected from
+    /// them, then commits them to the store and records the file.
+    pub async fn complete_upload(
+        &self,
+        upload_id: &str,
+        sha256: &str,
+        policy: &UploadPolicy,
+    ) -> Result<FileInfo, UploadError> {
+        self.expire_sessions().await;
+        let session = self
+            .sessions
+            .lock()
+            .await
+            .get(upload_id)
+            .ok_or(UploadError::UnknownUpload)?;
+        let mut session = session.lock().await;
+        let result = match session.check(sha256) {
+            // An incomplete upload is kept so that the missing chunks can
+            // still be sent.
+            Err(err @ (UploadError::Incomplete { .. } | UploadError::UnknownUpload)) => {
+                return Err(err);
+            }
+            Err(err) => Err(err),
+            Ok(()) => session.take().and_then(|file| {
+                policy.check_type(&session.filename, file.mime_type)?;
+                Ok(file)
+            }),
+        };
+        self.sessions.lock().await.remove(upload_id);
+        let result = match result {
+            Ok(file) => {
+                let mut files = self.files.lock().await;
+                self.insert(&mut files, session.filename.clone(), file)
+                    .await
+            }
+            Err(err) => Err(err),
+        };
+
+        match &result {
+            Ok(file) => self.progress.finish(
+                upload_id,
+                UploadProgress {
+                    bytes_received: file.size,
+                    total_bytes: Some(file.size),
+                    files: vec![file.clone()],
+                },
+            ),
+            Err(err) => self.progress.fail(upload_id, err),
+        }
+        result
+    }
+
+    /// Reads the content of the file `filename` in chunks, checking it
+    /// against `policy` and writing it to a new file of the store as it is
+    /// read. `total` is the size of the files of the request read so far.
+    async fn receive(
+        &self,
+        filename: &str,
+        mut content: impl AsyncRead + Unpin,
+        policy: &UploadPolicy,
+        total: &mut u64,
+    ) -> Result<ReceivedFile, UploadError> {
+        let mut chunk = Vec::new();
+        (&mut content)
+            .take(SNIFF_LEN)
+            .read_to_end(&mut chunk)
+            .await
+            .map_err(UploadError::Invalid)?;
+        let mime_type = sniff(&chunk);
+        policy.check_type(filename, mime_type)?;
+
+        let writer = SharedWriter::create(&self.store)
+            .await
+            .map_err(UploadError::Store)?;
+        let mut hasher = Sha256::new();
+        let mut size = 0;
+        while !chunk.is_empty() {
+            size += chunk.len() as u64;
+            *total += chunk.len() as u64;
+            policy.check_size(filename, size, *total)?;
+            hasher.update(&chunk);
+            chunk = writer.write_all(chunk).await.map_err(UploadError::Store)?;
+
+            chunk.resize(CHUNK_SIZE, 0);
+            let n = content
+                .read(&mut chunk)
+                .await
+                .map_err(UploadError::Invalid)?;
+            chunk.truncate(n);
+        }
+        Ok(ReceivedFile {
+            writer,
+            mime_type,
+            size,
+            sha256: format!("{:x}", hasher.finalize()),
+        })
+    }
+
+    /// Commits a received file under a new id and records it.
+    ///
+    /// The ids of the contents already in the store are skipped, such as
+    /// those uploaded before a restart, whose metadata is lost.
+    async fn insert(
+        &self,
+        files: &mut Files,
+        filename: String,
+        file: ReceivedFile,
+    ) -> Result<FileInfo, UploadError> {
+        let mut key = files.next_id;
+        loop {
+            match file.writer.commit(key.to_string()).await {
+                Ok(()) => break,
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => key += 1,
+                Err(err) => return Err(UploadError::Store(err)),
+            }
+        }
+        files.next_id = key + 1;
+        let id = key.to_string();
+        let info = FileInfo {
+            url: format!("{}/files/{id}", self.base_url),
+            id: id.into(),
+            filename,
+            size: file.size.into(),
+            mime_type: file.mime_type.to_string(),
+            sha256: file.sha256,
+            tags: Vec::new(),
+        };
+        files.files.insert(key, info.clone());
+        Ok(info)
+    }
+
+    /// Removes the chunked uploads that expired, and the files of the store
+    /// that they were written to.
+    async fn expire_sessions(&self) {
+        let expired = self.sessions.lock().await.remove_expired();
+        if !expired.is_empty() {
+            unblock(move || drop(expired)).await;
+        }
+    }
+
+    /// Opens the content of the file with the specified id for a download,
+    /// limited to the byte range requested by the `Range` header `range`.
+    /// Returns `None` if there is no such file.
+    pub async fn download(&self, id: &str, range: Option<&str>) -> io::Result<Option<Download>> {
+        let Some(info) = self.file(id).await else {
+            return Ok(None);
+        };
+        let store = self.store.clone();
+        let (id, range) = (id.to_string(), range.map(str::to_string));
+        unblock(move || {
+            store
+                .open(&id)?
+                .map(|content| Download::new(&info, content, range.as_deref()))
+                .transpose()
+        })
+        .await
+    }
+}
+
+impl Default for Storage {
+    fn default() -> Self {
+        Self::memory()
+    }
+}
+
+/// A page of the uploaded files.
+pub struct FilePage {
+    /// The files of the page, with their ids.
+    pub files: Vec<(usize, FileInfo)>,
+
+    /// Whether files before the page match the filter.
+    pub has_previous_page: bool,
+
+    /// Whether files after the page match the filter.
+    pub has_next_page: bool,
+}
+
+/// The recorded files, by id. The ids are not reused, so that the URL of a
+/// deleted file never serves the content of another one, and the stores
+/// refuse to replace a content.
+#[derive(Default)]
+struct Files {
+    files: BTreeMap<usize, FileInfo>,
+    next_id: usize,
+}
+
+/// The content of a file that was received but not committed yet.
+pub(crate) struct ReceivedFile {
+    pub(crate) writer: SharedWriter,
+    pub(crate) mime_type: &'static str,
+    pub(crate) size: u64,
+    pub(crate) sha256: String,
+}
+
+/// A new file of a store, written to on the blocking thread pool so that
+/// slow disks don't block the executor.
+///
+/// The writer is shared with the blocking task, so that a write that is still
+/// running when its future is dropped doesn't lose it.
+#[derive(Clone)]
+pub(crate) struct SharedWriter(Arc<std::sync::Mutex<Option<Box<dyn FileWriter>>>>);
+
+impl SharedWriter {
+    /// Starts writing a new file of `store`.
+    pub(crate) async fn create(store: &Arc<dyn FileStore>) -> io::Result<Self> {
+        let store = store.clone();
+        let writer = unblock(move || store.create()).await?;
+        Ok(Self(Arc::new(std::sync::Mutex::new(Some(writer)))))
+    }
+
+    /// Writes all of `buf`, which is returned to be reused.
+    pub(crate) async fn write_all(&self, buf: Vec<u8>) -> io::Result<Vec<u8>> {
+        self.with(move |writer| writer.write_all(&buf).map(|()| buf))
+            .await
+    }
+
+    /// Drops what was written after the first `len` bytes.
+    pub(crate) async fn truncate(&self, len: u64) -> io::Result<()> {
+        self.with(move |writer| writer.truncate(len)).await
+    }
+
+    /// Stores everything written as the content of the file `id`, once the
+    /// writes that are still running are done. The writer can be committed
+    /// with another id if it fails.
+    pub(crate) async fn commit(&self, id: String) -> io::Result<()> {
+        let writer = self.0.clone();
+        unblock(move || {
+            let mut writer = writer.lock().unwrap();
+            writer.as_mut().ok_or_else(committed)?.commit(&id)?;
+            *writer = None;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn with<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut dyn FileWriter) -> io::Result<T> + Send + 'static,
+    ) -> io::Result<T> {
+        let writer = self.0.clone();
+        unblock(move || match writer.lock().unwrap().as_mut() {
+            Some(writer) => f(writer.as_mut()),
+            None => Err(committed()),
+        })
+        .await
+    }
+}
+
+fn committed() -> io::Error {
+    io::Error::other("the file is already committed")
+}
+
+/// A new random id, that can't be guessed from the previous ones.
+pub(crate) fn random_id() -> String {
+    let mut bytes = [0; 16];
+    getrandom::fill(&mut bytes).expect("the system random number generator failed");
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use async_graphql::UploadValue;
+    use futures::executor::block_on;
+
+    use super::*;
+
+    fn read(store: &dyn FileStore, id: &str) -> Option<Vec<u8>> {
+        let mut content = store.open(id).unwrap()?;
+        let mut data = Vec::new();
+        content.read_to_end(&mut data).unwrap();
+        Some(data)
+    }
+
+    /// Checks writing, committing, opening and removing the files of `store`.
+    fn check_store(store: &dyn FileStore) {
+        let mut writer = store.create().unwrap();
+        writer.write_all(b"hello world").unwrap();
+        writer.truncate(5).unwrap();
+        writer.write_all(b"!").unwrap();
+        assert_eq!(read(store, "1"), None);
+        writer.commit("1").unwrap();
+        drop(writer);
+        assert_eq!(read(store, "1").unwrap(), b"hello!");
+
+        // An existing file is never replaced.
+        let mut writer = store.create().unwrap();
+        writer.write_all(b"other").unwrap();
+        let err = writer.commit("1").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        writer.commit("2").unwrap();
+        assert_eq!(read(store, "1").unwrap(), b"hello!");
+        assert_eq!(read(store, "2").unwrap(), b"other");
+
+        // A writer dropped without being committed stores nothing.
+        let mut writer = store.create().unwrap();
+        writer.write_all(b"dropped").unwrap();
+        drop(writer);
+
+        store.remove("1").unwrap();
+        assert_eq!(read(store, "1"), None);
+        store.remove("1").unwrap();
+        assert_eq!(read(store, "2").unwrap(), b"other");
+    }
+
+    #[test]
+    fn memory_file_store() {
+        let store = MemoryFileStore::default();
+        check_store(&store);
+        assert_eq!(store.files.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn local_file_store() {
+        let dir = tempfile::tempdir().unwrap();
+        check_store(&LocalFileStore::new(dir.path()).unwrap());
+        let names: Vec<_> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(names, ["2"]);
+    }
+
+    pub(crate) fn upload(filename: &str, content: &[u8]) -> UploadFile {
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(content).unwrap();
+        file.rewind().unwrap();
+        UploadValue {
+            filename: filename.to_string(),
+            content_type: None,
+            content: file,
+        }
+        .into()
+    }
+
+    fn upload_files(storage: &Storage, files: &[(&str, &[u8])]) -> Vec<FileInfo> {
+        let uploads = files
+            .iter()
+            .map(|(filename, content)| upload(filename, content))
+            .collect();
+        block_on(storage.upload(uploads, &UploadPolicy::default(), None)).unwrap()
+    }
+
+    #[test]
+    fn ids_skip_the_stored_files() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("0"), "before a restart").unwrap();
+        fs::write(dir.path().join("1"), "before a restart").unwrap();
+
+        let storage = Storage::local(dir.path()).unwrap();
+        let files = upload_files(&storage, &[("a.txt", b"a"), ("b.txt", b"b")]);
+        let ids: Vec<_> = files.iter().map(|file| file.id.as_str()).collect();
+        assert_eq!(ids, ["2", "3"]);
+        assert_eq!(fs::read(dir.path().join("0")).unwrap(), b"before a restart");
+        assert_eq!(fs::read(dir.path().join("3")).unwrap(), b"b");
+    }
+
+    #[test]
+    fn find_files() {
+        let storage = Storage::memory();
+        upload_files(
+            &storage,
+            &[
+                ("a.txt", b"a"),
+                ("b.png", b"\x89PNG\r\n\x1a\n"),
+                ("c.txt", b"c"),
+                ("d.txt", b"d"),
+            ],
+        );
+        let ids = |page: &FilePage| page.files.iter().map(|(id, _)| *id).collect::<Vec<_>>();
+
+        let text = UploadFilter {
+            mime_type: Some("text/*".to_string()),
+            ..Default::default()
+        };
+        let page = block_on(storage.find(&text, None, None));
+        assert_eq!(ids(&page), [0, 2, 3]);
+        assert!(!page.has_previous_page && !page.has_next_page);
+
+        let page = block_on(storage.find(&text, Some(0), Some(1)));
+        assert_eq!(ids(&page), [2]);
+        assert!(page.has_previous_page && page.has_next_page);
+
+        let page = block_on(storage.find(&text, Some(2), Some(5)));
+        assert_eq!(ids(&page), [3]);
+        assert!(page.has_previous_page && !page.has_next_page);
+
+        let png = UploadFilter {
+            filename_contains: Some("B.PNG".to_string()),
+            ..Default::default()
+        };
+        let page = block_on(storage.find(&png, None, None));
+        assert_eq!(ids(&page), [1]);
+        assert_eq!(page.files[0].1.mime_type, "image/png");
+    }
+
+    #[test]
+    fn delete_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = Storage::local(dir.path()).unwrap();
+        upload_files(&storage, &[("a.txt", b"a"), ("b.txt", b"b")]);
+
+        assert!(block_on(storage.delete("0")).unwrap());
+        assert!(!dir.path().join("0").exists());
+        assert!(block_on(storage.file("0")).is_none());
+        assert!(!block_on(storage.delete("0")).unwrap());
+        assert!(!block_on(storage.delete("x")).unwrap());
+        assert!(block_on(storage.file("1")).is_some());
+
+        // The id of a deleted file isn't reused.
+        let files = upload_files(&storage, &[("c.txt", b"c")]);
+        assert_eq!(files[0].id.as_str(), "2");
+    }
+}
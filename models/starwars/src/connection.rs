@@ -0,0 +1,155 @@
+//! Relay connections over in-memory lists, using the offset of each item as
+//! its cursor.
+
+use async_graphql::{
+    Error, OutputType, Result,
+    connection::{Connection, Edge, query},
+};
+
+/// Resolves the page of `items` selected by the `after`, `before`, `first`
+/// and `last` arguments, mapping each item of the page with `map_to`.
+///
+/// `after` and `before` are applied first, then `first` and finally `last`,
+/// as in the [Relay specification](https://relay.dev/graphql/connections.htm#sec-Pagination-algorithm).
+/// A cursor that is not the offset of one of the `items`, or an `after` cursor
+/// that doesn't come before the `before` cursor, is an error.
+pub async fn offset_connection<I, T, F>(
+    after: Option<String>,
+    before: Option<String>,
+    first: Option<i32>,
+    last: Option<i32>,
+    items: &[I],
+    map_to: F,
+) -> Result<Connection<usize, T>>
+where
+    F: Fn(&I) -> T,
+    T: OutputType,
+{
+    query(
+        after,
+        before,
+        first,
+        last,
+        |after: Option<usize>, before: Option<usize>, first, last| async move {
+            let mut start = match after {
+                Some(after) if after >= items.len() => {
+                    return Err(Error::new("The \"after\" cursor is out of range"));
+                }
+                Some(after) => after + 1,
+                None => 0,
+            };
+            let mut end = match before {
+                Some(before) if before >= items.len() => {
+                    return Err(Error::new("The \"before\" cursor is out of range"));
+                }
+                Some(before) => before,
+                None => items.len(),
+            };
+            if start > end {
+                return Err(Error::new(
+                    "The \"after\" cursor must come before the \"before\" cursor",
+                ));
+            }
+
+            if let Some(first) = first {
+                end = end.min(start.saturating_add(first));
+            }
+            if let Some(last) = last {
+                start = start.max(end.saturating_sub(last));
+            }
+
+            let mut connection = Connection::new(start > 0, end < items.len());
+            connection.edges.extend(
+                items[start..end]
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, item)| Edge::new(start + idx, map_to(item))),
+            );
+            Ok::<_, Error>(connection)
+        },
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::{option, prelude::*};
+
+    use super::*;
+
+    /// A cursor argument: absent, an offset that may be out of range, or
+    /// something that isn't an offset at all.
+    fn cursor() -> impl Strategy<Value = Option<String>> {
+        prop_oneof![
+            Just(None),
+            (0usize..24).prop_map(|offset| Some(offset.to_string())),
+            "[a-z]{1,3}".prop_map(Some),
+        ]
+    }
+
+    fn count() -> impl Strategy<Value = Option<i32>> {
+        option::of(-2i32..24)
+    }
+
+    proptest! {
+        #[test]
+        fn pages_match_the_relay_algorithm(
+            len in 0usize..20,
+            after in cursor(),
+            before in cursor(),
+            first in count(),
+            last in count(),
+        ) {
+            let items = (0..len).collect::<Vec<_>>();
+            let res = tokio::runtime::Builder::new_current_thread()
+                .build()
+                .unwrap()
+                .block_on(offset_connection(
+                    after.clone(),
+                    before.clone(),
+                    first,
+                    last,
+                    &items,
+                    |item| *item as i32,
+                ));
+
+            let offset = |cursor: Option<String>| match cursor {
+                Some(cursor) => cursor.parse().ok().filter(|offset| *offset < len).map(Some),
+                None => Some(None),
+            };
+            let (Some(after), Some(before)) = (offset(after), offset(before)) else {
+                prop_assert!(res.is_err(), "a cursor out of range is an error");
+                return Ok(());
+            };
+            let lower = after.map_or(0, |after| after + 1);
+            let upper = before.unwrap_or(len);
+            if lower > upper
+                || first.is_some_and(|first| first < 0)
+                || last.is_some_and(|last| last < 0)
+            {
+                prop_assert!(res.is_err());
+                return Ok(());
+            }
+
+            let connection = res.unwrap();
+            let offsets = connection.edges.iter().map(|edge| edge.cursor).collect::<Vec<_>>();
+            prop_assert!(offsets.iter().all(|offset| (lower..upper).contains(offset)));
+            prop_assert!(offsets.windows(2).all(|pair| pair[1] == pair[0] + 1));
+            prop_assert!(connection.edges.iter().all(|edge| edge.node == edge.cursor as i32));
+            if let Some(first) = first {
+                prop_assert!(offsets.len() <= first as usize);
+            }
+            if let Some(last) = last {
+                prop_assert!(offsets.len() <= last as usize);
+            }
+
+            // `first` keeps the beginning of the items between the cursors,
+            // then `last` keeps the end of what remains.
+            let end = first.map_or(upper, |first| upper.min(lower + first as usize));
+            let start = last.map_or(lower, |last| lower.max(end.saturating_sub(last as usize)));
+            prop_assert_eq!(offsets, (start..end).collect::<Vec<_>>());
+            prop_assert_eq!(connection.has_previous_page, start > 0);
+            prop_assert_eq!(connection.has_next_page, end < len);
+        }
+    }
+}
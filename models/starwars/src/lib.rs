@@ -1,136 +1,347 @@
+mod connection;
 mod model;
 
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    fs::File,
+    io::{self, BufReader, Read},
+    path::Path,
+    sync::Mutex,
+};
 
-use async_graphql::{EmptyMutation, EmptySubscription, Schema};
-use model::Episode;
-pub use model::QueryRoot;
+use async_graphql::{
+    Schema,
+    dataloader::{DataLoader, Loader},
+};
+use base64::{Engine, engine::general_purpose::STANDARD};
+pub use connection::offset_connection;
+use futures_util::{Stream, stream};
+pub use model::{Episode, MutationRoot, QueryRoot, Review, SubscriptionRoot};
+use serde::Deserialize;
 use slab::Slab;
-pub type StarWarsSchema = Schema<QueryRoot, EmptyMutation, EmptySubscription>;
+use thiserror::Error;
+use tokio::sync::broadcast::{self, error::RecvError};
+pub type StarWarsSchema = Schema<QueryRoot, MutationRoot, SubscriptionRoot>;
 
+/// The dataset used by [`StarWars::new`].
+const DEFAULT_FIXTURE: &str = include_str!("../starwars.json");
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct StarWarsChar {
-    id: &'static str,
-    name: &'static str,
+    id: String,
+    name: String,
+    #[serde(skip)]
     is_human: bool,
-    friends: Vec<usize>,
+    #[serde(default)]
+    friends: Vec<String>,
+    #[serde(default)]
     appears_in: Vec<Episode>,
-    home_planet: Option<&'static str>,
-    primary_function: Option<&'static str>,
+    home_planet: Option<String>,
+    primary_function: Option<String>,
+}
+
+impl StarWarsChar {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// The Relay global id of the character, see [`global_id`].
+    pub fn global_id(&self) -> String {
+        global_id(if self.is_human { "Human" } else { "Droid" }, &self.id)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Whether the character is a human, otherwise it is a droid.
+    pub fn is_human(&self) -> bool {
+        self.is_human
+    }
+
+    pub fn appears_in(&self) -> &[Episode] {
+        &self.appears_in
+    }
+
+    /// The home planet of a human.
+    pub fn home_planet(&self) -> Option<&str> {
+        self.home_planet.as_deref()
+    }
+
+    /// The primary function of a droid.
+    pub fn primary_function(&self) -> Option<&str> {
+        self.primary_function.as_deref()
+    }
+}
+
+#[derive(Deserialize)]
+pub struct StarWarsStarship {
+    id: String,
+    name: String,
+    /// The length in meters.
+    length: f64,
+}
+
+impl StarWarsStarship {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// The Relay global id of the starship, see [`global_id`].
+    pub fn global_id(&self) -> String {
+        global_id("Starship", &self.id)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The length in meters.
+    pub fn length(&self) -> f64 {
+        self.length
+    }
+}
+
+/// The Relay global id of the object `id` of the GraphQL type `ty`, the
+/// base64 encoding of `<ty>:<id>`, which is unique among all objects.
+pub fn global_id(ty: &str, id: &str) -> String {
+    STANDARD.encode(format!("{ty}:{id}"))
+}
+
+/// The GraphQL type and the id of the object with the global id `global_id`.
+pub fn parse_global_id(global_id: &str) -> Option<(String, String)> {
+    let decoded = String::from_utf8(STANDARD.decode(global_id).ok()?).ok()?;
+    let (ty, id) = decoded.split_once(':')?;
+    Some((ty.to_string(), id.to_string()))
+}
+
+/// An object that can be fetched by its global id.
+pub enum StarWarsNode<'a> {
+    Char(&'a StarWarsChar),
+    Starship(&'a StarWarsStarship),
 }
 
 pub struct StarWars {
-    luke: usize,
-    artoo: usize,
+    hero: usize,
+    episode_heroes: HashMap<Episode, usize>,
     chars: Slab<StarWarsChar>,
-    chars_by_id: HashMap<&'static str, usize>,
+    chars_by_id: HashMap<String, usize>,
+    starships: Vec<StarWarsStarship>,
+    starships_by_id: HashMap<String, usize>,
+    loader: DataLoader<CharacterLoader>,
+    reviews: Mutex<Vec<Review>>,
+    reviews_sender: broadcast::Sender<Review>,
+}
+
+/// Looks up the characters with the specified ids in batches, the values are
+/// the keys of the characters in [`StarWars`].
+pub struct CharacterLoader {
+    chars_by_id: HashMap<String, usize>,
+    /// The number of batches loaded so far.
+    #[cfg(test)]
+    batches: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+}
+
+impl Loader<String> for CharacterLoader {
+    type Value = usize;
+    type Error = Infallible;
+
+    async fn load(&self, keys: &[String]) -> Result<HashMap<String, Self::Value>, Self::Error> {
+        #[cfg(test)]
+        self.batches
+            .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        Ok(keys
+            .iter()
+            .filter_map(|id| Some((id.clone(), *self.chars_by_id.get(id)?)))
+            .collect())
+    }
+}
+
+/// The format of a fixture file.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum FixtureFormat {
+    Json,
+    Yaml,
+}
+
+/// The content of a fixture file, the ids refer to the characters.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Fixture {
+    /// The hero of the whole saga.
+    hero: String,
+    /// The heroes of particular episodes, the others default to `hero`.
+    #[serde(default)]
+    episode_heroes: HashMap<Episode, String>,
+    #[serde(default)]
+    humans: Vec<StarWarsChar>,
+    #[serde(default)]
+    droids: Vec<StarWarsChar>,
+    #[serde(default)]
+    starships: Vec<StarWarsStarship>,
+}
+
+/// An error loading a fixture file.
+#[derive(Debug, Error)]
+pub enum FixtureError {
+    #[error("failed to read the fixture: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("invalid JSON fixture: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("invalid YAML fixture: {0}")]
+    Yaml(#[from] serde_yaml::Error),
+
+    #[error("duplicate id `{0}`")]
+    DuplicateId(String),
+
+    #[error("character `{id}` has an unknown friend `{friend}`")]
+    DanglingFriend { id: String, friend: String },
+
+    #[error("unknown hero `{0}`")]
+    UnknownHero(String),
 }
 
 impl StarWars {
+    /// The characters of the original trilogy.
     #[allow(clippy::new_without_default)]
     pub fn new() -> Self {
+        Self::from_reader(DEFAULT_FIXTURE.as_bytes(), FixtureFormat::Json)
+            .expect("the default fixture is valid")
+    }
+
+    /// Load the characters from the fixture file at `path`, the format is
+    /// YAML if the extension is `yaml` or `yml` and JSON otherwise.
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, FixtureError> {
+        let path = path.as_ref();
+        let format = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("yaml" | "yml") => FixtureFormat::Yaml,
+            _ => FixtureFormat::Json,
+        };
+        Self::from_reader(BufReader::new(File::open(path)?), format)
+    }
+
+    /// Load the characters from a fixture, the ids must be unique among all
+    /// characters and starships, and every id that it refers to must be the
+    /// id of one of its characters.
+    pub fn from_reader(reader: impl Read, format: FixtureFormat) -> Result<Self, FixtureError> {
+        let fixture: Fixture = match format {
+            FixtureFormat::Json => serde_json::from_reader(reader)?,
+            FixtureFormat::Yaml => serde_yaml::from_reader(reader)?,
+        };
+
         let mut chars = Slab::new();
+        let mut chars_by_id = HashMap::new();
+        let humans = fixture.humans.into_iter().map(|ch| (true, ch));
+        let droids = fixture.droids.into_iter().map(|ch| (false, ch));
+        for (is_human, ch) in humans.chain(droids) {
+            if chars_by_id.contains_key(&ch.id) {
+                return Err(FixtureError::DuplicateId(ch.id));
+            }
+            let id = ch.id.clone();
+            chars_by_id.insert(id, chars.insert(StarWarsChar { is_human, ..ch }));
+        }
+
+        let mut starships_by_id = HashMap::new();
+        for (idx, starship) in fixture.starships.iter().enumerate() {
+            if chars_by_id.contains_key(&starship.id)
+                || starships_by_id.insert(starship.id.clone(), idx).is_some()
+            {
+                return Err(FixtureError::DuplicateId(starship.id.clone()));
+            }
+        }
+
+        for (_, ch) in &chars {
+            if let Some(friend) = ch.friends.iter().find(|id| !chars_by_id.contains_key(*id)) {
+                return Err(FixtureError::DanglingFriend {
+                    id: ch.id.clone(),
+                    friend: friend.clone(),
+                });
+            }
+        }
 
-        let luke = chars.insert(StarWarsChar {
-            id: "1000",
-            name: "Luke Skywalker",
-            is_human: true,
-            friends: vec![],
-            appears_in: vec![],
-            home_planet: Some("Tatooine"),
-            primary_function: None,
-        });
-
-        let vader = chars.insert(StarWarsChar {
-            id: "1001",
-            name: "Anakin Skywalker",
-            is_human: true,
-            friends: vec![],
-            appears_in: vec![],
-            home_planet: Some("Tatooine"),
-            primary_function: None,
-        });
-
-        let han = chars.insert(StarWarsChar {
-            id: "1002",
-            name: "Han Solo",
-            is_human: true,
-            friends: vec![],
-            appears_in: vec![Episode::Empire, Episode::NewHope, Episode::Jedi],
-            home_planet: None,
-            primary_function: None,
-        });
-
-        let leia = chars.insert(StarWarsChar {
-            id: "1003",
-            name: "Leia Organa",
-            is_human: true,
-            friends: vec![],
-            appears_in: vec![Episode::Empire, Episode::NewHope, Episode::Jedi],
-            home_planet: Some("Alderaa"),
-            primary_function: None,
-        });
-
-        let tarkin = chars.insert(StarWarsChar {
-            id: "1004",
-            name: "Wilhuff Tarkin",
-            is_human: true,
-            friends: vec![],
-            appears_in: vec![Episode::Empire, Episode::NewHope, Episode::Jedi],
-            home_planet: None,
-            primary_function: None,
-        });
-
-        let threepio = chars.insert(StarWarsChar {
-            id: "2000",
-            name: "C-3PO",
-            is_human: false,
-            friends: vec![],
-            appears_in: vec![Episode::Empire, Episode::NewHope, Episode::Jedi],
-            home_planet: None,
-            primary_function: Some("Protocol"),
-        });
-
-        let artoo = chars.insert(StarWarsChar {
-            id: "2001",
-            name: "R2-D2",
-            is_human: false,
-            friends: vec![],
-            appears_in: vec![Episode::Empire, Episode::NewHope, Episode::Jedi],
-            home_planet: None,
-            primary_function: Some("Astromech"),
-        });
-
-        chars[luke].friends = vec![han, leia, threepio, artoo];
-        chars[vader].friends = vec![tarkin];
-        chars[han].friends = vec![luke, leia, artoo];
-        chars[leia].friends = vec![luke, han, threepio, artoo];
-        chars[tarkin].friends = vec![vader];
-        chars[threepio].friends = vec![luke, han, leia, artoo];
-        chars[artoo].friends = vec![luke, han, leia];
-
-        let chars_by_id = chars.iter().map(|(idx, ch)| (ch.id, idx)).collect();
-        Self {
-            luke,
-            artoo,
+        let key = |id: String| {
+            chars_by_id
+                .get(&id)
+                .copied()
+                .ok_or(FixtureError::UnknownHero(id))
+        };
+        let hero = key(fixture.hero)?;
+        let episode_heroes = fixture
+            .episode_heroes
+            .into_iter()
+            .map(|(episode, id)| Ok((episode, key(id)?)))
+            .collect::<Result<_, FixtureError>>()?;
+
+        let loader = DataLoader::new(
+            CharacterLoader {
+                chars_by_id: chars_by_id.clone(),
+                #[cfg(test)]
+                batches: Default::default(),
+            },
+            tokio::spawn,
+        );
+        Ok(Self {
+            hero,
+            episode_heroes,
             chars,
             chars_by_id,
+            starships: fixture.starships,
+            starships_by_id,
+            loader,
+            reviews: Mutex::new(Vec::new()),
+            reviews_sender: broadcast::channel(64).0,
+        })
+    }
+
+    /// The hero of `episode`, or of the whole saga if it is `None`.
+    pub fn hero(&self, episode: Option<Episode>) -> &StarWarsChar {
+        let key = episode
+            .and_then(|episode| self.episode_heroes.get(&episode))
+            .copied()
+            .unwrap_or(self.hero);
+        &self.chars[key]
+    }
+
+    /// The object with the Relay global id `global_id`.
+    pub fn node(&self, global_id: &str) -> Option<StarWarsNode<'_>> {
+        let (ty, id) = parse_global_id(global_id)?;
+        match ty.as_str() {
+            "Human" => self.human(&id).map(StarWarsNode::Char),
+            "Droid" => self.droid(&id).map(StarWarsNode::Char),
+            "Starship" => self.starship(&id).map(StarWarsNode::Starship),
+            _ => None,
         }
     }
 
+    pub fn character(&self, id: &str) -> Option<&StarWarsChar> {
+        self.chars_by_id.get(id).map(|idx| &self.chars[*idx])
+    }
+
+    /// The human with the id or the Relay global id `id`.
     pub fn human(&self, id: &str) -> Option<&StarWarsChar> {
-        self.chars_by_id
-            .get(id)
-            .copied()
-            .map(|idx| self.chars.get(idx).unwrap())
-            .filter(|ch| ch.is_human)
+        self.character_by_any_id(id).filter(|ch| ch.is_human)
     }
 
+    /// The droid with the id or the Relay global id `id`.
     pub fn droid(&self, id: &str) -> Option<&StarWarsChar> {
-        self.chars_by_id
-            .get(id)
-            .copied()
-            .map(|idx| self.chars.get(idx).unwrap())
-            .filter(|ch| !ch.is_human)
+        self.character_by_any_id(id).filter(|ch| !ch.is_human)
+    }
+
+    /// The character with the id or the Relay global id `id`, the type of a
+    /// global id is checked by the callers.
+    fn character_by_any_id(&self, id: &str) -> Option<&StarWarsChar> {
+        match parse_global_id(id) {
+            Some((_, id)) => self.character(&id),
+            None => self.character(id),
+        }
+    }
+
+    pub fn characters(&self) -> Vec<&StarWarsChar> {
+        self.chars.iter().map(|(_, ch)| ch).collect()
     }
 
     pub fn humans(&self) -> Vec<&StarWarsChar> {
@@ -149,11 +360,382 @@ impl StarWars {
             .collect()
     }
 
-    pub fn friends(&self, ch: &StarWarsChar) -> Vec<&StarWarsChar> {
+    pub fn starship(&self, id: &str) -> Option<&StarWarsStarship> {
+        self.starships_by_id
+            .get(id)
+            .map(|idx| &self.starships[*idx])
+    }
+
+    pub fn starships(&self) -> Vec<&StarWarsStarship> {
+        self.starships.iter().collect()
+    }
+
+    /// The reviews of `episode`, oldest first.
+    pub fn reviews(&self, episode: Episode) -> Vec<Review> {
+        let reviews = self.reviews.lock().unwrap();
+        reviews
+            .iter()
+            .filter(|review| review.episode == episode)
+            .cloned()
+            .collect()
+    }
+
+    /// Store a review and send it to the receivers of
+    /// [`StarWars::subscribe_reviews`].
+    pub fn add_review(&self, review: Review) {
+        self.reviews.lock().unwrap().push(review.clone());
+        // There may be no receivers, which is not an error.
+        let _ = self.reviews_sender.send(review);
+    }
+
+    /// The reviews added from now on, those that a slow receiver misses are
+    /// skipped.
+    pub fn subscribe_reviews(&self) -> impl Stream<Item = Review> + use<> {
+        let receiver = self.reviews_sender.subscribe();
+        stream::unfold(receiver, |mut receiver| async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(review) => return Some((review, receiver)),
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        })
+    }
+
+    /// The friends of `ch`, the friends of all characters resolved at the
+    /// same time are loaded in one batch.
+    pub async fn friends(&self, ch: &StarWarsChar) -> Vec<&StarWarsChar> {
+        let Ok(keys) = self
+            .loader
+            .load_many(ch.friends.iter().map(ToString::to_string))
+            .await;
         ch.friends
             .iter()
-            .copied()
-            .filter_map(|id| self.chars.get(id))
+            .filter_map(|id| self.chars.get(*keys.get(id)?))
             .collect()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+
+    use async_graphql::{Request, Variables, value};
+    use futures_util::{FutureExt, StreamExt};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn friends_are_loaded_in_one_batch_per_level() {
+        let starwars = StarWars::new();
+        let batches = starwars.loader.loader().batches.clone();
+        let schema = Schema::build(QueryRoot, MutationRoot, SubscriptionRoot)
+            .data(starwars)
+            .finish();
+
+        let data = schema
+            .execute("{ hero { friends { friends { name } } } }")
+            .await
+            .into_result()
+            .unwrap()
+            .data;
+        let friends = |names: &[&str]| {
+            let friends: Vec<_> = names.iter().map(|name| value!({ "name": *name })).collect();
+            value!({ "friends": friends })
+        };
+        assert_eq!(
+            data,
+            value!({
+                "hero": {
+                    "friends": [
+                        friends(&["Luke Skywalker", "Leia Organa", "R2-D2"]),
+                        friends(&["Luke Skywalker", "Han Solo", "C-3PO", "R2-D2"]),
+                        friends(&["Luke Skywalker", "Han Solo", "Leia Organa", "R2-D2"]),
+                        friends(&["Luke Skywalker", "Han Solo", "Leia Organa"]),
+                    ]
+                }
+            })
+        );
+        // The friends of the hero, then the friends of all of them at once.
+        assert_eq!(batches.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn nodes_are_fetched_by_global_id() {
+        let schema = Schema::build(QueryRoot, MutationRoot, SubscriptionRoot)
+            .data(StarWars::new())
+            .finish();
+
+        let data = schema
+            .execute(
+                r#"{
+                    human(id: "1000") { id }
+                    node(id: "SHVtYW46MTAwMA==") { __typename ... on Human { name } }
+                    droid: node(id: "RHJvaWQ6MTAwMA==") { id }
+                    invalid: node(id: "1000") { id }
+                }"#,
+            )
+            .await
+            .into_result()
+            .unwrap()
+            .data;
+        assert_eq!(
+            data,
+            value!({
+                "human": { "id": "SHVtYW46MTAwMA==" },
+                "node": { "__typename": "Human", "name": "Luke Skywalker" },
+                "droid": null,
+                "invalid": null,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn global_ids_round_trip() {
+        let schema = Schema::build(QueryRoot, MutationRoot, SubscriptionRoot)
+            .data(StarWars::new())
+            .finish();
+
+        let query = r#"query ($human: String!, $droid: String!) {
+            human(id: $human) { id name }
+            droid(id: $droid) { id name }
+            humanNode: node(id: $human) { id ... on Human { name } }
+            droidNode: node(id: $droid) { id ... on Droid { name } }
+            notHuman: human(id: $droid) { id }
+            notDroid: droid(id: $human) { id }
+            raw: human(id: "1000") { id }
+        }"#;
+        let ids = schema
+            .execute("{ luke: hero(episode: EMPIRE) { id } r2: hero(episode: JEDI) { id } }")
+            .await
+            .into_result()
+            .unwrap()
+            .data
+            .into_json()
+            .unwrap();
+        let (human, droid) = (&ids["luke"]["id"], &ids["r2"]["id"]);
+        let variables = serde_json::json!({ "human": human, "droid": droid });
+        let data = schema
+            .execute(Request::new(query).variables(Variables::from_json(variables)))
+            .await
+            .into_result()
+            .unwrap()
+            .data;
+        assert_eq!(
+            data,
+            value!({
+                "human": { "id": "SHVtYW46MTAwMA==", "name": "Luke Skywalker" },
+                "droid": { "id": "RHJvaWQ6MjAwMQ==", "name": "R2-D2" },
+                "humanNode": { "id": "SHVtYW46MTAwMA==", "name": "Luke Skywalker" },
+                "droidNode": { "id": "RHJvaWQ6MjAwMQ==", "name": "R2-D2" },
+                "notHuman": null,
+                "notDroid": null,
+                "raw": { "id": "SHVtYW46MTAwMA==" },
+            })
+        );
+    }
+
+    fn schema() -> StarWarsSchema {
+        Schema::build(QueryRoot, MutationRoot, SubscriptionRoot)
+            .data(StarWars::new())
+            .finish()
+    }
+
+    #[tokio::test]
+    async fn create_review() {
+        let schema = schema();
+        let data = schema
+            .execute(
+                r#"mutation {
+                    createReview(episode: JEDI, review: { stars: 5, commentary: "Great!" }) {
+                        episode stars commentary
+                    }
+                }"#,
+            )
+            .await
+            .into_result()
+            .unwrap()
+            .data;
+        assert_eq!(
+            data,
+            value!({ "createReview": { "episode": "JEDI", "stars": 5, "commentary": "Great!" } })
+        );
+
+        let data = schema
+            .execute(
+                "{ jedi: reviews(episode: JEDI) { stars commentary } empire: reviews(episode: EMPIRE) { stars } }",
+            )
+            .await
+            .into_result()
+            .unwrap()
+            .data;
+        assert_eq!(
+            data,
+            value!({ "jedi": [{ "stars": 5, "commentary": "Great!" }], "empire": [] })
+        );
+    }
+
+    #[tokio::test]
+    async fn review_added_by_episode() {
+        let schema = schema();
+        let mut jedi =
+            schema.execute_stream("subscription { reviewAdded(episode: JEDI) { stars } }");
+        let mut all = schema.execute_stream("subscription { reviewAdded { episode stars } }");
+        // Subscribe before the reviews are created.
+        assert!(jedi.next().now_or_never().is_none());
+        assert!(all.next().now_or_never().is_none());
+
+        for (episode, stars) in [("EMPIRE", 1), ("JEDI", 2), ("NEW_HOPE", 3), ("JEDI", 4)] {
+            let mutation = format!(
+                "mutation {{ createReview(episode: {episode}, review: {{ stars: {stars} }}) {{ stars }} }}"
+            );
+            assert!(schema.execute(mutation).await.is_ok());
+        }
+
+        for stars in [2, 4] {
+            let response = jedi.next().await.unwrap();
+            assert_eq!(response.data, value!({ "reviewAdded": { "stars": stars } }));
+        }
+        assert!(jedi.next().now_or_never().is_none());
+        for (episode, stars) in [("EMPIRE", 1), ("JEDI", 2), ("NEW_HOPE", 3), ("JEDI", 4)] {
+            let response = all.next().await.unwrap();
+            assert_eq!(
+                response.data,
+                value!({ "reviewAdded": { "episode": episode, "stars": stars } })
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn invalid_review_input() {
+        let schema = schema();
+        let long = "x".repeat(501);
+        for (review, message) in [
+            (
+                "{ stars: 0 }",
+                r#"Failed to parse "Int": the value is 0, must be greater than or equal to 1 (occurred while parsing "ReviewInput")"#,
+            ),
+            (
+                "{ stars: 6 }",
+                r#"Failed to parse "Int": the value is 6, must be less than or equal to 5 (occurred while parsing "ReviewInput")"#,
+            ),
+            (
+                r#"{ stars: 3, commentary: "" }"#,
+                r#"Failed to parse "String": the string length is 0, must be greater than or equal to 1 (occurred while parsing "ReviewInput")"#,
+            ),
+            (
+                &format!(r#"{{ stars: 3, commentary: "{long}" }}"#),
+                r#"Failed to parse "String": the string length is 501, must be less than or equal to 500 (occurred while parsing "ReviewInput")"#,
+            ),
+        ] {
+            let mutation =
+                format!("mutation {{ createReview(episode: JEDI, review: {review}) {{ stars }} }}");
+            let errors = schema.execute(mutation).await.errors;
+            let messages: Vec<_> = errors.into_iter().map(|err| err.message).collect();
+            assert_eq!(messages, [message]);
+        }
+        assert_eq!(
+            schema
+                .execute("{ reviews(episode: JEDI) { stars } }")
+                .await
+                .data,
+            value!({ "reviews": [] })
+        );
+    }
+
+    /// A fixture with a human and a droid who are friends.
+    const FIXTURE: &str = r#"{
+        "hero": "1",
+        "episodeHeroes": { "EMPIRE": "2" },
+        "humans": [{ "id": "1", "name": "Luke", "friends": ["2"], "homePlanet": "Tatooine" }],
+        "droids": [{ "id": "2", "name": "R2", "friends": ["1"], "primaryFunction": "Astromech" }],
+        "starships": [{ "id": "3", "name": "X-Wing", "length": 12.5 }]
+    }"#;
+
+    fn load_error(json: &str) -> FixtureError {
+        StarWars::from_reader(json.as_bytes(), FixtureFormat::Json)
+            .err()
+            .expect("the fixture is invalid")
+    }
+
+    #[test]
+    fn fixture_from_json() {
+        let starwars = StarWars::from_reader(FIXTURE.as_bytes(), FixtureFormat::Json).unwrap();
+        assert_eq!(starwars.hero(None).name(), "Luke");
+        assert_eq!(starwars.hero(Some(Episode::Empire)).name(), "R2");
+        assert_eq!(starwars.hero(Some(Episode::Jedi)).name(), "Luke");
+        assert_eq!(starwars.human("1").unwrap().home_planet(), Some("Tatooine"));
+        assert!(starwars.human("2").is_none());
+        assert_eq!(
+            starwars.droid("2").unwrap().primary_function(),
+            Some("Astromech")
+        );
+        assert_eq!(starwars.starship("3").unwrap().length(), 12.5);
+    }
+
+    #[test]
+    fn fixture_from_yaml_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("fixture.yaml");
+        std::fs::write(
+            &path,
+            "hero: '2'\n\
+             humans:\n  - { id: '1', name: Luke, friends: ['2'] }\n\
+             droids:\n  - { id: '2', name: R2 }\n",
+        )
+        .unwrap();
+
+        let starwars = StarWars::from_path(&path).unwrap();
+        assert_eq!(starwars.hero(None).name(), "R2");
+        assert!(!starwars.hero(None).is_human());
+        assert_eq!(starwars.characters().len(), 2);
+
+        // Any other extension is read as JSON.
+        let path = dir.path().join("fixture.txt");
+        std::fs::write(&path, "hero: '2'").unwrap();
+        assert!(matches!(
+            StarWars::from_path(&path),
+            Err(FixtureError::Json(_))
+        ));
+        assert!(matches!(
+            StarWars::from_path(dir.path().join("missing.json")),
+            Err(FixtureError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn fixture_with_duplicate_id() {
+        let error = load_error(
+            r#"{ "hero": "1", "humans": [{ "id": "1", "name": "Luke" }], "droids": [{ "id": "1", "name": "R2" }] }"#,
+        );
+        assert!(matches!(error, FixtureError::DuplicateId(id) if id == "1"));
+
+        let error = load_error(
+            r#"{ "hero": "1", "humans": [{ "id": "1", "name": "Luke" }], "starships": [{ "id": "1", "name": "X-Wing", "length": 12.5 }] }"#,
+        );
+        assert!(matches!(error, FixtureError::DuplicateId(id) if id == "1"));
+    }
+
+    #[test]
+    fn fixture_with_dangling_friend() {
+        let error = load_error(
+            r#"{ "hero": "1", "humans": [{ "id": "1", "name": "Luke", "friends": ["3"] }] }"#,
+        );
+        assert!(matches!(
+            error,
+            FixtureError::DanglingFriend { id, friend } if id == "1" && friend == "3"
+        ));
+    }
+
+    #[test]
+    fn fixture_with_unknown_hero() {
+        let error = load_error(r#"{ "hero": "2", "humans": [{ "id": "1", "name": "Luke" }] }"#);
+        assert!(matches!(error, FixtureError::UnknownHero(id) if id == "2"));
+
+        let error = load_error(
+            r#"{ "hero": "1", "episodeHeroes": { "JEDI": "2" }, "humans": [{ "id": "1", "name": "Luke" }] }"#,
+        );
+        assert!(matches!(error, FixtureError::UnknownHero(id) if id == "2"));
+    }
+}
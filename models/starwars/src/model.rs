@@ -1,15 +1,18 @@
 #![allow(clippy::needless_lifetimes)]
 
 use async_graphql::{
-    Context, Enum, Error, Interface, Object, OutputType, Result,
-    connection::{Connection, Edge, query},
+    Context, Enum, ID, InputObject, Interface, Object, Result, SimpleObject, Subscription, Union,
+    connection::Connection,
 };
+use futures_util::{Stream, StreamExt, future};
+use serde::Deserialize;
 
 use super::StarWars;
-use crate::StarWarsChar;
+use crate::{StarWarsChar, StarWarsNode, StarWarsStarship, offset_connection};
 
 /// One of the films in the Star Wars Trilogy
-#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+#[derive(Enum, Copy, Clone, Eq, PartialEq, Hash, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum Episode {
     /// Released in 1977.
     NewHope,
@@ -27,13 +30,13 @@ pub struct Human<'a>(&'a StarWarsChar);
 #[Object]
 impl<'a> Human<'a> {
     /// The id of the human.
-    async fn id(&self) -> &str {
-        self.0.id
+    async fn id(&self) -> ID {
+        self.0.global_id().into()
     }
 
     /// The name of the human.
     async fn name(&self) -> &str {
-        self.0.name
+        &self.0.name
     }
 
     /// The friends of the human, or an empty list if they have none.
@@ -41,6 +44,7 @@ impl<'a> Human<'a> {
         let star_wars = ctx.data_unchecked::<StarWars>();
         star_wars
             .friends(self.0)
+            .await
             .into_iter()
             .map(|ch| {
                 if ch.is_human {
@@ -58,8 +62,8 @@ impl<'a> Human<'a> {
     }
 
     /// The home planet of the human, or null if unknown.
-    async fn home_planet(&self) -> &Option<&str> {
-        &self.0.home_planet
+    async fn home_planet(&self) -> Option<&str> {
+        self.0.home_planet.as_deref()
     }
 }
 
@@ -69,13 +73,13 @@ pub struct Droid<'a>(&'a StarWarsChar);
 #[Object]
 impl<'a> Droid<'a> {
     /// The id of the droid.
-    async fn id(&self) -> &str {
-        self.0.id
+    async fn id(&self) -> ID {
+        self.0.global_id().into()
     }
 
     /// The name of the droid.
     async fn name(&self) -> &str {
-        self.0.name
+        &self.0.name
     }
 
     /// The friends of the droid, or an empty list if they have none.
@@ -83,6 +87,7 @@ impl<'a> Droid<'a> {
         let star_wars = ctx.data_unchecked::<StarWars>();
         star_wars
             .friends(self.0)
+            .await
             .into_iter()
             .map(|ch| {
                 if ch.is_human {
@@ -100,8 +105,43 @@ impl<'a> Droid<'a> {
     }
 
     /// The primary function of the droid.
-    async fn primary_function(&self) -> &Option<&str> {
-        &self.0.primary_function
+    async fn primary_function(&self) -> Option<&str> {
+        self.0.primary_function.as_deref()
+    }
+}
+
+/// The unit of a length.
+#[derive(Enum, Copy, Clone, Eq, PartialEq, Default)]
+pub enum LengthUnit {
+    /// The standard unit around the world.
+    #[default]
+    Meter,
+
+    /// Primarily used in the United States.
+    Foot,
+}
+
+pub struct Starship<'a>(&'a StarWarsStarship);
+
+/// A ship in the Star Wars universe.
+#[Object]
+impl<'a> Starship<'a> {
+    /// The id of the starship.
+    async fn id(&self) -> ID {
+        self.0.global_id().into()
+    }
+
+    /// The name of the starship.
+    async fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    /// The length of the starship, in meters unless another unit is given.
+    async fn length(&self, #[graphql(default)] unit: LengthUnit) -> f64 {
+        match unit {
+            LengthUnit::Meter => self.0.length,
+            LengthUnit::Foot => self.0.length * 3.28084,
+        }
     }
 }
 
@@ -117,23 +157,18 @@ impl QueryRoot {
         )]
         episode: Option<Episode>,
     ) -> Character<'a> {
-        let star_wars = ctx.data_unchecked::<StarWars>();
-        match episode {
-            Some(episode) => {
-                if episode == Episode::Empire {
-                    Human(star_wars.chars.get(star_wars.luke).unwrap()).into()
-                } else {
-                    Droid(star_wars.chars.get(star_wars.artoo).unwrap()).into()
-                }
-            }
-            None => Human(star_wars.chars.get(star_wars.luke).unwrap()).into(),
+        let hero = ctx.data_unchecked::<StarWars>().hero(episode);
+        if hero.is_human {
+            Human(hero).into()
+        } else {
+            Droid(hero).into()
         }
     }
 
     async fn human<'a>(
         &self,
         ctx: &Context<'a>,
-        #[graphql(desc = "id of the human")] id: String,
+        #[graphql(desc = "id or global id of the human")] id: String,
     ) -> Option<Human<'a>> {
         ctx.data_unchecked::<StarWars>().human(&id).map(Human)
     }
@@ -147,13 +182,52 @@ impl QueryRoot {
         last: Option<i32>,
     ) -> Result<Connection<usize, Human<'a>>> {
         let humans = ctx.data_unchecked::<StarWars>().humans().to_vec();
-        query_characters(after, before, first, last, &humans, Human).await
+        offset_connection(after, before, first, last, &humans, |ch| Human(ch)).await
+    }
+
+    /// The reviews of `episode`, oldest first.
+    async fn reviews(&self, ctx: &Context<'_>, episode: Episode) -> Vec<Review> {
+        ctx.data_unchecked::<StarWars>().reviews(episode)
+    }
+
+    /// The characters and starships whose name contains `text`, ignoring case.
+    async fn search<'a>(&self, ctx: &Context<'a>, text: String) -> Vec<SearchResult<'a>> {
+        let star_wars = ctx.data_unchecked::<StarWars>();
+        let text = text.to_lowercase();
+        let matches = |name: &str| name.to_lowercase().contains(&text);
+        let chars = star_wars
+            .characters()
+            .into_iter()
+            .filter(|ch| matches(&ch.name))
+            .map(|ch| {
+                if ch.is_human {
+                    Human(ch).into()
+                } else {
+                    Droid(ch).into()
+                }
+            });
+        let starships = star_wars
+            .starships()
+            .into_iter()
+            .filter(|starship| matches(&starship.name))
+            .map(|starship| Starship(starship).into());
+        chars.chain(starships).collect()
+    }
+
+    /// Fetches an object given its id.
+    async fn node<'a>(&self, ctx: &Context<'a>, id: ID) -> Option<Node<'a>> {
+        let star_wars = ctx.data_unchecked::<StarWars>();
+        Some(match star_wars.node(&id)? {
+            StarWarsNode::Char(ch) if ch.is_human => Human(ch).into(),
+            StarWarsNode::Char(ch) => Droid(ch).into(),
+            StarWarsNode::Starship(starship) => Starship(starship).into(),
+        })
     }
 
     async fn droid<'a>(
         &self,
         ctx: &Context<'a>,
-        #[graphql(desc = "id of the droid")] id: String,
+        #[graphql(desc = "id or global id of the droid")] id: String,
     ) -> Option<Droid<'a>> {
         ctx.data_unchecked::<StarWars>().droid(&id).map(Droid)
     }
@@ -167,14 +241,79 @@ impl QueryRoot {
         last: Option<i32>,
     ) -> Result<Connection<usize, Droid<'a>>> {
         let droids = ctx.data_unchecked::<StarWars>().droids().to_vec();
-        query_characters(after, before, first, last, &droids, Droid).await
+        offset_connection(after, before, first, last, &droids, |ch| Droid(ch)).await
+    }
+}
+
+/// Represents a review for a movie.
+#[derive(SimpleObject, Clone)]
+pub struct Review {
+    /// The movie.
+    pub episode: Episode,
+
+    /// The number of stars this review gave, 1-5.
+    pub stars: i32,
+
+    /// Comment about the movie.
+    pub commentary: Option<String>,
+}
+
+/// The input object sent when someone is creating a new review.
+#[derive(InputObject)]
+pub struct ReviewInput {
+    /// 1-5 stars.
+    #[graphql(validator(minimum = 1, maximum = 5))]
+    stars: i32,
+
+    /// Comment about the movie, optional.
+    #[graphql(validator(min_length = 1, max_length = 500))]
+    commentary: Option<String>,
+}
+
+pub struct MutationRoot;
+
+#[Object]
+impl MutationRoot {
+    /// Add a review of `episode` and returns it.
+    async fn create_review(
+        &self,
+        ctx: &Context<'_>,
+        episode: Episode,
+        review: ReviewInput,
+    ) -> Review {
+        let review = Review {
+            episode,
+            stars: review.stars,
+            commentary: review.commentary,
+        };
+        ctx.data_unchecked::<StarWars>().add_review(review.clone());
+        review
+    }
+}
+
+pub struct SubscriptionRoot;
+
+#[Subscription]
+impl SubscriptionRoot {
+    /// The reviews created from now on, only those of `episode` if it is
+    /// specified.
+    async fn review_added(
+        &self,
+        ctx: &Context<'_>,
+        episode: Option<Episode>,
+    ) -> impl Stream<Item = Review> {
+        ctx.data_unchecked::<StarWars>()
+            .subscribe_reviews()
+            .filter(move |review| {
+                future::ready(episode.is_none_or(|episode| review.episode == episode))
+            })
     }
 }
 
 #[derive(Interface)]
 #[allow(clippy::duplicated_attributes)]
 #[graphql(
-    field(name = "id", ty = "&str"),
+    field(name = "id", ty = "ID"),
     field(name = "name", ty = "&str"),
     field(name = "friends", ty = "Vec<Character<'ctx>>"),
     field(name = "appears_in", ty = "&[Episode]")
@@ -184,60 +323,18 @@ pub enum Character<'a> {
     Droid(Droid<'a>),
 }
 
-async fn query_characters<'a, F, T>(
-    after: Option<String>,
-    before: Option<String>,
-    first: Option<i32>,
-    last: Option<i32>,
-    characters: &[&'a StarWarsChar],
-    map_to: F,
-) -> Result<Connection<usize, T>>
-where
-    F: Fn(&'a StarWarsChar) -> T,
-    T: OutputType,
-{
-    query(
-        after,
-        before,
-        first,
-        last,
-        |after, before, first, last| async move {
-            let mut start = 0usize;
-            let mut end = characters.len();
-
-            if let Some(after) = after {
-                if after >= characters.len() {
-                    return Ok(Connection::new(false, false));
-                }
-                start = after + 1;
-            }
+/// An object with an id that is unique among all objects.
+#[derive(Interface)]
+#[graphql(field(name = "id", ty = "ID"))]
+pub enum Node<'a> {
+    Human(Human<'a>),
+    Droid(Droid<'a>),
+    Starship(Starship<'a>),
+}
 
-            if let Some(before) = before {
-                if before == 0 {
-                    return Ok(Connection::new(false, false));
-                }
-                end = before;
-            }
-
-            let mut slice = &characters[start..end];
-
-            if let Some(first) = first {
-                slice = &slice[..first.min(slice.len())];
-                end -= first.min(slice.len());
-            } else if let Some(last) = last {
-                slice = &slice[slice.len() - last.min(slice.len())..];
-                start = end - last.min(slice.len());
-            }
-
-            let mut connection = Connection::new(start > 0, end < characters.len());
-            connection.edges.extend(
-                slice
-                    .iter()
-                    .enumerate()
-                    .map(|(idx, item)| Edge::new(start + idx, (map_to)(item))),
-            );
-            Ok::<_, Error>(connection)
-        },
-    )
-    .await
+#[derive(Union)]
+pub enum SearchResult<'a> {
+    Human(Human<'a>),
+    Droid(Droid<'a>),
+    Starship(Starship<'a>),
 }
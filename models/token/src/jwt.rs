@@ -0,0 +1,447 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use jsonwebtoken::{
+    Algorithm, DecodingKey, Validation, decode, decode_header,
+    errors::ErrorKind,
+    jwk::{AlgorithmParameters, JwkSet, KeyAlgorithm},
+};
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::{Claims, TokenError, TokenValidator};
+
+/// Where the keys of the tokens come from and which claims the tokens must
+/// have.
+///
+/// It can be deserialized from a configuration file, or read from the
+/// environment with [`JwtConfig::from_env`].
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct JwtConfig {
+    /// The secret of the HS256 tokens.
+    pub secret: Option<String>,
+
+    /// The PEM file of the RSA public key of the RS256 tokens.
+    pub public_key_file: Option<PathBuf>,
+
+    /// A JWKS file with the keys of the tokens, the key of a token is found
+    /// by the `kid` of its header.
+    pub jwks_file: Option<PathBuf>,
+
+    /// The accepted audiences, a token must have one of them if any is set.
+    /// Otherwise the tokens with an `aud` claim are rejected.
+    pub audience: Vec<String>,
+
+    /// The accepted issuers, a token must have one of them if any is set.
+    pub issuer: Vec<String>,
+
+    /// The number of seconds that `exp` and `nbf` may be off by, to allow
+    /// for clock skew.
+    pub leeway: Option<u64>,
+}
+
+impl JwtConfig {
+    /// Read the configuration from the `JWT_SECRET`, `JWT_PUBLIC_KEY_FILE`,
+    /// `JWT_JWKS_FILE`, `JWT_AUDIENCE`, `JWT_ISSUER` and `JWT_LEEWAY`
+    /// environment variables. The audiences and issuers are comma separated.
+    pub fn from_env() -> Self {
+        let var = |name| env::var(name).ok().filter(|value| !value.is_empty());
+        let list = |name| {
+            var(name)
+                .map(|value| {
+                    value
+                        .split(',')
+                        .map(|item| item.trim().to_string())
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+        Self {
+            secret: var("JWT_SECRET"),
+            public_key_file: var("JWT_PUBLIC_KEY_FILE").map(PathBuf::from),
+            jwks_file: var("JWT_JWKS_FILE").map(PathBuf::from),
+            audience: list("JWT_AUDIENCE"),
+            issuer: list("JWT_ISSUER"),
+            leeway: var("JWT_LEEWAY").and_then(|leeway| leeway.parse().ok()),
+        }
+    }
+}
+
+/// The reasons the keys of a [`JwtConfig`] can't be loaded.
+#[derive(Debug, Error)]
+pub enum KeyError {
+    #[error("failed to read `{}`: {source}", path.display())]
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[error("invalid JWKS file: {0}")]
+    Jwks(serde_json::Error),
+
+    #[error("invalid key: {0}")]
+    Invalid(jsonwebtoken::errors::Error),
+
+    #[error("the key {0} is neither an HS256 nor an RS256 key")]
+    Unsupported(String),
+
+    #[error("no key is configured")]
+    NoKey,
+}
+
+/// A key that the signature of the tokens is checked with.
+struct Key {
+    kid: Option<String>,
+    algorithm: Algorithm,
+    key: DecodingKey,
+}
+
+/// A validator of JSON Web Tokens signed with HS256 or RS256.
+///
+/// Besides the signature, the `exp` claim is required and checked, as are
+/// `nbf`, `aud` and `iss` when they are present or configured. The algorithm
+/// of a token must be the one of its key, whatever its header says.
+pub struct JwtValidator {
+    keys: Vec<Key>,
+    validation: Validation,
+}
+
+impl JwtValidator {
+    /// Create a validator from a configuration, reading its key files.
+    pub fn new(config: &JwtConfig) -> Result<Self, KeyError> {
+        let mut keys = Vec::new();
+        if let Some(secret) = &config.secret {
+            keys.push(Key {
+                kid: None,
+                algorithm: Algorithm::HS256,
+                key: DecodingKey::from_secret(secret.as_bytes()),
+            });
+        }
+        if let Some(path) = &config.public_key_file {
+            keys.push(Key {
+                kid: None,
+                algorithm: Algorithm::RS256,
+                key: DecodingKey::from_rsa_pem(&read(path)?).map_err(KeyError::Invalid)?,
+            });
+        }
+        if let Some(path) = &config.jwks_file {
+            let jwks: JwkSet = serde_json::from_slice(&read(path)?).map_err(KeyError::Jwks)?;
+            for jwk in &jwks.keys {
+                let kid = jwk.common.key_id.clone();
+                let algorithm = match (&jwk.algorithm, jwk.common.key_algorithm) {
+                    (AlgorithmParameters::OctetKey(_), None | Some(KeyAlgorithm::HS256)) => {
+                        Algorithm::HS256
+                    }
+                    (AlgorithmParameters::RSA(_), None | Some(KeyAlgorithm::RS256)) => {
+                        Algorithm::RS256
+                    }
+                    _ => return Err(KeyError::Unsupported(kid.unwrap_or_default())),
+                };
+                keys.push(Key {
+                    kid,
+                    algorithm,
+                    key: DecodingKey::from_jwk(jwk).map_err(KeyError::Invalid)?,
+                });
+            }
+        }
+        if keys.is_empty() {
+            return Err(KeyError::NoKey);
+        }
+
+        let mut validation = Validation::new(Algorithm::HS256);
+        validation.validate_nbf = true;
+        if let Some(leeway) = config.leeway {
+            validation.leeway = leeway;
+        }
+        let mut required_claims = vec!["exp", "sub"];
+        if !config.audience.is_empty() {
+            validation.set_audience(&config.audience);
+            required_claims.push("aud");
+        }
+        if !config.issuer.is_empty() {
+            validation.set_issuer(&config.issuer);
+            required_claims.push("iss");
+        }
+        validation.set_required_spec_claims(&required_claims);
+
+        Ok(Self { keys, validation })
+    }
+}
+
+impl TokenValidator for JwtValidator {
+    fn validate(&self, token: &str) -> Result<Claims, TokenError> {
+        let header = decode_header(token).map_err(invalid)?;
+        let key = self
+            .keys
+            .iter()
+            .find(|key| {
+                key.algorithm == header.alg
+                    && match (&header.kid, &key.kid) {
+                        (Some(kid), Some(key_id)) => kid == key_id,
+                        _ => true,
+                    }
+            })
+            .ok_or(TokenError::UnknownKey)?;
+        let mut validation = self.validation.clone();
+        validation.algorithms = vec![key.algorithm];
+        let data = decode::<Claims>(token, &key.key, &validation).map_err(invalid)?;
+        Ok(data.claims)
+    }
+}
+
+fn invalid(err: jsonwebtoken::errors::Error) -> TokenError {
+    match err.kind() {
+        ErrorKind::ExpiredSignature => TokenError::Expired,
+        _ => TokenError::Invalid(err.to_string()),
+    }
+}
+
+fn read(path: &Path) -> Result<Vec<u8>, KeyError> {
+    fs::read(path).map_err(|source| KeyError::Read {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::{
+        io::Write,
+        time::{SystemTime, UNIX_EPOCH},
+    };
+
+    use jsonwebtoken::{EncodingKey, Header, encode};
+    use serde_json::{Value, json};
+
+    use super::*;
+
+    const SECRET: &str = "secret";
+
+    /// The RSA public key of the RS256 tokens, whose private key is unknown.
+    const PUBLIC_KEY: &str = "-----BEGIN PUBLIC KEY-----
+MIIBIjANBgkqhkiG9w0BAQEFAAOCAQ8AMIIBCgKCAQEA3ZM3kIdT0i4JZFfXaQgL
++m1yHOGb+pAkEuLFuSGeqSGYl6d1xPzKJhmQ7/XcBM0dIVlBMhc+8+mYNnDK1XMZ
+AKkUh5C4IXAKkL8HkIe5fEL0eT8i+nMdTvCOJGuV6YTOWTugVpJfLFPMzLpu+gTW
+Q3OPVxPQOBJ+vCoI9Dw/FQErVLImLtbeZJX+LFY4jQLUrARbQKFQb7MFFD5OgAPW
+Mh3lLfb2V+dvD1gKEqNiQez3f836/2I1QIA8dxDERnlupyHLUTUEEQaYdHAEYAt+
+pwmxbH33MMKJ3+F36DijeIZP1hRxFhHTpeplrw5DYnE/dR9qK6s85Mb0eMl7cU4L
+sQIDAQAB
+-----END PUBLIC KEY-----
+";
+
+    pub(crate) fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    /// An HS256 token with `claims`, signed with `secret`.
+    pub(crate) fn token(claims: Value, secret: &str, kid: Option<&str>) -> String {
+        let mut header = Header::new(Algorithm::HS256);
+        header.kid = kid.map(str::to_string);
+        encode(
+            &header,
+            &claims,
+            &EncodingKey::from_secret(secret.as_bytes()),
+        )
+        .unwrap()
+    }
+
+    pub(crate) fn validator() -> JwtValidator {
+        JwtValidator::new(&JwtConfig {
+            secret: Some(SECRET.to_string()),
+            ..JwtConfig::default()
+        })
+        .unwrap()
+    }
+
+    fn file(content: &str) -> tempfile::NamedTempFile {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(content.as_bytes()).unwrap();
+        file
+    }
+
+    #[test]
+    fn valid_token() {
+        let token = token(
+            json!({ "sub": "1", "exp": now() + 60, "scope": "read write" }),
+            SECRET,
+            None,
+        );
+        let claims = validator().validate(&token).unwrap();
+        assert_eq!(claims.sub, "1");
+        assert_eq!(claims.scopes, ["read", "write"]);
+    }
+
+    #[test]
+    fn bad_signature() {
+        let token = token(json!({ "sub": "1", "exp": now() + 60 }), "other", None);
+        assert!(matches!(
+            validator().validate(&token),
+            Err(TokenError::Invalid(_))
+        ));
+        assert!(matches!(
+            validator().validate("not a token"),
+            Err(TokenError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn expired_token() {
+        let expired = token(json!({ "sub": "1", "exp": now() - 3600 }), SECRET, None);
+        assert!(matches!(
+            validator().validate(&expired),
+            Err(TokenError::Expired)
+        ));
+        let without_exp = token(json!({ "sub": "1" }), SECRET, None);
+        assert!(matches!(
+            validator().validate(&without_exp),
+            Err(TokenError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn token_not_yet_valid() {
+        let token = token(
+            json!({ "sub": "1", "exp": now() + 7200, "nbf": now() + 3600 }),
+            SECRET,
+            None,
+        );
+        assert!(matches!(
+            validator().validate(&token),
+            Err(TokenError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn missing_subject() {
+        let token = token(json!({ "exp": now() + 60 }), SECRET, None);
+        assert!(matches!(
+            validator().validate(&token),
+            Err(TokenError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn audience_and_issuer() {
+        let validator = JwtValidator::new(&JwtConfig {
+            secret: Some(SECRET.to_string()),
+            audience: vec!["api".to_string()],
+            issuer: vec!["https://auth.example.com".to_string()],
+            ..JwtConfig::default()
+        })
+        .unwrap();
+        let claims =
+            |aud: &str, iss: &str| json!({ "sub": "1", "exp": now() + 60, "aud": aud, "iss": iss });
+
+        let valid = token(claims("api", "https://auth.example.com"), SECRET, None);
+        assert_eq!(validator.validate(&valid).unwrap().sub, "1");
+        for claims in [
+            claims("other", "https://auth.example.com"),
+            claims("api", "https://other.example.com"),
+            json!({ "sub": "1", "exp": now() + 60, "iss": "https://auth.example.com" }),
+            json!({ "sub": "1", "exp": now() + 60, "aud": "api" }),
+        ] {
+            let token = token(claims, SECRET, None);
+            assert!(matches!(
+                validator.validate(&token),
+                Err(TokenError::Invalid(_))
+            ));
+        }
+
+        // Without a configured audience, the tokens meant for one are rejected.
+        let token = token(
+            json!({ "sub": "1", "exp": now() + 60, "aud": "api" }),
+            SECRET,
+            None,
+        );
+        assert!(matches!(
+            self::validator().validate(&token),
+            Err(TokenError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn algorithm_mismatch() {
+        let public_key = file(PUBLIC_KEY);
+        let validator = JwtValidator::new(&JwtConfig {
+            public_key_file: Some(public_key.path().to_path_buf()),
+            ..JwtConfig::default()
+        })
+        .unwrap();
+
+        // An HS256 token isn't checked with the RS256 key, even when it is
+        // signed with the public key as its secret.
+        let claims = json!({ "sub": "1", "exp": now() + 60 });
+        for secret in [SECRET, PUBLIC_KEY] {
+            let token = token(claims.clone(), secret, None);
+            assert!(matches!(
+                validator.validate(&token),
+                Err(TokenError::UnknownKey)
+            ));
+        }
+    }
+
+    #[test]
+    fn keys_of_a_jwks() {
+        let jwks = file(
+            &json!({
+                "keys": [
+                    { "kty": "oct", "kid": "a", "alg": "HS256", "k": "c2VjcmV0" },
+                    { "kty": "oct", "kid": "b", "alg": "HS256", "k": "b3RoZXI" },
+                ]
+            })
+            .to_string(),
+        );
+        let validator = JwtValidator::new(&JwtConfig {
+            jwks_file: Some(jwks.path().to_path_buf()),
+            ..JwtConfig::default()
+        })
+        .unwrap();
+        let claims = json!({ "sub": "1", "exp": now() + 60 });
+
+        let token_a = token(claims.clone(), "secret", Some("a"));
+        assert_eq!(validator.validate(&token_a).unwrap().sub, "1");
+        let token_b = token(claims.clone(), "other", Some("b"));
+        assert_eq!(validator.validate(&token_b).unwrap().sub, "1");
+        let token_c = token(claims.clone(), "secret", Some("c"));
+        assert!(matches!(
+            validator.validate(&token_c),
+            Err(TokenError::UnknownKey)
+        ));
+        let wrong_key = token(claims, "secret", Some("b"));
+        assert!(matches!(
+            validator.validate(&wrong_key),
+            Err(TokenError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn invalid_configurations() {
+        assert!(matches!(
+            JwtValidator::new(&JwtConfig::default()),
+            Err(KeyError::NoKey)
+        ));
+        let missing = JwtConfig {
+            public_key_file: Some(PathBuf::from("/nonexistent/key.pem")),
+            ..JwtConfig::default()
+        };
+        assert!(matches!(
+            JwtValidator::new(&missing),
+            Err(KeyError::Read { .. })
+        ));
+        let jwks = file("{}");
+        let invalid = JwtConfig {
+            jwks_file: Some(jwks.path().to_path_buf()),
+            ..JwtConfig::default()
+        };
+        assert!(matches!(
+            JwtValidator::new(&invalid),
+            Err(KeyError::Jwks(_))
+        ));
+    }
+}
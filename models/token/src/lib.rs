@@ -1,17 +1,155 @@
-use async_graphql::{Context, Data, EmptyMutation, Object, Result, Schema, Subscription};
+mod jwt;
+
+use std::sync::Arc;
+
+use async_graphql::{
+    Context, Data, EmptyMutation, Error, ErrorExtensions, Object, Pos, Request, Result, Schema,
+    ServerError, SimpleObject, Subscription,
+};
 use futures_util::Stream;
-use serde::Deserialize;
+pub use jwt::{JwtConfig, JwtValidator, KeyError};
+use serde::{Deserialize, Deserializer};
+use thiserror::Error;
 
 pub type TokenSchema = Schema<QueryRoot, EmptyMutation, SubscriptionRoot>;
 
-pub struct Token(pub String);
+/// The claims of a valid token, put into the request data.
+#[derive(Clone, Debug, Deserialize, SimpleObject)]
+#[graphql(name = "User")]
+pub struct Claims {
+    /// The subject of the token, the id of the user.
+    pub sub: String,
+
+    /// The scopes granted to the token.
+    #[serde(
+        rename = "scope",
+        alias = "scp",
+        alias = "scopes",
+        default,
+        deserialize_with = "deserialize_scopes"
+    )]
+    pub scopes: Vec<String>,
+}
+
+/// Deserializes the scopes of a token, either a space separated string like
+/// the `scope` claim of OAuth 2.0 or a list.
+fn deserialize_scopes<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Scopes {
+        Joined(String),
+        List(Vec<String>),
+    }
+
+    Ok(match Scopes::deserialize(deserializer)? {
+        Scopes::Joined(scopes) => scopes.split_whitespace().map(str::to_string).collect(),
+        Scopes::List(scopes) => scopes,
+    })
+}
+
+/// A validator of the tokens sent by the clients.
+pub trait TokenValidator: Send + Sync {
+    /// Checks `token` and returns its claims.
+    fn validate(&self, token: &str) -> Result<Claims, TokenError>;
+}
+
+/// The reasons a token is rejected.
+#[derive(Debug, Error)]
+pub enum TokenError {
+    #[error("the Authorization header must be `Bearer <token>`")]
+    Malformed,
+
+    #[error("no key matches the token")]
+    UnknownKey,
+
+    #[error("the token has expired")]
+    Expired,
+
+    #[error("invalid token: {0}")]
+    Invalid(String),
+}
+
+impl ErrorExtensions for TokenError {
+    fn extend(&self) -> Error {
+        self.extend_with(|err, e| match err {
+            TokenError::Expired => e.set("code", "TOKEN_EXPIRED"),
+            _ => e.set("code", "INVALID_TOKEN"),
+        })
+    }
+}
+
+/// The validator used by the servers of the token schema.
+///
+/// ```ignore
+/// let validator = SharedValidator::jwt(&JwtConfig::from_env())?;
+/// let request = validator.authenticate(request, Some("Bearer eyJhbGciOi..."))?;
+/// ```
+#[derive(Clone)]
+pub struct SharedValidator(Arc<dyn TokenValidator>);
+
+impl SharedValidator {
+    /// Create a shared validator from a validator.
+    pub fn new(validator: impl TokenValidator + 'static) -> Self {
+        Self(Arc::new(validator))
+    }
+
+    /// Create a shared validator of JSON Web Tokens.
+    pub fn jwt(config: &JwtConfig) -> Result<Self, KeyError> {
+        Ok(Self::new(JwtValidator::new(config)?))
+    }
+
+    /// Checks `token` and returns its claims.
+    pub fn validate(&self, token: &str) -> Result<Claims, TokenError> {
+        self.0.validate(token)
+    }
+
+    /// Checks the bearer token of the `Authorization` header of a request, if
+    /// there is one, and puts its claims into the request data.
+    pub fn authenticate(
+        &self,
+        request: Request,
+        authorization: Option<&str>,
+    ) -> Result<Request, ServerError> {
+        let Some(authorization) = authorization else {
+            return Ok(request);
+        };
+        let claims = authorization
+            .strip_prefix("Bearer ")
+            .ok_or(TokenError::Malformed)
+            .and_then(|token| self.validate(token.trim()))
+            .map_err(|err| err.extend().into_server_error(Pos::default()))?;
+        Ok(request.data(claims))
+    }
+
+    // For more details see:
+    // https://github.com/enisdenjo/graphql-ws/blob/master/PROTOCOL.md#connectioninit
+    pub async fn on_connection_init(self, value: serde_json::Value) -> Result<Data> {
+        #[derive(Deserialize)]
+        struct Payload {
+            token: String,
+        }
+
+        // Coerce the connection params into our `Payload` struct so we can
+        // validate the token exists in the headers.
+        if let Ok(payload) = serde_json::from_value::<Payload>(value) {
+            let claims = self.validate(&payload.token).map_err(|err| err.extend())?;
+            let mut data = Data::default();
+            data.insert(claims);
+            Ok(data)
+        } else {
+            Err("Token is required".into())
+        }
+    }
+}
 
 pub struct QueryRoot;
 
 #[Object]
 impl QueryRoot {
-    async fn current_token<'a>(&self, ctx: &'a Context<'_>) -> Option<&'a str> {
-        ctx.data_opt::<Token>().map(|token| token.0.as_str())
+    /// The user that the token of the request was issued to, null without a
+    /// token.
+    async fn current_user<'a>(&self, ctx: &'a Context<'_>) -> Option<&'a Claims> {
+        ctx.data_opt::<Claims>()
     }
 }
 
@@ -20,28 +158,128 @@ pub struct SubscriptionRoot;
 #[Subscription]
 impl SubscriptionRoot {
     async fn values(&self, ctx: &Context<'_>) -> Result<impl Stream<Item = i32>> {
-        if ctx.data::<Token>()?.0 != "123456" {
+        if ctx.data_opt::<Claims>().is_none() {
             return Err("Forbidden".into());
         }
         Ok(futures_util::stream::once(async move { 10 }))
     }
 }
 
-// For more details see:
-// https://github.com/enisdenjo/graphql-ws/blob/master/PROTOCOL.md#connectioninit
-pub async fn on_connection_init(value: serde_json::Value) -> Result<Data> {
-    #[derive(Deserialize)]
-    struct Payload {
-        token: String,
-    }
-
-    // Coerce the connection params into our `Payload` struct so we can
-    // validate the token exists in the headers.
-    if let Ok(payload) = serde_json::from_value::<Payload>(value) {
-        let mut data = Data::default();
-        data.insert(Token(payload.token));
-        Ok(data)
-    } else {
-        Err("Token is required".into())
+#[cfg(test)]
+mod tests {
+    use std::any::TypeId;
+
+    use async_graphql::Value;
+    use serde_json::json;
+
+    use super::*;
+    use crate::jwt::tests::{now, token, validator};
+
+    fn schema() -> TokenSchema {
+        Schema::new(QueryRoot, EmptyMutation, SubscriptionRoot)
+    }
+
+    fn code(extensions: Option<&async_graphql::ErrorExtensionValues>) -> Option<&Value> {
+        extensions.and_then(|extensions| extensions.get("code"))
+    }
+
+    #[tokio::test]
+    async fn authenticate() {
+        let validator = SharedValidator::new(validator());
+        let query = "{ currentUser { sub } }";
+
+        let request = validator.authenticate(Request::new(query), None).unwrap();
+        let response = schema().execute(request).await;
+        assert_eq!(
+            response.data.into_json().unwrap(),
+            json!({ "currentUser": null })
+        );
+
+        let token = token(json!({ "sub": "1", "exp": now() + 60 }), "secret", None);
+        let authorization = format!("Bearer {token}");
+        let request = validator
+            .authenticate(Request::new(query), Some(&authorization))
+            .unwrap();
+        let response = schema().execute(request).await;
+        assert_eq!(
+            response.data.into_json().unwrap(),
+            json!({ "currentUser": { "sub": "1" } })
+        );
+    }
+
+    #[test]
+    fn rejected_authorizations() {
+        let validator = SharedValidator::new(validator());
+        let expired = token(json!({ "sub": "1", "exp": now() - 3600 }), "secret", None);
+
+        for (authorization, message, expected_code) in [
+            (
+                format!("Basic {expired}"),
+                TokenError::Malformed.to_string(),
+                "INVALID_TOKEN",
+            ),
+            (
+                "Bearer".to_string(),
+                TokenError::Malformed.to_string(),
+                "INVALID_TOKEN",
+            ),
+            (
+                "Bearer not-a-token".to_string(),
+                "invalid token: InvalidToken".to_string(),
+                "INVALID_TOKEN",
+            ),
+            (
+                format!("Bearer {expired}"),
+                TokenError::Expired.to_string(),
+                "TOKEN_EXPIRED",
+            ),
+        ] {
+            let err = validator
+                .authenticate(
+                    Request::new("{ currentUser { sub } }"),
+                    Some(&authorization),
+                )
+                .unwrap_err();
+            assert_eq!(err.message, message);
+            assert_eq!(
+                code(err.extensions.as_ref()),
+                Some(&Value::from(expected_code))
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn on_connection_init() {
+        let validator = SharedValidator::new(validator());
+        let token = token(json!({ "sub": "1", "exp": now() + 60 }), "secret", None);
+
+        let data = validator
+            .clone()
+            .on_connection_init(json!({ "token": token }))
+            .await
+            .unwrap();
+        let claims = data
+            .get(&TypeId::of::<Claims>())
+            .and_then(|claims| claims.downcast_ref::<Claims>())
+            .unwrap();
+        assert_eq!(claims.sub, "1");
+
+        for payload in [json!({}), json!({ "token": 1 }), json!(null)] {
+            let err = validator
+                .clone()
+                .on_connection_init(payload)
+                .await
+                .unwrap_err();
+            assert_eq!(err.message, "Token is required");
+        }
+
+        let err = validator
+            .on_connection_init(json!({ "token": "not-a-token" }))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            code(err.extensions.as_ref()),
+            Some(&Value::from("INVALID_TOKEN"))
+        );
     }
 }
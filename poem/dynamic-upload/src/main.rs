@@ -1,18 +1,118 @@
-use async_graphql::http::GraphiQLSource;
-use async_graphql_poem::GraphQL;
-use poem::{IntoResponse, Route, Server, get, handler, listener::TcpListener, web::Html};
+use std::io;
+
+use async_graphql::{
+    dynamic::Schema,
+    http::{GraphiQLSource, MultipartOptions},
+};
+use async_graphql_poem::{GraphQLResponse, GraphQLSubscription};
+use dynamic_files::{Download, Storage, UPLOAD_ID_HEADER, UploadPolicy};
+use futures_util::TryStreamExt;
+use poem::{
+    Body, EndpointExt, IntoResponse, Request, Response, Result, Route, Server,
+    error::{BadRequest, InternalServerError},
+    get, handler,
+    http::{StatusCode, header},
+    listener::TcpListener,
+    web::{Data, Html, Path},
+};
 
 #[handler]
 async fn graphiql() -> impl IntoResponse {
-    Html(GraphiQLSource::build().endpoint("/").finish())
+    Html(
+        GraphiQLSource::build()
+            .endpoint("/")
+            .subscription_endpoint("/ws")
+            .finish(),
+    )
+}
+
+/// Executes the request with its files streamed to the resolvers as they are
+/// received, the multipart options of the upload policy reject the files that
+/// are too large.
+#[handler]
+async fn index(
+    req: &Request,
+    body: Body,
+    schema: Data<&Schema>,
+    Data(options): Data<&MultipartOptions>,
+) -> Result<GraphQLResponse> {
+    let content_type = req.header(header::CONTENT_TYPE).map(ToString::to_string);
+    let body = body
+        .into_bytes_stream()
+        .map_err(io::Error::other)
+        .into_async_read();
+    let response = dynamic_files::execute(*schema, content_type.as_deref(), body, *options)
+        .await
+        .map_err(BadRequest)?;
+    Ok(response.into())
+}
+
+#[handler]
+async fn download(
+    req: &Request,
+    Path(id): Path<String>,
+    Data(storage): Data<&Storage>,
+) -> Result<Response> {
+    let range = req.header(header::RANGE);
+    Ok(
+        match storage
+            .download(&id, range)
+            .await
+            .map_err(InternalServerError)?
+        {
+            Some(file) => file_response(file),
+            None => StatusCode::NOT_FOUND.into_response(),
+        },
+    )
+}
+
+/// Sends the progress of the uploads that have an id as their body is
+/// received.
+async fn track_upload(mut req: Request) -> Result<Request> {
+    let Some(upload_id) = req.header(UPLOAD_ID_HEADER).map(ToString::to_string) else {
+        return Ok(req);
+    };
+    let content_length = req
+        .header(header::CONTENT_LENGTH)
+        .and_then(|value| value.parse().ok());
+    let storage = req.data::<Storage>().unwrap().clone();
+    let body = req.take_body().into_bytes_stream();
+    req.set_body(Body::from_bytes_stream(storage.track_upload(
+        &upload_id,
+        content_length,
+        body,
+    )));
+    Ok(req)
+}
+
+fn file_response(file: Download) -> Response {
+    let mut builder = Response::builder()
+        .status(StatusCode::from_u16(file.status).unwrap())
+        .content_type(&file.content_type)
+        .header(header::CONTENT_LENGTH, file.content_length)
+        .header(header::ACCEPT_RANGES, "bytes");
+    if let Some(content_range) = &file.content_range {
+        builder = builder.header(header::CONTENT_RANGE, content_range);
+    }
+    builder.body(Body::from_bytes_stream(file.into_stream()))
 }
 
 #[tokio::main]
 async fn main() {
-    let app = Route::new().at(
-        "/",
-        get(graphiql).post(GraphQL::new(dynamic_files::schema().unwrap())),
-    );
+    let storage = Storage::local("uploads")
+        .unwrap()
+        .with_base_url("http://localhost:8000");
+    let policy = UploadPolicy::default()
+        .max_file_size(1024 * 1024 * 1024)
+        .max_total_size(4 * 1024 * 1024 * 1024);
+    let schema = dynamic_files::schema(storage.clone(), policy.clone()).unwrap();
+    let app = Route::new()
+        .at("/", get(graphiql).post(index.before(track_upload)))
+        .at("/ws", get(GraphQLSubscription::new(schema.clone())))
+        .at("/files/:id", get(download))
+        .data(schema)
+        .data(storage)
+        .data(policy.multipart_options());
 
     println!("GraphiQL IDE: http://localhost:8000");
     Server::new(TcpListener::bind("0.0.0.0:8000"))
@@ -1,20 +1,60 @@
-use async_graphql::{EmptyMutation, EmptySubscription, Schema, http::GraphiQLSource};
-use async_graphql_poem::GraphQL;
-use poem::{IntoResponse, Route, Server, get, handler, listener::TcpListener, web::Html};
-use starwars::{QueryRoot, StarWars};
+use std::io;
+
+use async_graphql::{Schema, http::GraphiQLSource};
+use async_graphql_poem::{GraphQLRequest, GraphQLResponse, GraphQLSubscription};
+use futures_util::StreamExt;
+use incremental_delivery::{CONTENT_TYPE, Delivery, accepts_multipart, multipart};
+use poem::{
+    Body, EndpointExt, IntoResponse, Response, Route, Server, get, handler,
+    http::{HeaderMap, header},
+    listener::TcpListener,
+    web::{Data, Html},
+};
+use starwars::{MutationRoot, QueryRoot, StarWars, StarWarsSchema, SubscriptionRoot};
 
 #[handler]
 async fn graphiql() -> impl IntoResponse {
-    Html(GraphiQLSource::build().endpoint("/").finish())
+    Html(
+        GraphiQLSource::build()
+            .endpoint("/")
+            .subscription_endpoint("/ws")
+            .finish(),
+    )
+}
+
+/// Answers with the `@defer` and `@stream` results as they come when the
+/// client accepts `multipart/mixed` responses.
+#[handler]
+async fn index(
+    schema: Data<&StarWarsSchema>,
+    headers: &HeaderMap,
+    req: GraphQLRequest,
+) -> Response {
+    let accept = headers
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok());
+    match incremental_delivery::execute(schema.0, req.0, accepts_multipart(accept)).await {
+        Delivery::Single(response) => GraphQLResponse(*response).into_response(),
+        Delivery::Incremental(payloads) => {
+            Response::builder()
+                .content_type(CONTENT_TYPE)
+                .body(Body::from_bytes_stream(
+                    multipart(payloads).map(Ok::<_, io::Error>),
+                ))
+        }
+    }
 }
 
 #[tokio::main]
 async fn main() {
-    let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+    let schema = Schema::build(QueryRoot, MutationRoot, SubscriptionRoot)
         .data(StarWars::new())
         .finish();
 
-    let app = Route::new().at("/", get(graphiql).post(GraphQL::new(schema)));
+    let app = Route::new()
+        .at("/", get(graphiql).post(index))
+        .at("/ws", get(GraphQLSubscription::new(schema.clone())))
+        .data(schema);
 
     println!("GraphiQL IDE: http://localhost:8000");
     Server::new(TcpListener::bind("127.0.0.1:8000"))
@@ -1,43 +1,8 @@
-use async_graphql::{Context, Object, Result, Schema, Subscription, http::GraphiQLSource};
+use async_graphql::{Schema, http::GraphiQLSource};
 use async_graphql_poem::{GraphQL, GraphQLSubscription};
-use futures_util::{Stream, StreamExt};
+use books::{MutationRoot, QueryRoot, SharedBroker, Storage, SubscriptionRoot};
 use poem::{IntoResponse, Route, Server, get, handler, listener::TcpListener, web::Html};
-use redis::{AsyncCommands, Client};
-
-struct QueryRoot;
-
-#[Object]
-impl QueryRoot {
-    async fn version(&self) -> &'static str {
-        std::env!("CARGO_PKG_VERSION")
-    }
-}
-
-struct MutationRoot;
-
-#[Object]
-impl MutationRoot {
-    async fn publish(&self, ctx: &Context<'_>, value: String) -> Result<bool> {
-        let client = ctx.data_unchecked::<Client>();
-        let mut conn = client.get_multiplexed_async_connection().await?;
-        conn.publish::<_, _, ()>("values", value).await?;
-        Ok(true)
-    }
-}
-
-struct SubscriptionRoot;
-
-#[Subscription]
-impl SubscriptionRoot {
-    async fn values(&self, ctx: &Context<'_>) -> Result<impl Stream<Item = String>> {
-        let client = ctx.data_unchecked::<Client>();
-        let mut conn = client.get_async_pubsub().await?;
-        conn.subscribe("values").await?;
-        Ok(conn
-            .into_on_message()
-            .filter_map(|msg| async move { msg.get_payload().ok() }))
-    }
-}
+use redis::Client;
 
 #[handler]
 async fn graphiql() -> impl IntoResponse {
@@ -51,18 +16,22 @@ async fn graphiql() -> impl IntoResponse {
 
 #[tokio::main]
 async fn main() {
+    // Every replica started with a different `PORT` shares the books through
+    // the database file and the book mutations through Redis.
+    let port = std::env::var("PORT").unwrap_or_else(|_| "8000".to_string());
     let client = Client::open("redis://127.0.0.1/").unwrap();
 
     let schema = Schema::build(QueryRoot, MutationRoot, SubscriptionRoot)
-        .data(client)
+        .data(Storage::sqlite("books.db").unwrap())
+        .data(SharedBroker::redis(client))
         .finish();
 
     let app = Route::new()
         .at("/", get(graphiql).post(GraphQL::new(schema.clone())))
         .at("/ws", get(GraphQLSubscription::new(schema)));
 
-    println!("GraphiQL IDE: http://localhost:8000");
-    Server::new(TcpListener::bind("127.0.0.1:8000"))
+    println!("GraphiQL IDE: http://localhost:{port}");
+    Server::new(TcpListener::bind(format!("127.0.0.1:{port}")))
         .run(app)
         .await
         .unwrap();
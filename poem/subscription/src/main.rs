@@ -1,3 +1,5 @@
+mod sse;
+
 use async_graphql::{Schema, http::GraphiQLSource};
 use async_graphql_poem::{GraphQL, GraphQLSubscription};
 use books::{MutationRoot, QueryRoot, Storage, SubscriptionRoot};
@@ -21,7 +23,8 @@ async fn main() {
 
     let app = Route::new()
         .at("/", get(graphiql).post(GraphQL::new(schema.clone())))
-        .at("/ws", get(GraphQLSubscription::new(schema)));
+        .at("/ws", get(GraphQLSubscription::new(schema.clone())))
+        .at("/sse", sse::endpoint(schema));
 
     println!("GraphiQL IDE: http://localhost:8000");
     Server::new(TcpListener::bind("127.0.0.1:8000"))
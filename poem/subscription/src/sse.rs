@@ -0,0 +1,91 @@
+//! The GraphQL over SSE endpoint, the protocol is implemented by the
+//! `sse-transport` crate.
+
+use async_graphql::http::parse_query_string;
+use async_graphql_poem::GraphQLRequest;
+use books::BooksSchema;
+use futures_util::{Stream, StreamExt};
+use poem::{
+    Endpoint, EndpointExt, IntoResponse, Request, Response, get, handler,
+    http::StatusCode,
+    web::{
+        Data, Query,
+        sse::{Event, SSE},
+    },
+};
+use sse_transport::{KEEP_ALIVE_INTERVAL, Params, Streams, TOKEN_HEADER, distinct};
+
+/// The endpoint of both modes, it only serves `schema`.
+pub fn endpoint(schema: BooksSchema) -> impl Endpoint {
+    get(listen)
+        .post(execute)
+        .put(reserve)
+        .delete(cancel)
+        .data(schema)
+        .data(Streams::default())
+}
+
+fn sse(events: impl Stream<Item = sse_transport::Event> + Send + 'static) -> Response {
+    SSE::new(events.map(|event| Event::message(event.data).event_type(event.event)))
+        .keep_alive(KEEP_ALIVE_INTERVAL)
+        .into_response()
+}
+
+#[handler]
+async fn reserve(Data(streams): Data<&Streams>) -> Response {
+    Response::builder()
+        .status(StatusCode::CREATED)
+        .content_type("text/plain; charset=utf-8")
+        .body(streams.reserve())
+}
+
+#[handler]
+async fn listen(
+    req: &Request,
+    Query(params): Query<Params>,
+    Data(schema): Data<&BooksSchema>,
+    Data(streams): Data<&Streams>,
+) -> Response {
+    match params.token(req.header(TOKEN_HEADER)) {
+        Some(token) => match streams.listen(&token) {
+            Ok(events) => sse(events),
+            Err(status) => status.into_response(),
+        },
+        None => match parse_query_string(req.uri().query().unwrap_or_default()) {
+            Ok(request) => sse(distinct(schema, request)),
+            Err(err) => (StatusCode::BAD_REQUEST, err.to_string()).into_response(),
+        },
+    }
+}
+
+#[handler]
+async fn execute(
+    req: &Request,
+    Query(params): Query<Params>,
+    Data(schema): Data<&BooksSchema>,
+    Data(streams): Data<&Streams>,
+    GraphQLRequest(request): GraphQLRequest,
+) -> Response {
+    match params.token(req.header(TOKEN_HEADER)) {
+        Some(token) => match streams.execute(&token, schema, request) {
+            Ok(()) => StatusCode::ACCEPTED.into_response(),
+            Err(status) => status.into_response(),
+        },
+        None => sse(distinct(schema, request)),
+    }
+}
+
+#[handler]
+async fn cancel(
+    req: &Request,
+    Query(params): Query<Params>,
+    Data(streams): Data<&Streams>,
+) -> StatusCode {
+    match (
+        params.token(req.header(TOKEN_HEADER)),
+        params.operation_id(),
+    ) {
+        (Some(token), Some(id)) => streams.cancel(&token, id),
+        _ => StatusCode::BAD_REQUEST,
+    }
+}
@@ -1,5 +1,5 @@
 use async_graphql::{
-    EmptyMutation, Schema,
+    EmptyMutation, Response, Schema,
     http::{ALL_WEBSOCKET_PROTOCOLS, GraphiQLSource},
 };
 use async_graphql_poem::{GraphQLProtocol, GraphQLRequest, GraphQLResponse, GraphQLWebSocket};
@@ -9,12 +9,12 @@ use poem::{
     listener::TcpListener,
     web::{Data, Html, websocket::WebSocket},
 };
-use token::{QueryRoot, SubscriptionRoot, Token, TokenSchema, on_connection_init};
+use token::{JwtConfig, QueryRoot, SharedValidator, SubscriptionRoot, TokenSchema};
 
-fn get_token_from_headers(headers: &HeaderMap) -> Option<Token> {
+fn get_authorization_from_headers(headers: &HeaderMap) -> Option<&str> {
     headers
-        .get("Token")
-        .and_then(|value| value.to_str().map(|s| Token(s.to_string())).ok())
+        .get("Authorization")
+        .and_then(|value| value.to_str().ok())
 }
 
 #[handler]
@@ -30,29 +30,31 @@ async fn graphiql() -> impl IntoResponse {
 #[handler]
 async fn index(
     schema: Data<&TokenSchema>,
+    validator: Data<&SharedValidator>,
     headers: &HeaderMap,
     req: GraphQLRequest,
 ) -> GraphQLResponse {
-    let mut req = req.0;
-    if let Some(token) = get_token_from_headers(headers) {
-        req = req.data(token);
+    match validator.authenticate(req.0, get_authorization_from_headers(headers)) {
+        Ok(req) => schema.execute(req).await.into(),
+        Err(err) => Response::from_errors(vec![err]).into(),
     }
-    schema.execute(req).await.into()
 }
 
 #[handler]
 async fn ws(
     schema: Data<&TokenSchema>,
+    validator: Data<&SharedValidator>,
     protocol: GraphQLProtocol,
     websocket: WebSocket,
 ) -> impl IntoResponse {
     let schema = schema.0.clone();
+    let validator = validator.0.clone();
     websocket
         .protocols(ALL_WEBSOCKET_PROTOCOLS)
         .on_upgrade(move |stream| {
             GraphQLWebSocket::new(stream, schema, protocol)
                 // connection params are used to extract the token in this fn
-                .on_connection_init(on_connection_init)
+                .on_connection_init(move |value| validator.on_connection_init(value))
                 .serve()
         })
 }
@@ -60,11 +62,22 @@ async fn ws(
 #[tokio::main]
 async fn main() {
     let schema = Schema::new(QueryRoot, EmptyMutation, SubscriptionRoot);
+    let validator = match SharedValidator::jwt(&JwtConfig::from_env()) {
+        Ok(validator) => validator,
+        Err(err) => {
+            eprintln!(
+                "Invalid JWT configuration: {err}. Set JWT_SECRET, JWT_PUBLIC_KEY_FILE or \
+                 JWT_JWKS_FILE, for example `JWT_SECRET=secret`."
+            );
+            std::process::exit(1);
+        }
+    };
 
     let app = Route::new()
         .at("/", get(graphiql).post(index))
         .at("/ws", get(ws))
-        .data(schema);
+        .data(schema)
+        .data(validator);
 
     println!("GraphiQL IDE: http://localhost:8000");
     Server::new(TcpListener::bind("127.0.0.1:8000"))
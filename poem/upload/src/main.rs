@@ -1,35 +1,127 @@
-use async_graphql::{EmptySubscription, Schema, http::GraphiQLSource};
-use async_graphql_poem::{GraphQLRequest, GraphQLResponse};
-use files::{FilesSchema, MutationRoot, QueryRoot, Storage};
+use std::io;
+
+use async_graphql::{
+    Schema,
+    http::{GraphiQLSource, MultipartOptions},
+};
+use async_graphql_poem::{GraphQLResponse, GraphQLSubscription};
+use files::{
+    Download, FilesSchema, MutationRoot, QueryRoot, Storage, SubscriptionRoot, UPLOAD_ID_HEADER,
+    UploadPolicy,
+};
+use futures_util::TryStreamExt;
 use poem::{
-    EndpointExt, IntoResponse, Route, Server, get, handler,
+    Body, EndpointExt, IntoResponse, Request, Response, Result, Route, Server,
+    error::{BadRequest, InternalServerError},
+    get, handler,
+    http::{StatusCode, header},
     listener::TcpListener,
     middleware::Cors,
-    web::{Data, Html},
+    web::{Data, Html, Path},
 };
 
+/// Executes the request with its files streamed to the resolvers as they are
+/// received, the multipart options of the upload policy reject the files that
+/// are too large.
 #[handler]
-async fn index(schema: Data<&FilesSchema>, req: GraphQLRequest) -> GraphQLResponse {
-    schema.execute(req.0).await.into()
+async fn index(
+    req: &Request,
+    body: Body,
+    schema: Data<&FilesSchema>,
+    Data(options): Data<&MultipartOptions>,
+) -> Result<GraphQLResponse> {
+    let content_type = req.header(header::CONTENT_TYPE).map(ToString::to_string);
+    let body = body
+        .into_bytes_stream()
+        .map_err(io::Error::other)
+        .into_async_read();
+    let response = files::execute(*schema, content_type.as_deref(), body, *options)
+        .await
+        .map_err(BadRequest)?;
+    Ok(response.into())
 }
 
 #[handler]
 async fn graphiql() -> impl IntoResponse {
-    Html(GraphiQLSource::build().endpoint("/").finish())
+    Html(
+        GraphiQLSource::build()
+            .endpoint("/")
+            .subscription_endpoint("/ws")
+            .finish(),
+    )
+}
+
+#[handler]
+async fn download(
+    req: &Request,
+    Path(id): Path<String>,
+    Data(storage): Data<&Storage>,
+) -> Result<Response> {
+    let range = req.header(header::RANGE);
+    Ok(
+        match storage
+            .download(&id, range)
+            .await
+            .map_err(InternalServerError)?
+        {
+            Some(file) => file_response(file),
+            None => StatusCode::NOT_FOUND.into_response(),
+        },
+    )
+}
+
+/// Sends the progress of the uploads that have an id as their body is
+/// received.
+async fn track_upload(mut req: Request) -> Result<Request> {
+    let Some(upload_id) = req.header(UPLOAD_ID_HEADER).map(ToString::to_string) else {
+        return Ok(req);
+    };
+    let content_length = req
+        .header(header::CONTENT_LENGTH)
+        .and_then(|value| value.parse().ok());
+    let storage = req.data::<Storage>().unwrap().clone();
+    let body = req.take_body().into_bytes_stream();
+    req.set_body(Body::from_bytes_stream(storage.track_upload(
+        &upload_id,
+        content_length,
+        body,
+    )));
+    Ok(req)
+}
+
+fn file_response(file: Download) -> Response {
+    let mut builder = Response::builder()
+        .status(StatusCode::from_u16(file.status).unwrap())
+        .content_type(&file.content_type)
+        .header(header::CONTENT_LENGTH, file.content_length)
+        .header(header::ACCEPT_RANGES, "bytes");
+    if let Some(content_range) = &file.content_range {
+        builder = builder.header(header::CONTENT_RANGE, content_range);
+    }
+    builder.body(Body::from_bytes_stream(file.into_stream()))
 }
 
 #[tokio::main]
 async fn main() -> std::io::Result<()> {
-    let schema = Schema::build(QueryRoot, MutationRoot, EmptySubscription)
-        .data(Storage::default())
+    let storage = Storage::local("uploads")?.with_base_url("http://localhost:8000");
+    let policy = UploadPolicy::default()
+        .max_file_size(1024 * 1024 * 1024)
+        .max_total_size(4 * 1024 * 1024 * 1024);
+    let schema = Schema::build(QueryRoot, MutationRoot, SubscriptionRoot)
+        .data(storage.clone())
+        .data(policy.clone())
         .finish();
 
     println!("GraphiQL IDE: http://localhost:8000");
 
     let app = Route::new()
-        .at("/", get(graphiql).post(index))
+        .at("/", get(graphiql).post(index.before(track_upload)))
+        .at("/ws", get(GraphQLSubscription::new(schema.clone())))
+        .at("/files/:id", get(download))
         .with(Cors::new())
-        .data(schema);
+        .data(schema)
+        .data(storage)
+        .data(policy.multipart_options());
     Server::new(TcpListener::bind("127.0.0.1:8000"))
         .run(app)
         .await
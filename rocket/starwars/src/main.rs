@@ -1,9 +1,7 @@
-use async_graphql::{EmptyMutation, EmptySubscription, Schema, http::GraphiQLSource};
+use async_graphql::{Schema, http::GraphiQLSource};
 use async_graphql_rocket::{GraphQLQuery, GraphQLRequest, GraphQLResponse};
 use rocket::{State, response::content, routes};
-use starwars::{QueryRoot, StarWars};
-
-pub type StarWarsSchema = Schema<QueryRoot, EmptyMutation, EmptySubscription>;
+use starwars::{MutationRoot, QueryRoot, StarWars, StarWarsSchema, SubscriptionRoot};
 
 #[rocket::get("/")]
 fn graphiql() -> content::RawHtml<String> {
@@ -25,7 +23,7 @@ async fn graphql_request(
 
 #[rocket::launch]
 fn rocket() -> _ {
-    let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+    let schema = Schema::build(QueryRoot, MutationRoot, SubscriptionRoot)
         .data(StarWars::new())
         .finish();
 
@@ -1,7 +1,30 @@
-use async_graphql::{EmptyMutation, EmptySubscription, Schema, http::GraphiQLSource};
+use std::{
+    convert::Infallible,
+    io::{self, SeekFrom},
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use async_graphql::{
+    EmptyMutation, EmptySubscription, ParseRequestError, Schema,
+    http::{GraphiQLSource, MultipartOptions},
+};
 use async_graphql_rocket::{GraphQLQuery, GraphQLRequest, GraphQLResponse};
-use files::{FilesSchema, MutationRoot, QueryRoot, Storage};
-use rocket::{State, response::content, routes};
+use bytes::Bytes;
+use files::{
+    Download, FilesSchema, MutationRoot, QueryRoot, Storage, SubscriptionRoot, UploadPolicy,
+};
+use futures::{Stream, StreamExt};
+use rocket::{
+    Request, Response, State,
+    data::{Data, Limits, ToByteUnit},
+    http::{ContentType, Status},
+    request::{FromRequest, Outcome},
+    response::{self, Debug, Responder, content},
+    routes,
+    tokio::io::{AsyncRead, AsyncSeek, ReadBuf},
+};
+use tokio_util::compat::TokioAsyncReadCompatExt;
 
 pub type StarWarsSchema = Schema<QueryRoot, EmptyMutation, EmptySubscription>;
 
@@ -20,32 +43,160 @@ async fn graphql_request(schema: &State<FilesSchema>, request: GraphQLRequest) -
     request.execute(schema.inner()).await
 }
 
-#[rocket::post(
-    "/graphql",
-    data = "<request>",
-    format = "multipart/form-data",
-    rank = 2
-)]
+/// Executes the request with its files streamed to the resolvers as they are
+/// received, the multipart options of the upload policy reject the files that
+/// are too large. The body is limited by the `data-form` limit.
+#[rocket::post("/graphql", data = "<body>", format = "multipart/form-data", rank = 2)]
 async fn graphql_request_multipart(
     schema: &State<FilesSchema>,
-    request: GraphQLRequest,
-) -> GraphQLResponse {
-    request.execute(schema.inner()).await
+    options: &State<MultipartOptions>,
+    content_type: &ContentType,
+    limits: &Limits,
+    body: Data<'_>,
+) -> Result<GraphQLResponse, (Status, String)> {
+    let body = body.open(limits.get("data-form").unwrap_or(Limits::DATA_FORM));
+    files::execute(
+        schema.inner(),
+        Some(&content_type.to_string()),
+        body.compat(),
+        **options,
+    )
+    .await
+    .map(GraphQLResponse::from)
+    .map_err(|err| match err {
+        ParseRequestError::PayloadTooLarge => (Status::PayloadTooLarge, err.to_string()),
+        _ => (Status::BadRequest, err.to_string()),
+    })
+}
+
+/// The `Range` header of the request.
+struct Range<'r>(Option<&'r str>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Range<'r> {
+    type Error = Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(Range(request.headers().get_one("Range")))
+    }
+}
+
+/// The content of an uploaded file, or of the requested range of it.
+struct FileResponse(Download);
+
+impl<'r> Responder<'r, 'static> for FileResponse {
+    fn respond_to(self, _: &'r Request<'_>) -> response::Result<'static> {
+        let download = self.0;
+        let mut response = Response::build();
+        response
+            .status(Status::new(download.status))
+            .raw_header("Content-Type", download.content_type.clone())
+            .raw_header("Accept-Ranges", "bytes");
+        if let Some(content_range) = download.content_range.clone() {
+            response.raw_header("Content-Range", content_range);
+        }
+        let length = download.content_length as usize;
+        response
+            .sized_body(length, DownloadBody::new(download))
+            .ok()
+    }
+}
+
+/// The body of a download, read from the store as Rocket sends it.
+///
+/// Rocket only sends the length of seekable bodies, but it doesn't seek those
+/// whose length is given, so seeking is only supported to the current
+/// position.
+struct DownloadBody {
+    stream: Pin<Box<dyn Stream<Item = io::Result<Bytes>> + Send>>,
+    chunk: Bytes,
+    position: u64,
+}
+
+impl DownloadBody {
+    fn new(download: Download) -> Self {
+        Self {
+            stream: download.into_stream().boxed(),
+            chunk: Bytes::new(),
+            position: 0,
+        }
+    }
+}
+
+impl AsyncRead for DownloadBody {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        while this.chunk.is_empty() {
+            match this.stream.as_mut().poll_next(cx) {
+                Poll::Ready(Some(Ok(chunk))) => this.chunk = chunk,
+                Poll::Ready(Some(Err(err))) => return Poll::Ready(Err(err)),
+                Poll::Ready(None) => return Poll::Ready(Ok(())),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        let len = this.chunk.len().min(buf.remaining());
+        buf.put_slice(&this.chunk.split_to(len));
+        this.position += len as u64;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncSeek for DownloadBody {
+    fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+        match position {
+            SeekFrom::Current(0) => Ok(()),
+            SeekFrom::Start(position) if position == self.position => Ok(()),
+            _ => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "a download can't be seeked",
+            )),
+        }
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        Poll::Ready(Ok(self.position))
+    }
+}
+
+#[rocket::get("/files/<id>")]
+async fn download(
+    storage: &State<Storage>,
+    id: &str,
+    range: Range<'_>,
+) -> Result<Option<FileResponse>, Debug<std::io::Error>> {
+    Ok(storage.download(id, range.0).await?.map(FileResponse))
 }
 
 #[rocket::launch]
 fn rocket() -> _ {
-    let schema = Schema::build(QueryRoot, MutationRoot, EmptySubscription)
-        .data(Storage::default())
+    let storage = Storage::local("uploads")
+        .unwrap()
+        .with_base_url("http://localhost:8000");
+    let policy = UploadPolicy::default()
+        .max_file_size(1024 * 1024 * 1024)
+        .max_total_size(4 * 1024 * 1024 * 1024);
+    let schema = Schema::build(QueryRoot, MutationRoot, SubscriptionRoot)
+        .data(storage.clone())
+        .data(policy.clone())
         .finish();
 
-    rocket::build().manage(schema).mount(
-        "/",
-        routes![
-            graphql_query,
-            graphql_request,
-            graphql_request_multipart,
-            graphiql
-        ],
-    )
+    let limits = Limits::default().limit("data-form", 4.gibibytes());
+    rocket::custom(rocket::Config::figment().merge(("limits", limits)))
+        .manage(schema)
+        .manage(storage)
+        .manage(policy.multipart_options())
+        .mount(
+            "/",
+            routes![
+                graphql_query,
+                graphql_request,
+                graphql_request_multipart,
+                graphiql,
+                download
+            ],
+        )
 }
@@ -0,0 +1,935 @@
+//! Incremental delivery of `@defer` and `@stream` over `multipart/mixed`, in
+//! the [format](https://github.com/graphql/graphql-wg/blob/main/rfcs/DeferStream.md)
+//! of the 2022-08-24 proposal that Apollo Client and graphql-js understand.
+//!
+//! async-graphql doesn't execute these directives, so a query using them is
+//! split. The initial operation leaves out the deferred fragments and the
+//! fields streamed from their first item, and each of them is executed as an
+//! operation of its own that only selects the fields leading to it. Their
+//! results are sent as they complete. The items of a field streamed after an
+//! `initialCount` are taken from the initial result and sent in the next
+//! payload, and a `@defer` or `@stream` inside a deferred fragment or streamed
+//! field is delivered with it.
+//!
+//! Only queries are split. The directives of the other operations, and of the
+//! clients that don't accept `multipart/mixed`, are ignored. The operations of
+//! the deferred parts execute the fields on their path again and don't get the
+//! data of the request.
+
+use std::{
+    collections::{HashMap, HashSet},
+    convert::Infallible,
+    mem,
+};
+
+use async_graphql::{
+    Executor, Name, Positioned, Request, Response, Value, Variables,
+    indexmap::IndexMap,
+    parser::types::{
+        Directive, DocumentOperations, ExecutableDocument, Field, FragmentDefinition,
+        InlineFragment, OperationType, Selection, SelectionSet,
+    },
+};
+use futures_util::{
+    FutureExt, Stream, StreamExt,
+    future::BoxFuture,
+    stream::{self, BoxStream, FuturesUnordered},
+};
+use serde_json::json;
+
+/// The content type of the incremental responses.
+pub const CONTENT_TYPE: &str = r#"multipart/mixed; boundary="-"; deferSpec=20220824"#;
+
+/// The alias of the `__typename` selected instead of a selection set whose
+/// fields are all deferred, it is removed from the initial result.
+const FILLER: &str = "_deferred";
+
+/// Whether a client with this `Accept` header takes incremental responses.
+pub fn accepts_multipart(accept: Option<&str>) -> bool {
+    accept.is_some_and(|accept| {
+        accept
+            .split(',')
+            .any(|ty| ty.trim().starts_with("multipart/mixed"))
+    })
+}
+
+/// The result of a request.
+pub enum Delivery {
+    /// The whole result, when nothing is deferred.
+    Single(Box<Response>),
+    /// The payloads of the initial result and of the deferred parts, the last
+    /// one has `"hasNext": false`.
+    Incremental(BoxStream<'static, serde_json::Value>),
+}
+
+/// Executes the request, incrementally if it is a query with an enabled
+/// `@defer` or `@stream` and `incremental` is set because the client accepts
+/// [`multipart`] responses.
+pub async fn execute<E: Executor>(
+    executor: &E,
+    mut request: Request,
+    incremental: bool,
+) -> Delivery {
+    let Ok(document) = request.parsed_query().cloned() else {
+        return Delivery::Single(Box::new(executor.execute(request).await));
+    };
+    let mut stripped = document.clone();
+    if !strip_document(&mut stripped) {
+        return Delivery::Single(Box::new(executor.execute(request).await));
+    }
+    let split = match incremental {
+        true => split(&document, &request),
+        false => None,
+    };
+    let Some(Split { initial, deferred }) = split else {
+        request.set_parsed_query(stripped);
+        return Delivery::Single(Box::new(executor.execute(request).await));
+    };
+
+    let mut follow_ups = Vec::new();
+    let mut ready = Vec::new();
+    for mut part in deferred {
+        match part.document.take() {
+            Some(document) => {
+                let mut follow_up =
+                    Request::new(request.query.clone()).variables(request.variables.clone());
+                follow_up.operation_name = request.operation_name.clone();
+                follow_up.set_parsed_query(document);
+                follow_ups.push((part, follow_up));
+            }
+            None => ready.push(part),
+        }
+    }
+
+    request.set_parsed_query(initial);
+    let mut response = executor.execute(request).await;
+    if response.data == Value::Null {
+        return Delivery::Single(Box::new(response));
+    }
+    let mut entries = Vec::new();
+    for part in &ready {
+        entries.extend(part.take_from_initial(&mut response.data));
+    }
+    for (part, _) in &follow_ups {
+        part.take_from_initial(&mut response.data);
+    }
+    if entries.is_empty() && follow_ups.is_empty() {
+        return Delivery::Single(Box::new(response));
+    }
+
+    let mut initial = json!(response);
+    initial["hasNext"] = json!(true);
+
+    let parts = FuturesUnordered::<BoxFuture<'static, Vec<serde_json::Value>>>::new();
+    if !entries.is_empty() {
+        parts.push(async move { entries }.boxed());
+    }
+    for (part, follow_up) in follow_ups {
+        let executor = executor.clone();
+        parts.push(async move { part.entries(executor.execute(follow_up).await) }.boxed());
+    }
+    let total = parts.len();
+    let subsequent = parts.enumerate().filter_map(move |(index, entries)| {
+        let has_next = index + 1 < total;
+        let payload = match (entries.is_empty(), has_next) {
+            (true, true) => None,
+            (true, false) => Some(json!({ "hasNext": false })),
+            (false, _) => Some(json!({ "incremental": entries, "hasNext": has_next })),
+        };
+        async move { payload }
+    });
+    Delivery::Incremental(
+        stream::once(async move { initial })
+            .chain(subsequent)
+            .boxed(),
+    )
+}
+
+/// The body of a `multipart/mixed` response with the [`CONTENT_TYPE`] of
+/// these payloads.
+pub fn multipart(
+    payloads: impl Stream<Item = serde_json::Value> + Send + 'static,
+) -> impl Stream<Item = String> + Send + 'static {
+    payloads
+        .map(|payload| {
+            format!("\r\n---\r\nContent-Type: application/json; charset=utf-8\r\n\r\n{payload}")
+        })
+        .chain(stream::once(async { "\r\n-----\r\n".to_string() }))
+}
+
+/// The operation of a query without its deferred parts, and these parts.
+struct Split {
+    initial: ExecutableDocument,
+    deferred: Vec<Deferred>,
+}
+
+/// Splits the operation of the request if it is a query, `None` otherwise or
+/// if it can't be executed.
+fn split(document: &ExecutableDocument, request: &Request) -> Option<Split> {
+    let (name, operation) = match (&document.operations, &request.operation_name) {
+        (DocumentOperations::Single(operation), None) => (None, operation),
+        (DocumentOperations::Multiple(operations), Some(name)) => {
+            let (name, operation) = operations.get_key_value(name.as_str())?;
+            (Some(name), operation)
+        }
+        (DocumentOperations::Multiple(operations), None) if operations.len() == 1 => {
+            let (name, operation) = operations.iter().next()?;
+            (Some(name), operation)
+        }
+        _ => return None,
+    };
+    if operation.node.ty != OperationType::Query {
+        return None;
+    }
+
+    let mut variables = request.variables.clone();
+    for definition in &operation.node.variable_definitions {
+        if let Some(value) = definition.node.default_value() {
+            variables
+                .entry(definition.node.name.node.clone())
+                .or_insert_with(|| value.clone());
+        }
+    }
+
+    let selection_set = inline(
+        &operation.node.selection_set.node,
+        &document.fragments,
+        &mut Vec::new(),
+    )?;
+    let mut splitter = Splitter {
+        variables: &variables,
+        steps: Vec::new(),
+        deferred: Vec::new(),
+    };
+    let mut initial = splitter.split(&selection_set, &response_keys(&selection_set));
+    if initial.items.is_empty() {
+        splitter.fill(&mut initial);
+    }
+
+    let document = |selection_set| {
+        let mut operation = operation.clone();
+        operation.node.selection_set.node = selection_set;
+        let mut used = HashSet::new();
+        used_variables(&operation.node.selection_set.node, &mut used);
+        operation
+            .node
+            .variable_definitions
+            .retain(|definition| used.contains(&definition.node.name.node));
+        ExecutableDocument {
+            operations: match name {
+                Some(name) => {
+                    DocumentOperations::Multiple(HashMap::from([(name.clone(), operation)]))
+                }
+                None => DocumentOperations::Single(operation),
+            },
+            fragments: HashMap::new(),
+        }
+    };
+    let mut deferred = splitter.deferred;
+    for part in &mut deferred {
+        part.document = part.selection_set().map(&document);
+    }
+    Some(Split {
+        initial: document(initial),
+        deferred,
+    })
+}
+
+/// A field or inline fragment on the way from the root of the operation to a
+/// deferred part, without its selection set.
+#[derive(Clone)]
+enum Step {
+    Field(Positioned<Field>),
+    Fragment(Positioned<InlineFragment>),
+}
+
+enum Part {
+    /// A deferred fragment, executed on its own.
+    Defer {
+        label: Option<String>,
+        fragment: Positioned<InlineFragment>,
+    },
+    /// A field streamed from its first item, executed on its own and
+    /// replaced by an empty list in the initial result.
+    Stream {
+        label: Option<String>,
+        field: Positioned<Field>,
+    },
+    /// The items of a field after the first `initial_count`, taken from the
+    /// initial result.
+    Rest {
+        label: Option<String>,
+        key: Name,
+        initial_count: usize,
+    },
+    /// The [`FILLER`] of a selection set whose fields are all deferred.
+    Filler,
+}
+
+/// A part of the query delivered after the initial result, in the objects
+/// reached by `steps`.
+struct Deferred {
+    steps: Vec<Step>,
+    part: Part,
+    /// The operation that executes the part, if it isn't taken from the
+    /// initial result.
+    document: Option<ExecutableDocument>,
+}
+
+impl Deferred {
+    /// The response keys of the fields on the way to the part.
+    fn keys(&self) -> Vec<Name> {
+        self.steps
+            .iter()
+            .filter_map(|step| match step {
+                Step::Field(field) => Some(field.node.response_key().node.clone()),
+                Step::Fragment(_) => None,
+            })
+            .collect()
+    }
+
+    /// The selection set of the operation that executes the part.
+    fn selection_set(&self) -> Option<SelectionSet> {
+        let mut selection = match &self.part {
+            Part::Defer { fragment, .. } => {
+                fragment.position_node(Selection::InlineFragment(fragment.clone()))
+            }
+            Part::Stream { field, .. } => field.position_node(Selection::Field(field.clone())),
+            Part::Rest { .. } | Part::Filler => return None,
+        };
+        for step in self.steps.iter().rev() {
+            selection = match step {
+                Step::Field(field) => {
+                    let mut field = field.clone();
+                    field.node.selection_set.node.items = vec![selection];
+                    field.position_node(Selection::Field(field.clone()))
+                }
+                Step::Fragment(fragment) => {
+                    let mut fragment = fragment.clone();
+                    fragment.node.selection_set.node.items = vec![selection];
+                    fragment.position_node(Selection::InlineFragment(fragment.clone()))
+                }
+            };
+        }
+        Some(SelectionSet {
+            items: vec![selection],
+        })
+    }
+
+    /// Removes the part from the initial result, returns the entries of the
+    /// items taken from it.
+    fn take_from_initial(&self, data: &mut Value) -> Vec<serde_json::Value> {
+        let mut entries = Vec::new();
+        let keys = self.keys();
+        visit(
+            data,
+            &keys,
+            &mut Vec::new(),
+            &mut |path, object| match &self.part {
+                Part::Defer { .. } => {}
+                Part::Stream { field, .. } => {
+                    // The field was replaced by its `__typename`.
+                    let key = &field.node.response_key().node;
+                    if let Some(value @ Value::String(_)) = object.get_mut(key) {
+                        *value = Value::List(Vec::new());
+                    }
+                }
+                Part::Rest {
+                    label,
+                    key,
+                    initial_count,
+                } => {
+                    if let Some(Value::List(items)) = object.get_mut(key)
+                        && items.len() > *initial_count
+                    {
+                        let items = items.split_off(*initial_count);
+                        let path = [path, &[json!(key), json!(initial_count)]].concat();
+                        entries.push(entry("items", Value::List(items), path, label));
+                    }
+                }
+                Part::Filler => {
+                    object.shift_remove(FILLER);
+                }
+            },
+        );
+        entries
+    }
+
+    /// The entries of the response of the operation that executes the part.
+    fn entries(&self, response: Response) -> Vec<serde_json::Value> {
+        let Response {
+            mut data, errors, ..
+        } = response;
+        let keys = self.keys();
+        let mut entries = Vec::new();
+        let (kind, label) = match &self.part {
+            Part::Defer { label, .. } => {
+                visit(&mut data, &keys, &mut Vec::new(), &mut |path, object| {
+                    if !object.is_empty() {
+                        let object = Value::Object(mem::take(object));
+                        entries.push(entry("data", object, path.to_vec(), label));
+                    }
+                });
+                ("data", label)
+            }
+            Part::Stream { label, field } => {
+                let key = &field.node.response_key().node;
+                visit(&mut data, &keys, &mut Vec::new(), &mut |path, object| {
+                    if let Some(Value::List(items)) = object.get_mut(key)
+                        && !items.is_empty()
+                    {
+                        let items = Value::List(mem::take(items));
+                        let path = [path, &[json!(key), json!(0)]].concat();
+                        entries.push(entry("items", items, path, label));
+                    }
+                });
+                ("items", label)
+            }
+            Part::Rest { .. } | Part::Filler => return entries,
+        };
+        if !errors.is_empty() {
+            if entries.is_empty() {
+                let path = keys.iter().map(|key| json!(key)).collect();
+                entries.push(entry(kind, Value::Null, path, label));
+            }
+            entries[0]["errors"] = json!(errors);
+        }
+        entries
+    }
+}
+
+/// An entry of the `incremental` list of a payload.
+fn entry(
+    kind: &str,
+    value: Value,
+    path: Vec<serde_json::Value>,
+    label: &Option<String>,
+) -> serde_json::Value {
+    let mut entry = json!({ kind: value, "path": path });
+    if let Some(label) = label {
+        entry["label"] = json!(label);
+    }
+    entry
+}
+
+/// A function called with the path and the fields of an object.
+type Visitor<'a> = dyn FnMut(&[serde_json::Value], &mut IndexMap<Name, Value>) + 'a;
+
+/// Calls `f` with the path and the fields of the objects at the end of `keys`,
+/// through all the items of the lists on the way.
+fn visit(value: &mut Value, keys: &[Name], path: &mut Vec<serde_json::Value>, f: &mut Visitor<'_>) {
+    match value {
+        Value::List(items) => {
+            for (index, item) in items.iter_mut().enumerate() {
+                path.push(json!(index));
+                visit(item, keys, path, f);
+                path.pop();
+            }
+        }
+        Value::Object(object) => match keys.split_first() {
+            None => f(path, object),
+            Some((key, keys)) => {
+                if let Some(value) = object.get_mut(key) {
+                    path.push(json!(key));
+                    visit(value, keys, path, f);
+                    path.pop();
+                }
+            }
+        },
+        _ => {}
+    }
+}
+
+/// Takes the deferred parts out of an operation.
+struct Splitter<'a> {
+    variables: &'a Variables,
+    steps: Vec<Step>,
+    deferred: Vec<Deferred>,
+}
+
+/// The arguments of an enabled `@defer` or `@stream`.
+struct Arguments {
+    label: Option<String>,
+    initial_count: usize,
+}
+
+impl Splitter<'_> {
+    /// The selection set without its deferred parts, which are added to
+    /// `self.deferred`. `counts` are the number of fields selected with each
+    /// response key in the object of the selection set.
+    fn split(
+        &mut self,
+        selection_set: &SelectionSet,
+        counts: &HashMap<Name, usize>,
+    ) -> SelectionSet {
+        let mut items = Vec::new();
+        for item in &selection_set.items {
+            match &item.node {
+                Selection::Field(field) => {
+                    let mut field = field.clone();
+                    let stream = self.enabled(&field.node.directives, "stream");
+                    field
+                        .node
+                        .directives
+                        .retain(|directive| !is_incremental(directive));
+                    if let Some(Arguments {
+                        label,
+                        initial_count,
+                    }) = stream
+                    {
+                        strip(&mut field.node.selection_set.node);
+                        let key = field.node.response_key().node.clone();
+                        if initial_count == 0 && counts.get(&key) == Some(&1) {
+                            let placeholder = Field {
+                                alias: Some(field.node.response_key().clone()),
+                                name: field.node.name.position_node(Name::new("__typename")),
+                                arguments: Vec::new(),
+                                directives: field.node.directives.clone(),
+                                selection_set: Default::default(),
+                            };
+                            items.push(
+                                item.position_node(Selection::Field(
+                                    field.position_node(placeholder),
+                                )),
+                            );
+                            self.defer(Part::Stream { label, field });
+                        } else {
+                            // Another field has the key, the whole list is in
+                            // the initial result.
+                            self.defer(Part::Rest {
+                                label,
+                                key,
+                                initial_count,
+                            });
+                            items.push(item.position_node(Selection::Field(field)));
+                        }
+                        continue;
+                    }
+
+                    let selection_set = mem::take(&mut field.node.selection_set.node);
+                    self.steps.push(Step::Field(field.clone()));
+                    let mut split = self.split(&selection_set, &response_keys(&selection_set));
+                    if split.items.is_empty() && !selection_set.items.is_empty() {
+                        self.fill(&mut split);
+                    }
+                    self.steps.pop();
+                    field.node.selection_set.node = split;
+                    items.push(item.position_node(Selection::Field(field)));
+                }
+                Selection::InlineFragment(fragment) => {
+                    let mut fragment = fragment.clone();
+                    let defer = self.enabled(&fragment.node.directives, "defer");
+                    fragment
+                        .node
+                        .directives
+                        .retain(|directive| !is_incremental(directive));
+                    if let Some(Arguments { label, .. }) = defer {
+                        strip(&mut fragment.node.selection_set.node);
+                        self.defer(Part::Defer { label, fragment });
+                        continue;
+                    }
+
+                    let selection_set = mem::take(&mut fragment.node.selection_set.node);
+                    self.steps.push(Step::Fragment(fragment.clone()));
+                    fragment.node.selection_set.node = self.split(&selection_set, counts);
+                    self.steps.pop();
+                    if !fragment.node.selection_set.node.items.is_empty() {
+                        items.push(item.position_node(Selection::InlineFragment(fragment)));
+                    }
+                }
+                Selection::FragmentSpread(_) => items.push(item.clone()),
+            }
+        }
+        SelectionSet { items }
+    }
+
+    /// Adds a deferred part in the objects of the current step.
+    fn defer(&mut self, part: Part) {
+        self.deferred.push(Deferred {
+            steps: self.steps.clone(),
+            part,
+            document: None,
+        });
+    }
+
+    /// Selects the [`FILLER`] in a selection set whose fields are all
+    /// deferred.
+    fn fill(&mut self, selection_set: &mut SelectionSet) {
+        let field = Field {
+            alias: Some(Positioned::new(Name::new(FILLER), Default::default())),
+            name: Positioned::new(Name::new("__typename"), Default::default()),
+            arguments: Vec::new(),
+            directives: Vec::new(),
+            selection_set: Default::default(),
+        };
+        selection_set.items.push(Positioned::new(
+            Selection::Field(Positioned::new(field, Default::default())),
+            Default::default(),
+        ));
+        self.defer(Part::Filler);
+    }
+
+    /// The arguments of the directive `name` in `directives`, unless it is
+    /// missing or disabled by its `if` argument.
+    fn enabled(&self, directives: &[Positioned<Directive>], name: &str) -> Option<Arguments> {
+        let directive = &directives
+            .iter()
+            .find(|directive| directive.node.name.node == name)?
+            .node;
+        let argument = |name| {
+            directive
+                .get_argument(name)?
+                .node
+                .clone()
+                .into_const_with(|variable| self.variables.get(&variable).cloned().ok_or(()))
+                .ok()
+        };
+        if argument("if") == Some(Value::Boolean(false)) {
+            return None;
+        }
+        Some(Arguments {
+            label: match argument("label") {
+                Some(Value::String(label)) => Some(label),
+                _ => None,
+            },
+            initial_count: match argument("initialCount") {
+                Some(Value::Number(count)) => count.as_u64().unwrap_or_default() as usize,
+                _ => 0,
+            },
+        })
+    }
+}
+
+/// The number of fields selected with each response key in the object of a
+/// selection set, including those of its inline fragments.
+fn response_keys(selection_set: &SelectionSet) -> HashMap<Name, usize> {
+    fn count(selection_set: &SelectionSet, counts: &mut HashMap<Name, usize>) {
+        for item in &selection_set.items {
+            match &item.node {
+                Selection::Field(field) => {
+                    *counts
+                        .entry(field.node.response_key().node.clone())
+                        .or_default() += 1;
+                }
+                Selection::InlineFragment(fragment) => {
+                    count(&fragment.node.selection_set.node, counts)
+                }
+                Selection::FragmentSpread(_) => {}
+            }
+        }
+    }
+
+    let mut counts = HashMap::new();
+    count(selection_set, &mut counts);
+    counts
+}
+
+/// Replaces the fragment spreads with inline fragments, `None` if a fragment
+/// is missing or spreads itself.
+fn inline(
+    selection_set: &SelectionSet,
+    fragments: &HashMap<Name, Positioned<FragmentDefinition>>,
+    visiting: &mut Vec<Name>,
+) -> Option<SelectionSet> {
+    let mut items = Vec::new();
+    for item in &selection_set.items {
+        let selection = match &item.node {
+            Selection::Field(field) => {
+                let mut field = field.clone();
+                field.node.selection_set.node =
+                    inline(&field.node.selection_set.node, fragments, visiting)?;
+                Selection::Field(field)
+            }
+            Selection::InlineFragment(fragment) => {
+                let mut fragment = fragment.clone();
+                fragment.node.selection_set.node =
+                    inline(&fragment.node.selection_set.node, fragments, visiting)?;
+                Selection::InlineFragment(fragment)
+            }
+            Selection::FragmentSpread(spread) => {
+                let name = &spread.node.fragment_name.node;
+                let definition = &fragments.get(name)?.node;
+                if visiting.contains(name) {
+                    return None;
+                }
+                visiting.push(name.clone());
+                let selection_set = inline(&definition.selection_set.node, fragments, visiting)?;
+                visiting.pop();
+                Selection::InlineFragment(spread.position_node(InlineFragment {
+                    type_condition: Some(definition.type_condition.clone()),
+                    directives: spread.node.directives.clone(),
+                    selection_set: definition.selection_set.position_node(selection_set),
+                }))
+            }
+        };
+        items.push(item.position_node(selection));
+    }
+    Some(SelectionSet { items })
+}
+
+fn is_incremental(directive: &Positioned<Directive>) -> bool {
+    matches!(directive.node.name.node.as_str(), "defer" | "stream")
+}
+
+/// Removes the `@defer` and `@stream` directives of a selection set, returns
+/// whether it had any.
+fn strip(selection_set: &mut SelectionSet) -> bool {
+    let mut stripped = false;
+    for item in &mut selection_set.items {
+        let directives = item.node.directives_mut();
+        let count = directives.len();
+        directives.retain(|directive| !is_incremental(directive));
+        stripped |= directives.len() != count;
+        match &mut item.node {
+            Selection::Field(field) => stripped |= strip(&mut field.node.selection_set.node),
+            Selection::InlineFragment(fragment) => {
+                stripped |= strip(&mut fragment.node.selection_set.node)
+            }
+            Selection::FragmentSpread(_) => {}
+        }
+    }
+    stripped
+}
+
+/// Removes the `@defer` and `@stream` directives of a document, returns
+/// whether it had any.
+fn strip_document(document: &mut ExecutableDocument) -> bool {
+    let mut stripped = false;
+    match &mut document.operations {
+        DocumentOperations::Single(operation) => {
+            stripped |= strip(&mut operation.node.selection_set.node)
+        }
+        DocumentOperations::Multiple(operations) => {
+            for operation in operations.values_mut() {
+                stripped |= strip(&mut operation.node.selection_set.node);
+            }
+        }
+    }
+    for fragment in document.fragments.values_mut() {
+        stripped |= strip(&mut fragment.node.selection_set.node);
+    }
+    stripped
+}
+
+/// Adds the variables used by the arguments and directives of a selection
+/// set.
+fn used_variables(selection_set: &SelectionSet, used: &mut HashSet<Name>) {
+    fn directives(directives: &[Positioned<Directive>], used: &mut HashSet<Name>) {
+        for directive in directives {
+            for (_, value) in &directive.node.arguments {
+                let _ = value.node.clone().into_const_with(|name| {
+                    used.insert(name);
+                    Ok::<_, Infallible>(Value::Null)
+                });
+            }
+        }
+    }
+
+    for item in &selection_set.items {
+        directives(item.node.directives(), used);
+        match &item.node {
+            Selection::Field(field) => {
+                for (_, value) in &field.node.arguments {
+                    let _ = value.node.clone().into_const_with(|name| {
+                        used.insert(name);
+                        Ok::<_, Infallible>(Value::Null)
+                    });
+                }
+                used_variables(&field.node.selection_set.node, used);
+            }
+            Selection::InlineFragment(fragment) => {
+                used_variables(&fragment.node.selection_set.node, used)
+            }
+            Selection::FragmentSpread(_) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_graphql::Schema;
+    use starwars::{MutationRoot, QueryRoot, StarWars, StarWarsSchema, SubscriptionRoot};
+
+    use super::*;
+
+    fn schema() -> StarWarsSchema {
+        Schema::build(QueryRoot, MutationRoot, SubscriptionRoot)
+            .data(StarWars::new())
+            .finish()
+    }
+
+    /// The payloads of the request, or its response if it isn't incremental.
+    async fn payloads(request: impl Into<Request>, incremental: bool) -> Vec<serde_json::Value> {
+        match execute(&schema(), request.into(), incremental).await {
+            Delivery::Single(response) => vec![json!(response)],
+            Delivery::Incremental(payloads) => payloads.collect().await,
+        }
+    }
+
+    #[tokio::test]
+    async fn defer_fragment() {
+        let query = r#"{
+            hero(episode: EMPIRE) {
+                name
+                ... @defer(label: "friends") { friends { name } }
+            }
+        }"#;
+        assert_eq!(
+            payloads(query, true).await,
+            [
+                json!({ "data": { "hero": { "name": "Luke Skywalker" } }, "hasNext": true }),
+                json!({
+                    "incremental": [{
+                        "data": {
+                            "friends": [
+                                { "name": "Han Solo" },
+                                { "name": "Leia Organa" },
+                                { "name": "C-3PO" },
+                                { "name": "R2-D2" },
+                            ],
+                        },
+                        "path": ["hero"],
+                        "label": "friends",
+                    }],
+                    "hasNext": false,
+                }),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn defer_fragment_spread_in_list() {
+        let query = r#"
+            query Heroes { humans(first: 2) { edges { node { name ...Planet @defer } } } }
+            query Other { hero { name } }
+            fragment Planet on Human { homePlanet }
+        "#;
+        assert_eq!(
+            payloads(Request::new(query).operation_name("Heroes"), true).await,
+            [
+                json!({
+                    "data": {
+                        "humans": {
+                            "edges": [
+                                { "node": { "name": "Luke Skywalker" } },
+                                { "node": { "name": "Anakin Skywalker" } },
+                            ],
+                        },
+                    },
+                    "hasNext": true,
+                }),
+                json!({
+                    "incremental": [
+                        {
+                            "data": { "homePlanet": "Tatooine" },
+                            "path": ["humans", "edges", 0, "node"],
+                        },
+                        {
+                            "data": { "homePlanet": "Tatooine" },
+                            "path": ["humans", "edges", 1, "node"],
+                        },
+                    ],
+                    "hasNext": false,
+                }),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn defer_all_fields() {
+        assert_eq!(
+            payloads("{ hero { ... @defer { name } } }", true).await,
+            [
+                json!({ "data": { "hero": {} }, "hasNext": true }),
+                json!({
+                    "incremental": [{ "data": { "name": "Luke Skywalker" }, "path": ["hero"] }],
+                    "hasNext": false,
+                }),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn stream_friends() {
+        let query = r#"{ human(id: "1000") { name friends @stream(label: "friends") { name } } }"#;
+        assert_eq!(
+            payloads(query, true).await,
+            [
+                json!({
+                    "data": { "human": { "name": "Luke Skywalker", "friends": [] } },
+                    "hasNext": true,
+                }),
+                json!({
+                    "incremental": [{
+                        "items": [
+                            { "name": "Han Solo" },
+                            { "name": "Leia Organa" },
+                            { "name": "C-3PO" },
+                            { "name": "R2-D2" },
+                        ],
+                        "path": ["human", "friends", 0],
+                        "label": "friends",
+                    }],
+                    "hasNext": false,
+                }),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn stream_after_initial_count() {
+        let query = r#"query ($count: Int!) {
+            human(id: "1002") { friends @stream(initialCount: $count) { name } }
+        }"#;
+        let request = Request::new(query).variables(Variables::from_json(json!({ "count": 1 })));
+        assert_eq!(
+            payloads(request, true).await,
+            [
+                json!({
+                    "data": { "human": { "friends": [{ "name": "Luke Skywalker" }] } },
+                    "hasNext": true,
+                }),
+                json!({
+                    "incremental": [{
+                        "items": [{ "name": "Leia Organa" }, { "name": "R2-D2" }],
+                        "path": ["human", "friends", 1],
+                    }],
+                    "hasNext": false,
+                }),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn disabled_directives() {
+        let whole = json!({
+            "data": { "hero": { "name": "Luke Skywalker", "homePlanet": "Tatooine" } },
+        });
+
+        let query = r#"query ($defer: Boolean = false) {
+            hero { name ... on Human @defer(if: $defer) { homePlanet } }
+        }"#;
+        assert_eq!(payloads(query, true).await, std::slice::from_ref(&whole));
+
+        // Without multipart responses, the query is executed at once.
+        let query = "{ hero { name ... on Human @defer { homePlanet } } }";
+        assert_eq!(payloads(query, false).await, [whole]);
+    }
+
+    #[tokio::test]
+    async fn multipart_body() {
+        let body = multipart(stream::iter([json!({ "hasNext": false })]))
+            .collect::<String>()
+            .await;
+        assert_eq!(
+            body,
+            "\r\n---\r\nContent-Type: application/json; charset=utf-8\r\n\r\n\
+             {\"hasNext\":false}\r\n-----\r\n"
+        );
+        assert!(accepts_multipart(Some(
+            "multipart/mixed;deferSpec=20220824, application/json"
+        )));
+        assert!(!accepts_multipart(Some("application/json")));
+        assert!(!accepts_multipart(None));
+    }
+}
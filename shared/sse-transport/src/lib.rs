@@ -0,0 +1,418 @@
+//! GraphQL over Server-Sent Events, following the
+//! [GraphQL over SSE protocol](https://github.com/enisdenjo/graphql-sse/blob/master/PROTOCOL.md).
+//!
+//! In the distinct connections mode every `GET` or `POST` request executes one
+//! operation and streams its results. In the single connection mode a stream
+//! is reserved with `PUT`, listened to with `GET` and the operations are
+//! executed with `POST` and stopped with `DELETE`, all with the reservation
+//! token in the `x-graphql-event-stream-token` header or `token` parameter.
+//! A reservation that isn't listened to within a minute is dropped, and the
+//! operations are only accepted once it is listened to.
+//!
+//! This crate keeps the state of the protocol, the servers only turn the
+//! [`Event`]s into their own type of event and the errors into responses.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use async_graphql::{Executor, Request, Response};
+use futures_channel::mpsc::{Receiver, Sender, channel};
+use futures_util::{SinkExt, Stream, StreamExt, stream};
+pub use http::StatusCode;
+use serde::Deserialize;
+use serde_json::json;
+use tokio::task::AbortHandle;
+use uuid::Uuid;
+
+/// The header of the reservation token.
+pub const TOKEN_HEADER: &str = "x-graphql-event-stream-token";
+
+/// How long a reserved stream waits for the client to listen to it, by
+/// default.
+pub const RESERVATION_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How often the servers send a comment to keep the stream open.
+pub const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// How many events are buffered for a listener before the operations wait
+/// for it to catch up.
+const STREAM_CAPACITY: usize = 16;
+
+/// The query parameters of the requests.
+#[derive(Deserialize)]
+pub struct Params {
+    token: Option<String>,
+    #[serde(rename = "operationId")]
+    operation_id: Option<String>,
+}
+
+impl Params {
+    /// The reservation token, from the value of the [`TOKEN_HEADER`] header
+    /// or else from the `token` parameter.
+    pub fn token(&self, header: Option<&str>) -> Option<String> {
+        header
+            .map(ToString::to_string)
+            .or_else(|| self.token.clone())
+    }
+
+    /// The operation to stop.
+    pub fn operation_id(&self) -> Option<&str> {
+        self.operation_id.as_deref()
+    }
+}
+
+/// An event of a stream.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Event {
+    /// `next` or `complete`.
+    pub event: &'static str,
+    pub data: String,
+}
+
+/// A result of the operation `id`, which is `None` in the distinct
+/// connections mode.
+fn next_event(id: Option<&str>, response: &Response) -> Event {
+    let data = match id {
+        Some(id) => json!({ "id": id, "payload": response }),
+        None => json!(response),
+    };
+    Event {
+        event: "next",
+        data: data.to_string(),
+    }
+}
+
+/// The end of the operation `id`, which is `None` in the distinct connections
+/// mode.
+fn complete_event(id: Option<&str>) -> Event {
+    let data = match id {
+        Some(id) => json!({ "id": id }).to_string(),
+        None => String::new(),
+    };
+    Event {
+        event: "complete",
+        data,
+    }
+}
+
+/// Executes the request and streams its results in the distinct connections
+/// mode.
+pub fn distinct(
+    schema: &impl Executor,
+    request: Request,
+) -> impl Stream<Item = Event> + Send + 'static {
+    schema
+        .execute_stream(request, None)
+        .map(|response| next_event(None, &response))
+        .chain(stream::once(async { complete_event(None) }))
+}
+
+/// The streams reserved in the single connection mode, by token.
+///
+/// The reservations that weren't listened to in time are dropped whenever a
+/// stream is reserved, listened to, or receives or stops an operation.
+#[derive(Clone)]
+pub struct Streams {
+    reservations: Arc<Mutex<HashMap<String, Reservation>>>,
+    timeout: Duration,
+}
+
+struct Reservation {
+    sender: Sender<Event>,
+    /// Taken when the client starts listening to the stream.
+    receiver: Option<Receiver<Event>>,
+    reserved_at: Instant,
+    operations: HashMap<String, AbortHandle>,
+}
+
+impl Reservation {
+    /// Whether the client didn't listen to the stream in time.
+    fn expired(&self, timeout: Duration) -> bool {
+        self.receiver.is_some() && self.reserved_at.elapsed() >= timeout
+    }
+}
+
+/// Removes the reservation and stops its operations when the client stops
+/// listening.
+struct Listener {
+    streams: Streams,
+    token: String,
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        let reservation = self
+            .streams
+            .reservations
+            .lock()
+            .unwrap()
+            .remove(&self.token);
+        if let Some(reservation) = reservation {
+            for handle in reservation.operations.values() {
+                handle.abort();
+            }
+        }
+    }
+}
+
+impl Default for Streams {
+    fn default() -> Self {
+        Self::new(RESERVATION_TIMEOUT)
+    }
+}
+
+impl Streams {
+    /// Create the streams of a server, whose reservations are dropped if they
+    /// aren't listened to within `timeout`.
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            reservations: Default::default(),
+            timeout,
+        }
+    }
+
+    /// Locks the reservations, without the expired ones.
+    fn reservations(&self) -> std::sync::MutexGuard<'_, HashMap<String, Reservation>> {
+        let mut reservations = self.reservations.lock().unwrap();
+        reservations.retain(|_, reservation| !reservation.expired(self.timeout));
+        reservations
+    }
+
+    /// Reserves a stream, returns its token.
+    pub fn reserve(&self) -> String {
+        let token = Uuid::new_v4().to_string();
+        let (sender, receiver) = channel(STREAM_CAPACITY);
+        self.reservations().insert(
+            token.clone(),
+            Reservation {
+                sender,
+                receiver: Some(receiver),
+                reserved_at: Instant::now(),
+                operations: HashMap::new(),
+            },
+        );
+        token
+    }
+
+    /// Returns the events of the operations executed on the stream `token`,
+    /// which can only be listened to once.
+    pub fn listen(
+        &self,
+        token: &str,
+    ) -> Result<impl Stream<Item = Event> + Send + 'static, StatusCode> {
+        let mut reservations = self.reservations();
+        let reservation = reservations.get_mut(token).ok_or(StatusCode::NOT_FOUND)?;
+        let receiver = reservation.receiver.take().ok_or(StatusCode::CONFLICT)?;
+        let listener = Listener {
+            streams: self.clone(),
+            token: token.to_string(),
+        };
+        Ok(receiver.map(move |event| {
+            let _ = &listener;
+            event
+        }))
+    }
+
+    /// Executes the request on the stream `token`, its id is the
+    /// `operationId` extension.
+    pub fn execute(
+        &self,
+        token: &str,
+        schema: &impl Executor,
+        request: Request,
+    ) -> Result<(), StatusCode> {
+        let id = match request.extensions.get("operationId") {
+            Some(async_graphql::Value::String(id)) => id.clone(),
+            _ => return Err(StatusCode::BAD_REQUEST),
+        };
+        let mut reservations = self.reservations();
+        let reservation = reservations.get_mut(token).ok_or(StatusCode::NOT_FOUND)?;
+        if reservation.receiver.is_some() || reservation.operations.contains_key(&id) {
+            return Err(StatusCode::CONFLICT);
+        }
+
+        let task = {
+            let streams = self.clone();
+            let token = token.to_string();
+            let id = id.clone();
+            let mut sender = reservation.sender.clone();
+            let mut responses = schema.execute_stream(request, None);
+            async move {
+                while let Some(response) = responses.next().await {
+                    if sender.send(next_event(Some(&id), &response)).await.is_err() {
+                        return;
+                    }
+                }
+                let _ = sender.send(complete_event(Some(&id))).await;
+                if let Some(reservation) = streams.reservations.lock().unwrap().get_mut(&token) {
+                    reservation.operations.remove(&id);
+                }
+            }
+        };
+        // The lock is held until the handle is stored, so the task can't
+        // remove it before.
+        let handle = tokio::spawn(task);
+        reservation.operations.insert(id, handle.abort_handle());
+        Ok(())
+    }
+
+    /// Stops the operation `id` of the stream `token`.
+    pub fn cancel(&self, token: &str, id: &str) -> StatusCode {
+        match self.reservations().get_mut(token) {
+            Some(reservation) => {
+                if let Some(handle) = reservation.operations.remove(id) {
+                    handle.abort();
+                }
+                StatusCode::OK
+            }
+            None => StatusCode::NOT_FOUND,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_graphql::{Schema, Value};
+    use books::{BooksSchema, MutationRoot, QueryRoot, Storage, SubscriptionRoot};
+    use futures_util::FutureExt;
+
+    use super::*;
+
+    fn schema() -> BooksSchema {
+        Schema::build(QueryRoot, MutationRoot, SubscriptionRoot)
+            .data(Storage::memory())
+            .finish()
+    }
+
+    fn operation(id: &str, query: &str) -> Request {
+        let mut request = Request::new(query);
+        request
+            .extensions
+            .insert("operationId".to_string(), Value::from(id));
+        request
+    }
+
+    #[tokio::test]
+    async fn distinct_connections() {
+        let events = distinct(&schema(), Request::new("{ books { id } }"))
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(
+            events,
+            [
+                Event {
+                    event: "next",
+                    data: r#"{"data":{"books":[]}}"#.to_string(),
+                },
+                Event {
+                    event: "complete",
+                    data: String::new(),
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn single_connection() {
+        let schema = schema();
+        let streams = Streams::default();
+        let token = streams.reserve();
+
+        // Operations are refused until the stream is listened to.
+        assert_eq!(
+            streams.execute(&token, &schema, operation("1", "{ books { id } }")),
+            Err(StatusCode::CONFLICT)
+        );
+        let mut events = streams.listen(&token).unwrap();
+        assert_eq!(
+            streams.execute(&token, &schema, Request::new("{ books { id } }")),
+            Err(StatusCode::BAD_REQUEST)
+        );
+        streams
+            .execute(&token, &schema, operation("1", "{ books { id } }"))
+            .unwrap();
+
+        assert_eq!(
+            events.next().await.unwrap(),
+            Event {
+                event: "next",
+                data: r#"{"id":"1","payload":{"data":{"books":[]}}}"#.to_string(),
+            }
+        );
+        assert_eq!(
+            events.next().await.unwrap(),
+            Event {
+                event: "complete",
+                data: r#"{"id":"1"}"#.to_string(),
+            }
+        );
+        assert_eq!(streams.cancel(&token, "1"), StatusCode::OK);
+
+        // The reservation is removed once the client stops listening.
+        drop(events);
+        assert_eq!(streams.cancel(&token, "1"), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn cancel_operation() {
+        let schema = schema();
+        let streams = Streams::default();
+        let token = streams.reserve();
+        let mut events = streams.listen(&token).unwrap();
+        streams
+            .execute(
+                &token,
+                &schema,
+                operation("1", "subscription { books { id } }"),
+            )
+            .unwrap();
+        assert_eq!(
+            streams.execute(&token, &schema, operation("1", "{ books { id } }")),
+            Err(StatusCode::CONFLICT)
+        );
+        assert_eq!(streams.cancel(&token, "1"), StatusCode::OK);
+        tokio::task::yield_now().await;
+        assert!(events.next().now_or_never().is_none());
+    }
+
+    #[tokio::test]
+    async fn unknown_token() {
+        let schema = schema();
+        let streams = Streams::default();
+        assert!(matches!(
+            streams.listen("unknown"),
+            Err(StatusCode::NOT_FOUND)
+        ));
+        assert_eq!(
+            streams.execute("unknown", &schema, operation("1", "{ books { id } }")),
+            Err(StatusCode::NOT_FOUND)
+        );
+        assert_eq!(streams.cancel("unknown", "1"), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn expired_token() {
+        let streams = Streams::new(Duration::ZERO);
+        let token = streams.reserve();
+        assert!(matches!(streams.listen(&token), Err(StatusCode::NOT_FOUND)));
+        assert!(streams.reservations.lock().unwrap().is_empty());
+
+        // An expired reservation is dropped by any request, not only by the
+        // next reservation.
+        let token = streams.reserve();
+        assert_eq!(streams.cancel("unknown", "1"), StatusCode::NOT_FOUND);
+        assert!(streams.reservations.lock().unwrap().is_empty());
+        assert_eq!(streams.cancel(&token, "1"), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn listen_twice() {
+        let streams = Streams::default();
+        let token = streams.reserve();
+        let _events = streams.listen(&token).unwrap();
+        assert!(matches!(streams.listen(&token), Err(StatusCode::CONFLICT)));
+    }
+}
@@ -0,0 +1,159 @@
+//! Checks that the static and dynamic versions of the model crates are
+//! interchangeable.
+//!
+//! For every pair of crates the SDL of both schemas is compared, then the
+//! operations in `operations/<pair>` are executed against both schemas, in
+//! the order of their file names, and the data and error messages of the
+//! responses are compared. The pairs are checked by the tests of this crate:
+//!
+//! ```bash
+//! cargo test -p parity
+//! ```
+
+use std::{collections::BTreeMap, fmt, fs, io, path::Path};
+
+use async_graphql::{Executor, Request, Response};
+use serde_json::json;
+
+/// A difference between the static and dynamic schemas of a pair.
+#[derive(Debug)]
+pub struct Difference {
+    /// The kind and name of the definition, such as `type Query`, or the file
+    /// name of the operation.
+    pub name: String,
+    pub detail: String,
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} differs:\n  {}", self.name, self.detail.replace('\n', "\n  "))
+    }
+}
+
+/// The differences between the SDL of the static and dynamic schemas, and
+/// between their responses to the operations in `operations`.
+pub async fn compare(
+    operations: &Path,
+    (static_sdl, static_schema): (String, impl Executor),
+    (dynamic_sdl, dynamic_schema): (String, impl Executor),
+) -> io::Result<Vec<Difference>> {
+    let mut differences = Vec::new();
+    let mut differ = |name: &str, detail: String| {
+        differences.push(Difference {
+            name: name.to_string(),
+            detail,
+        })
+    };
+
+    let static_definitions = definitions(&static_sdl);
+    let dynamic_definitions = definitions(&dynamic_sdl);
+    for (name, expected) in &static_definitions {
+        match dynamic_definitions.get(name) {
+            Some(actual) if actual != expected => differ(name, diff(expected, actual)),
+            Some(_) => {}
+            None => differ(name, "only in the static schema".to_string()),
+        }
+    }
+    for name in dynamic_definitions
+        .keys()
+        .filter(|name| !static_definitions.contains_key(*name))
+    {
+        differ(name, "only in the dynamic schema".to_string());
+    }
+
+    let mut paths = fs::read_dir(operations)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    paths.retain(|path| path.extension().is_some_and(|ext| ext == "graphql"));
+    paths.sort();
+    for path in paths {
+        let query = fs::read_to_string(&path)?;
+        let expected = outcome(&static_schema.execute(Request::new(query.clone())).await);
+        let actual = outcome(&dynamic_schema.execute(Request::new(query)).await);
+        if actual != expected {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            differ(&name, format!("- {expected}\n+ {actual}"));
+        }
+    }
+    Ok(differences)
+}
+
+/// The data and error messages of `response`. The locations and paths of the
+/// errors are left out, the dynamic schemas don't set the path of the errors
+/// returned by resolvers.
+fn outcome(response: &Response) -> String {
+    let messages: Vec<_> = response.errors.iter().map(|err| &err.message).collect();
+    json!({ "data": response.data, "errors": messages }).to_string()
+}
+
+/// The definitions of `sdl` with their descriptions, by kind and name.
+///
+/// The root types are renamed to `Query`, `Mutation` and `Subscription`, as
+/// the static schemas name them after their Rust types.
+fn definitions(sdl: &str) -> BTreeMap<String, String> {
+    let roots: BTreeMap<&str, &str> = sdl
+        .lines()
+        .skip_while(|line| *line != "schema {")
+        .skip(1)
+        .take_while(|line| *line != "}")
+        .filter_map(|line| {
+            let (operation, name) = line.trim().split_once(": ")?;
+            let root = match operation {
+                "query" => "Query",
+                "mutation" => "Mutation",
+                "subscription" => "Subscription",
+                _ => return None,
+            };
+            Some((name, root))
+        })
+        .collect();
+
+    let mut definitions = BTreeMap::new();
+    let mut current: Option<(String, String)> = None;
+    let mut description = String::new();
+    let mut in_description = false;
+    for line in sdl.lines().filter(|line| !line.is_empty()) {
+        if in_description || line.starts_with("\"\"\"") {
+            if line == "\"\"\"" {
+                in_description = !in_description;
+            }
+            description.push_str(line);
+            description.push('\n');
+        } else if line.starts_with(['\t', ' ', '}', ')']) {
+            if let Some((_, text)) = &mut current {
+                text.push_str(line);
+                text.push('\n');
+            }
+        } else {
+            definitions.extend(current.take());
+            let (kind, rest) = line.split_once(' ').unwrap_or((line, ""));
+            let name = rest.split([' ', '(', '{']).next().unwrap_or_default();
+            let (key, line) = match roots.get(name) {
+                Some(root) => (format!("{kind} {root}"), line.replacen(name, root, 1)),
+                None => (format!("{kind} {name}"), line.to_string()),
+            };
+            current = Some((key, format!("{}{line}\n", std::mem::take(&mut description))));
+        }
+    }
+    definitions.extend(current);
+    // The `schema` definition only lists the root types, which are compared
+    // as types.
+    definitions.retain(|key, _| !key.starts_with("schema"));
+    definitions
+}
+
+/// The lines of `expected` that are missing from `actual`, prefixed with `-`,
+/// followed by the lines of `actual` that are missing from `expected`,
+/// prefixed with `+`.
+fn diff(expected: &str, actual: &str) -> String {
+    let missing = |from: &'static str, lines: &str, other: &str| {
+        lines
+            .lines()
+            .filter(|line| !other.lines().any(|other| other == *line))
+            .map(|line| format!("{from} {}", line.trim()))
+            .collect::<Vec<_>>()
+    };
+    let mut lines = missing("-", expected, actual);
+    lines.extend(missing("+", actual, expected));
+    lines.join("\n")
+}
@@ -0,0 +1,102 @@
+use std::path::{Path, PathBuf};
+
+use async_graphql::Schema;
+use parity::{Difference, compare};
+
+/// The operations of `pair`.
+fn operations(pair: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("operations")
+        .join(pair)
+}
+
+/// Fails with the differences that aren't `known`, and with the known ones
+/// that are gone so that they are removed from the list.
+fn check(differences: Vec<Difference>, known: &[&str]) {
+    let unexpected: Vec<_> = differences
+        .iter()
+        .filter(|difference| !known.contains(&difference.name.as_str()))
+        .map(ToString::to_string)
+        .collect();
+    assert!(unexpected.is_empty(), "{}", unexpected.join("\n"));
+    for name in known {
+        assert!(
+            differences.iter().any(|difference| difference.name == *name),
+            "`{name}` no longer differs"
+        );
+    }
+}
+
+#[tokio::test]
+async fn starwars() {
+    let schema = Schema::build(
+        starwars::QueryRoot,
+        starwars::MutationRoot,
+        starwars::SubscriptionRoot,
+    )
+    .data(starwars::StarWars::new())
+    .finish();
+    let dynamic_schema = dynamic_starwars::schema().unwrap();
+    let differences = compare(
+        &operations("starwars"),
+        (schema.sdl(), schema),
+        (dynamic_schema.sdl(), dynamic_schema),
+    )
+    .await
+    .unwrap();
+    check(differences, &[]);
+}
+
+/// dynamic-books keeps the API of the example it comes from: `getBooks` and
+/// `getBook` in place of `books`, and only the `bookMutation` subscription,
+/// whose events have no `sequence` to resume from.
+const KNOWN_BOOKS_DIFFERENCES: &[&str] = &[
+    "type Query",
+    "type Subscription",
+    "type BookChanged",
+    "2-books.graphql",
+];
+
+#[tokio::test]
+async fn books() {
+    let schema = Schema::build(
+        books::QueryRoot,
+        books::MutationRoot,
+        books::SubscriptionRoot,
+    )
+    .data(books::Storage::default())
+    .finish();
+    let dynamic_schema = dynamic_books::schema().unwrap();
+    let differences = compare(
+        &operations("books"),
+        (schema.sdl(), schema),
+        (dynamic_schema.sdl(), dynamic_schema),
+    )
+    .await
+    .unwrap();
+    check(differences, KNOWN_BOOKS_DIFFERENCES);
+}
+
+#[tokio::test]
+async fn files() {
+    let schema = Schema::build(
+        files::QueryRoot,
+        files::MutationRoot,
+        files::SubscriptionRoot,
+    )
+    .data(files::Storage::default())
+    .finish();
+    let dynamic_schema = dynamic_files::schema(
+        dynamic_files::Storage::default(),
+        dynamic_files::UploadPolicy::default(),
+    )
+    .unwrap();
+    let differences = compare(
+        &operations("files"),
+        (schema.sdl(), schema),
+        (dynamic_schema.sdl(), dynamic_schema),
+    )
+    .await
+    .unwrap();
+    check(differences, &[]);
+}
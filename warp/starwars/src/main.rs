@@ -1,24 +1,21 @@
 use std::convert::Infallible;
 
-use async_graphql::{EmptyMutation, EmptySubscription, Schema, http::GraphiQLSource};
-use async_graphql_warp::{GraphQLBadRequest, GraphQLResponse};
+use async_graphql::{Schema, http::GraphiQLSource};
+use async_graphql_warp::{GraphQLBadRequest, GraphQLResponse, graphql_subscription};
 use http::StatusCode;
-use starwars::{QueryRoot, StarWars};
+use starwars::{MutationRoot, QueryRoot, StarWars, StarWarsSchema, SubscriptionRoot};
 use warp::{Filter, Rejection, http::Response as HttpResponse};
 
 #[tokio::main]
 async fn main() {
-    let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+    let schema = Schema::build(QueryRoot, MutationRoot, SubscriptionRoot)
         .data(StarWars::new())
         .finish();
 
     println!("GraphiQL IDE: http://localhost:8000");
 
-    let graphql_post = async_graphql_warp::graphql(schema).and_then(
-        |(schema, request): (
-            Schema<QueryRoot, EmptyMutation, EmptySubscription>,
-            async_graphql::Request,
-        )| async move {
+    let graphql_post = async_graphql_warp::graphql(schema.clone()).and_then(
+        |(schema, request): (StarWarsSchema, async_graphql::Request)| async move {
             Ok::<_, Infallible>(GraphQLResponse::from(schema.execute(request).await))
         },
     );
@@ -26,10 +23,16 @@ async fn main() {
     let graphiql = warp::path::end().and(warp::get()).map(|| {
         HttpResponse::builder()
             .header("content-type", "text/html")
-            .body(GraphiQLSource::build().endpoint("/").finish())
+            .body(
+                GraphiQLSource::build()
+                    .endpoint("/")
+                    .subscription_endpoint("/")
+                    .finish(),
+            )
     });
 
-    let routes = graphiql
+    let routes = graphql_subscription(schema)
+        .or(graphiql)
         .or(graphql_post)
         .recover(|err: Rejection| async move {
             if let Some(GraphQLBadRequest(err)) = err.find() {
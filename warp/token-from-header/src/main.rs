@@ -1,13 +1,23 @@
 use std::convert::Infallible;
 
-use async_graphql::{Data, EmptyMutation, Schema, http::GraphiQLSource};
+use async_graphql::{EmptyMutation, Schema, http::GraphiQLSource};
 use async_graphql_warp::{GraphQLResponse, GraphQLWebSocket, graphql_protocol};
-use token::{QueryRoot, SubscriptionRoot, Token, on_connection_init};
+use token::{JwtConfig, QueryRoot, SharedValidator, SubscriptionRoot, TokenSchema};
 use warp::{Filter, http::Response as HttpResponse, ws::Ws};
 
 #[tokio::main]
 async fn main() {
     let schema = Schema::build(QueryRoot, EmptyMutation, SubscriptionRoot).finish();
+    let validator = match SharedValidator::jwt(&JwtConfig::from_env()) {
+        Ok(validator) => validator,
+        Err(err) => {
+            eprintln!(
+                "Invalid JWT configuration: {err}. Set JWT_SECRET, JWT_PUBLIC_KEY_FILE or \
+                 JWT_JWKS_FILE, for example `JWT_SECRET=secret`."
+            );
+            std::process::exit(1);
+        }
+    };
 
     println!("GraphiQL IDE: http://localhost:8000");
 
@@ -22,51 +32,41 @@ async fn main() {
             )
     });
 
-    let graphql_post = warp::header::optional::<String>("token")
+    let graphql_post = warp::header::optional::<String>("authorization")
         .and(async_graphql_warp::graphql(schema.clone()))
-        .and_then(
-            |token,
-             (schema, mut request): (
-                Schema<QueryRoot, EmptyMutation, SubscriptionRoot>,
-                async_graphql::Request,
-            )| async move {
-                if let Some(token) = token {
-                    request = request.data(Token(token));
+        .and_then({
+            let validator = validator.clone();
+            move |authorization: Option<String>,
+                  (schema, request): (TokenSchema, async_graphql::Request)| {
+                let validator = validator.clone();
+                async move {
+                    let resp = match validator.authenticate(request, authorization.as_deref()) {
+                        Ok(request) => schema.execute(request).await,
+                        Err(err) => async_graphql::Response::from_errors(vec![err]),
+                    };
+                    Ok::<_, Infallible>(GraphQLResponse::from(resp))
                 }
-                let resp = schema.execute(request).await;
-                Ok::<_, Infallible>(GraphQLResponse::from(resp))
-            },
-        );
+            }
+        });
 
     let subscription = warp::path!("ws")
         .and(warp::ws())
-        .and(warp::header::optional::<String>("token"))
         .and(warp::any().map(move || schema.clone()))
         .and(graphql_protocol())
-        .map(
-            move |ws: Ws,
-                  token,
-                  schema: Schema<QueryRoot, EmptyMutation, SubscriptionRoot>,
-                  protocol| {
-                let reply = ws.on_upgrade(move |socket| {
-                    let mut data = Data::default();
-                    if let Some(token) = token {
-                        data.insert(Token(token));
-                    }
+        .map(move |ws: Ws, schema: TokenSchema, protocol| {
+            let validator = validator.clone();
+            let reply = ws.on_upgrade(move |socket| {
+                GraphQLWebSocket::new(socket, schema, protocol)
+                    .on_connection_init(move |value| validator.on_connection_init(value))
+                    .serve()
+            });
 
-                    GraphQLWebSocket::new(socket, schema, protocol)
-                        .with_data(data)
-                        .on_connection_init(on_connection_init)
-                        .serve()
-                });
-
-                warp::reply::with_header(
-                    reply,
-                    "Sec-WebSocket-Protocol",
-                    protocol.sec_websocket_protocol(),
-                )
-            },
-        );
+            warp::reply::with_header(
+                reply,
+                "Sec-WebSocket-Protocol",
+                protocol.sec_websocket_protocol(),
+            )
+        });
 
     let routes = subscription.or(graphiql).or(graphql_post);
     warp::serve(routes).run(([127, 0, 0, 1], 8000)).await;